@@ -21,6 +21,25 @@ pub fn load<P: AsRef<Path>>(path: P) -> Vec<u8> {
     data
 }
 
+/// Sample-accurate loop region, in frames.
+///
+/// Playback wraps from `end` (exclusive) back to `start`.
+#[derive(Debug, Clone, Copy)]
+struct LoopRegion {
+    start: usize,
+    end: usize,
+}
+
+impl LoopRegion {
+    fn next_frame(&self, frame: usize) -> usize {
+        if frame + 1 >= self.end {
+            self.start
+        } else {
+            frame + 1
+        }
+    }
+}
+
 pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     #[cfg(not(target_os = "android"))]
     let mut audio_stream = {
@@ -45,24 +64,38 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
         .map(Result::unwrap)
         .collect::<Vec<_>>();
 
+    // Optional loop region `<start> <end>` in frames, defaults to the whole track.
+    #[cfg(not(target_os = "android"))]
+    let loop_region = {
+        let mut args = std::env::args().skip(2).map(|arg| arg.parse::<usize>());
+        match (args.next(), args.next()) {
+            (Some(start), Some(end)) => LoopRegion {
+                start: start?,
+                end: end?.min(samples.len()),
+            },
+            _ => LoopRegion {
+                start: 0,
+                end: samples.len(),
+            },
+        }
+    };
+
+    #[cfg(target_os = "android")]
+    let loop_region = LoopRegion {
+        start: 0,
+        end: samples.len(),
+    };
+
+    if loop_region.start >= loop_region.end {
+        println!("Error: Invalid loop region {:?}.", loop_region);
+        return Ok(());
+    }
+
     unsafe {
         let instance_properties = Instance::properties();
         let instance = Instance::create("audir-music");
 
-        let output_device = match instance.default_physical_output_device() {
-            Some(device) => device,
-            None => instance
-                .enumerate_physical_devices()
-                .into_iter()
-                .find(|device| {
-                    let properties = instance.physical_device_properties(*device);
-                    match properties {
-                        Ok(properties) => properties.streams.contains(audir::StreamFlags::OUTPUT),
-                        Err(_) => false,
-                    }
-                })
-                .unwrap(),
-        };
+        let output_device = instance.default_output_or_first().unwrap();
 
         let sample_rate = 48_000;
         let format = audir::Format::F32;
@@ -75,10 +108,11 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
                 sample_rate,
                 format,
                 channels: output_channels,
+                endianness: audir::Endianness::NATIVE,
             },
         ));
 
-        let mut sample = 0;
+        let mut sample = loop_region.start;
         let mut device = instance.create_device(
             audir::DeviceDesc {
                 physical_device: output_device,
@@ -87,25 +121,25 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
                     format,
                     sample_rate,
                 },
+                no_persist: false,
+                acquire_timeout: Some(std::time::Duration::from_secs(1)),
+                src_quality: audir::SrcQuality::None,
+                auto_recover: false,
+                session_guid: None,
+                alertable_wait: false,
+                target_latency: None,
+                channel_map: None,
+                auto_downmix: false,
+                input_device: None,
             },
             audir::Channels {
                 input: audir::ChannelMask::empty(),
                 output: output_channels,
             },
-            Box::new(move |stream| {
-                let properties = stream.properties;
-                let num_channels = properties.num_channels();
-
-                let buffer = std::slice::from_raw_parts_mut(
-                    stream.buffers.output as *mut f32,
-                    stream.buffers.frames as usize * num_channels,
-                );
-
-                for dt in 0..stream.buffers.frames as usize {
-                    let frame = samples[sample];
-                    buffer[num_channels * dt as usize] = frame[0];
-                    buffer[num_channels * dt as usize + 1] = frame[1];
-                    sample = (sample + 1) % samples.len();
+            Box::new(move |mut stream| {
+                for frame in stream.output_frames::<f32, 2>() {
+                    *frame = samples[sample];
+                    sample = loop_region.next_frame(sample);
                 }
             }),
         )?;
@@ -114,7 +148,7 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
 
         loop {
             if instance_properties.stream_mode == audir::StreamMode::Polling {
-                device.submit_buffers(!0)?;
+                device.submit_buffers(None)?;
             }
         }
     }
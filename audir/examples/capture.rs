@@ -11,21 +11,9 @@ fn main() -> anyhow::Result<()> {
     unsafe {
         let instance_properties = Instance::properties();
         let instance = Instance::create("audir - capture");
-        let physical_devices = instance.enumerate_physical_devices();
-
-        let input_device = match instance.default_physical_input_device() {
-            Some(device) => device,
-            None => physical_devices
-                .into_iter()
-                .find(|device| {
-                    let properties = instance.physical_device_properties(*device);
-                    match properties {
-                        Ok(properties) => properties.streams.contains(audir::StreamFlags::INPUT),
-                        Err(_) => false,
-                    }
-                })
-                .expect("no input device found"),
-        };
+        let input_device = instance
+            .default_input_or_first()
+            .expect("no input device found");
 
         println!(
             "{:X}: {:#?}",
@@ -33,12 +21,19 @@ fn main() -> anyhow::Result<()> {
             instance.physical_device_properties(input_device)?
         );
 
-        let sample_rate = 48_000;
+        // Use the rate the device is running at, hardcoding a rate (e.g 48kHz)
+        // breaks on devices running at a different rate.
+        let sample_rate = instance
+            .physical_device_default_concurrent_format(input_device)?
+            .sample_rate;
+
+        let format = audir::Format::F32;
+        let channels = audir::ChannelMask::FRONT_LEFT | audir::ChannelMask::FRONT_RIGHT;
 
         let spec = hound::WavSpec {
-            channels: 2,
+            channels: channels.bits().count_ones() as _,
             sample_rate: sample_rate as u32,
-            bits_per_sample: 32,
+            bits_per_sample: format.bits_per_sample() as _,
             sample_format: hound::SampleFormat::Float,
         };
         let writer = Arc::new(Mutex::new(
@@ -52,22 +47,26 @@ fn main() -> anyhow::Result<()> {
                     physical_device: input_device,
                     sharing: audir::SharingMode::Concurrent,
                     sample_desc: audir::SampleDesc {
-                        format: audir::Format::F32,
+                        format,
                         sample_rate,
                     },
+                    no_persist: false,
+                    acquire_timeout: Some(std::time::Duration::from_secs(1)),
+                    src_quality: audir::SrcQuality::None,
+                    auto_recover: true,
+                    session_guid: None,
+                    alertable_wait: false,
+                    target_latency: None,
+                    channel_map: None,
+                    auto_downmix: false,
+                    input_device: None,
                 },
                 audir::Channels {
-                    input: audir::ChannelMask::FRONT_LEFT | audir::ChannelMask::FRONT_RIGHT,
+                    input: channels,
                     output: audir::ChannelMask::empty(),
                 },
                 Box::new(move |stream| {
-                    let num_channels = stream.properties.num_channels();
-
-                    let audir::StreamBuffers { input, frames, .. } = stream.buffers;
-                    let buffer = std::slice::from_raw_parts(
-                        input as *const f32,
-                        frames as usize * num_channels,
-                    );
+                    let buffer = stream.input_buffer::<f32>();
 
                     let mut writer = wav.lock().unwrap();
                     for sample in buffer {
@@ -84,7 +83,7 @@ fn main() -> anyhow::Result<()> {
                     let _session = instance.create_session(sample_rate)?;
                     device.start();
                     while start.elapsed() < duration {
-                        device.submit_buffers(!0)?;
+                        device.submit_buffers(None)?;
                     }
                 }
                 audir::StreamMode::Callback => {
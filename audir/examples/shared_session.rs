@@ -0,0 +1,105 @@
+//! Two processes sharing one audio session.
+//!
+//! The parent process spawns a child process of this example, both play a tone
+//! with the same session id. Both streams show up as a single entry in the volume mixer.
+//! Sessions are only supported by WASAPI, the example is Windows only.
+
+#[cfg(windows)]
+use audir::wasapi::Instance;
+
+#[cfg(windows)]
+use audir::{Device, Instance as InstanceTrait};
+
+/// Session id shared by all processes of the application.
+#[cfg(windows)]
+const SESSION_GUID: u128 = 0x6f1d_2c3a_8b4e_4f5a_9c7d_1e2f_3a4b_5c6d;
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("shared sessions are only supported by WASAPI");
+}
+
+#[cfg(windows)]
+fn main() -> anyhow::Result<()> {
+    let is_child = std::env::args().any(|arg| arg == "--child");
+    let child = if is_child {
+        None
+    } else {
+        let exe = std::env::current_exe()?;
+        Some(std::process::Command::new(exe).arg("--child").spawn()?)
+    };
+
+    let frequency = if is_child { 660.0 } else { 440.0 };
+    play(frequency, std::time::Duration::from_secs(4))?;
+
+    if let Some(mut child) = child {
+        child.wait()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn play(frequency: f32, duration: std::time::Duration) -> anyhow::Result<()> {
+    unsafe {
+        let instance_properties = Instance::properties();
+        let instance = Instance::create("audir - shared session");
+        let output_device = instance.default_output_or_first().unwrap();
+        let format = instance.physical_device_default_concurrent_format(output_device)?;
+        let sample_rate = format.sample_rate;
+
+        let mut phase = 0.0f32;
+        let mut device = instance.create_device(
+            audir::DeviceDesc {
+                physical_device: output_device,
+                sharing: audir::SharingMode::Concurrent,
+                sample_desc: format.sample_desc(),
+                no_persist: false,
+                acquire_timeout: Some(std::time::Duration::from_secs(1)),
+                src_quality: audir::SrcQuality::None,
+                auto_recover: false,
+                session_guid: Some(SESSION_GUID),
+                alertable_wait: false,
+                target_latency: None,
+                channel_map: None,
+                auto_downmix: false,
+                input_device: None,
+            },
+            audir::Channels {
+                input: audir::ChannelMask::empty(),
+                output: format.channels,
+            },
+            Box::new(move |mut stream| {
+                let sample_rate = stream.properties.sample_rate as f32;
+                let num_channels = stream.properties.num_channels();
+
+                let buffer = stream.output_buffer::<f32>();
+                for frame in buffer.chunks_exact_mut(num_channels) {
+                    let sample = (phase * 2.0 * std::f32::consts::PI).sin() * 0.25;
+                    phase = (phase + frequency / sample_rate).fract();
+                    for channel in frame {
+                        *channel = sample;
+                    }
+                }
+            }),
+        )?;
+
+        let start = std::time::Instant::now();
+        match instance_properties.stream_mode {
+            audir::StreamMode::Polling => {
+                let _session = instance.create_session(sample_rate)?;
+                device.start();
+                while start.elapsed() < duration {
+                    device.submit_buffers(None)?;
+                }
+            }
+            audir::StreamMode::Callback => {
+                device.start();
+                while start.elapsed() < duration {}
+            }
+        }
+        device.stop();
+
+        Ok(())
+    }
+}
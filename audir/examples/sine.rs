@@ -25,24 +25,12 @@ fn main() -> anyhow::Result<()> {
             );
         }
 
-        let output_device = match instance.default_physical_output_device() {
-            Some(device) => device,
-            None => physical_devices
-                .into_iter()
-                .find(|device| {
-                    let properties = instance.physical_device_properties(*device);
-                    match properties {
-                        Ok(properties) => properties.streams.contains(audir::StreamFlags::OUTPUT),
-                        Err(_) => false,
-                    }
-                })
-                .unwrap(),
-        };
+        let output_device = instance.default_output_or_first().unwrap();
 
         let format = instance.physical_device_default_concurrent_format(output_device)?;
 
         println!(
-            "{:X}: {:#?} @ {:#?}",
+            "{:X}: {:#?} @ {}",
             output_device,
             instance.physical_device_properties(output_device)?,
             format,
@@ -57,12 +45,22 @@ fn main() -> anyhow::Result<()> {
                 physical_device: output_device,
                 sharing: audir::SharingMode::Concurrent,
                 sample_desc: format.sample_desc(),
+                no_persist: false,
+                acquire_timeout: Some(std::time::Duration::from_secs(1)),
+                src_quality: audir::SrcQuality::None,
+                auto_recover: false,
+                session_guid: None,
+                alertable_wait: false,
+                target_latency: None,
+                channel_map: None,
+                auto_downmix: false,
+                input_device: None,
             },
             audir::Channels {
                 input: audir::ChannelMask::empty(),
                 output: format.channels,
             },
-            Box::new(move |stream| {
+            Box::new(move |mut stream| {
                 let sample_rate = stream.properties.sample_rate as f32;
                 let num_channels = stream.properties.num_channels();
 
@@ -74,16 +72,11 @@ fn main() -> anyhow::Result<()> {
                 });
                 let source = source.as_mut().unwrap();
 
-                let audir::StreamBuffers { output, frames, .. } = stream.buffers;
-                let buffer = std::slice::from_raw_parts_mut(
-                    output as *mut f32,
-                    frames as usize * num_channels,
-                );
-
-                for dt in 0..frames {
+                let buffer = stream.output_buffer::<f32>();
+                for frame in buffer.chunks_exact_mut(num_channels) {
                     let sample = source.next() as f32 * 0.5;
-                    for i in 0..num_channels {
-                        buffer[num_channels * dt as usize + i] = sample;
+                    for channel in frame {
+                        *channel = sample;
                     }
                 }
             }),
@@ -94,7 +87,7 @@ fn main() -> anyhow::Result<()> {
                 let _session = instance.create_session(sample_rate)?;
                 device.start();
                 loop {
-                    device.submit_buffers(!0)?;
+                    device.submit_buffers(None)?;
                 }
             }
             audir::StreamMode::Callback => {
@@ -0,0 +1,61 @@
+//! Object based spatial audio.
+//!
+//! Plays a tone on a dynamic audio object circling around the listener.
+//! Spatial audio is only supported by WASAPI and requires a spatial sound format
+//! (e.g Windows Sonic) being enabled for the output device, the example is Windows only.
+
+#[cfg(windows)]
+use audir::wasapi::Instance;
+
+#[cfg(windows)]
+use audir::Instance as InstanceTrait;
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("spatial audio is only supported by WASAPI");
+}
+
+#[cfg(windows)]
+fn main() -> anyhow::Result<()> {
+    unsafe {
+        if !Instance::properties().supports_spatial_audio {
+            anyhow::bail!("spatial audio isn't supported by the backend");
+        }
+
+        let instance = Instance::create("audir - spatial");
+        let output_device = instance.default_output_or_first().unwrap();
+        if !instance.spatial_audio_available(output_device) {
+            anyhow::bail!("spatial audio isn't available, enable a spatial sound format");
+        }
+
+        let frequency = 440.0;
+        let radius = 2.0;
+        let mut phase = 0.0f32;
+        let mut angle = 0.0f32;
+        let mut device = instance.create_spatial_device(
+            output_device,
+            1,
+            Box::new(move |stream| {
+                let sample_rate = stream.sample_rate as f32;
+                let object = &mut stream.objects[0];
+                for sample in object.buffer() {
+                    *sample = (phase * 2.0 * std::f32::consts::PI).sin() * 0.25;
+                    phase = (phase + frequency / sample_rate).fract();
+                }
+
+                // One revolution every 4 seconds.
+                angle += stream.frames as f32 / sample_rate * std::f32::consts::PI / 2.0;
+                object.position = [radius * angle.sin(), 0.0, -radius * angle.cos()];
+            }),
+        )?;
+
+        device.start()?;
+        let start = std::time::Instant::now();
+        while start.elapsed() < std::time::Duration::from_secs(8) {
+            device.submit_buffers(None)?;
+        }
+        device.stop()?;
+
+        Ok(())
+    }
+}
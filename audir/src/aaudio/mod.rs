@@ -1,4 +1,4 @@
-use crate::{api, api::Result};
+use crate::{alloc_guard, api, api::Result};
 use ndk::aaudio;
 use std::collections::HashMap;
 use std::ptr;
@@ -58,6 +58,8 @@ impl api::Instance for Instance {
             driver_id: api::DriverId::AAudio,
             stream_mode: api::StreamMode::Callback,
             sharing: api::SharingModeFlags::CONCURRENT | api::SharingModeFlags::EXCLUSIVE,
+            supports_duplex: false,
+            supports_spatial_audio: false,
         }
     }
 
@@ -256,6 +258,9 @@ impl api::Instance for Instance {
             device_name: device.device_name.clone(),
             streams: device.streams,
             form_factor: api::FormFactor::Unknown, // todo
+            offload_capable: false,
+            adapter_id: String::new(),
+            roles: api::DeviceRoleFlags::empty(),
         })
     }
 
@@ -308,21 +313,39 @@ impl api::Instance for Instance {
             .unwrap()
             .device_id(desc.physical_device as _)
             .data_callback(Box::new(move |astream, data, frames| {
-                callback(api::Stream {
+                let stream = api::Stream {
                     properties: get_stream_properties(&astream),
                     buffers: api::StreamBuffers {
                         frames: frames as _,
                         input: ptr::null(),
                         output: data as *mut _,
+                        gap_frames: 0,
+                        capture_time: None,
+                        silent: false,
                     },
-                });
+                };
+                alloc_guard::guarded(|| callback(stream));
                 aaudio::AAudioCallbackResult::Continue
             }));
         let stream = builder.open_stream().unwrap();
+        if let Some(latency) = desc.target_latency {
+            // Best-effort, the stream clamps the size to its buffer capacity.
+            let frames = latency.as_secs_f64() * stream.get_sample_rate() as f64;
+            let _ = stream.set_buffer_size_in_frames(frames as _);
+        }
         Ok(Device { stream })
     }
 
-    unsafe fn create_session(&self, _: usize) -> Result<()> {
+    unsafe fn create_session_with_affinity(
+        &self,
+        _sample_rate: usize,
+        thread_affinity: Option<api::CoreMask>,
+    ) -> Result<Self::Session> {
+        if thread_affinity.is_some() {
+            return api::Error::unsupported(
+                "Thread affinity isn't supported by the AAudio backend",
+            );
+        }
         Ok(())
     }
 
@@ -343,8 +366,16 @@ unsafe fn get_stream_properties(stream: &aaudio::AAudioStream) -> api::StreamPro
     };
     api::StreamProperties {
         channels,
+        requested_channels: channels,
+        device_channels: channels,
         sample_rate: stream.get_sample_rate() as _,
-        buffer_size: stream.get_buffer_size_in_frames() as _,
+        buffer_size_frames: stream.get_buffer_size_in_frames() as _,
+        valid_bits: match stream.get_format() {
+            aaudio::AAudioFormat::PCM_I16 => 16,
+            _ => 32,
+        },
+        timer_driven: false,
+        conversions: api::ConversionFlags::empty(),
     }
 }
 
@@ -0,0 +1,83 @@
+//! Detection of heap allocations inside stream callbacks.
+//!
+//! Allocating on the audio thread is a common cause of glitches. With the `alloc-guard`
+//! feature enabled and `AllocGuard` registered as global allocator, debug builds report
+//! stream callbacks which allocate:
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static ALLOCATOR: audir::AllocGuard = audir::AllocGuard(std::alloc::System);
+//! ```
+//!
+//! Release builds and builds without the feature don't track allocations.
+
+#[cfg(feature = "alloc-guard")]
+use std::alloc::{GlobalAlloc, Layout, System};
+#[cfg(all(feature = "alloc-guard", debug_assertions))]
+use std::cell::Cell;
+
+#[cfg(all(feature = "alloc-guard", debug_assertions))]
+thread_local! {
+    /// Number of allocations inside the running stream callback, `None` outside of callbacks.
+    static CALLBACK_ALLOCATIONS: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Global allocator wrapper counting allocations of stream callbacks.
+#[cfg(feature = "alloc-guard")]
+pub struct AllocGuard<A = System>(pub A);
+
+#[cfg(feature = "alloc-guard")]
+impl<A> AllocGuard<A> {
+    #[inline]
+    fn track(&self) {
+        #[cfg(debug_assertions)]
+        let _ = CALLBACK_ALLOCATIONS.try_with(|count| {
+            if let Some(n) = count.get() {
+                count.set(Some(n + 1));
+            }
+        });
+    }
+}
+
+#[cfg(feature = "alloc-guard")]
+unsafe impl<A: GlobalAlloc> GlobalAlloc for AllocGuard<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.track();
+        self.0.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.track();
+        self.0.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.track();
+        self.0.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.dealloc(ptr, layout)
+    }
+}
+
+/// Invoke a stream callback, reporting allocations done by the callback.
+#[cfg(all(feature = "alloc-guard", debug_assertions))]
+#[allow(dead_code)] // Unused if only the null backend is enabled.
+pub(crate) fn guarded<R>(callback: impl FnOnce() -> R) -> R {
+    CALLBACK_ALLOCATIONS.with(|count| count.set(Some(0)));
+    let result = callback();
+    let allocations = CALLBACK_ALLOCATIONS.with(|count| count.replace(None));
+    if let Some(n) = allocations.filter(|&n| n > 0) {
+        log::error!("stream callback allocated {} times", n);
+    }
+    result
+}
+
+/// Invoke a stream callback.
+#[cfg(not(all(feature = "alloc-guard", debug_assertions)))]
+#[inline]
+#[allow(dead_code)] // Unused if only the null backend is enabled.
+pub(crate) fn guarded<R>(callback: impl FnOnce() -> R) -> R {
+    callback()
+}
@@ -1,18 +1,41 @@
 use crate::handle;
 
-use std::{error, fmt, result};
+use std::{error, fmt, result, slice, time::Duration};
 
 /// Opaque physical device handle.
 pub type PhysicalDevice = handle::RawHandle;
 
 pub const DEFAULT_SAMPLE_RATE: usize = 0;
+
+/// Wait indefinitely for the stream buffers in `submit_buffers`.
+///
+/// Timeouts exceeding `u32::MAX` milliseconds are treated as infinite as well.
+pub const INFINITE_TIMEOUT: Duration = Duration::MAX;
+
+/// Infinite timeout of the platform wait functions in milliseconds.
+#[allow(dead_code)]
+pub(crate) const INFINITE_TIMEOUT_MS: u32 = !0;
+
+/// Timeout in milliseconds for the platform wait functions.
+#[allow(dead_code)]
+pub(crate) fn timeout_ms(timeout: Duration) -> u32 {
+    timeout.as_millis().min(INFINITE_TIMEOUT_MS as u128) as _
+}
+
 /// Driver Implementations
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DriverId {
     Wasapi,
+    Asio,
     PulseAudio,
     OpenSLES,
     AAudio,
+    /// Not implemented yet, never listed by `available_backends`.
+    Alsa,
+    /// Not implemented yet, never listed by `available_backends`.
+    Jack,
+    /// Not implemented yet, never listed by `available_backends`.
+    CoreAudio,
 
     Null,
 }
@@ -35,6 +58,7 @@ bitflags::bitflags! {
 /// Sharing mode specifies system-wide access to a physical device resource.
 /// Access is not isolated to the current process or instance.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SharingMode {
     /// Exclusive device access.
     Exclusive,
@@ -60,53 +84,335 @@ pub enum StreamMode {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FormFactor {
-    ///
+    /// Unknown form factor.
     Unknown,
     /// Remote Network
     Remote,
-    ///
+    /// Line level connector (e.g line in/out jack).
     LineLevel,
-    ///
+    /// Speakers.
+    Speakers,
+    /// Headphones.
     Headphones,
-    ///
+    /// Headset with microphone.
     Headset,
-    ///
+    /// Handset (e.g telephone handset).
+    Handset,
+    /// Microphone.
     Microphone,
+    /// Digital passthrough connector of unknown type.
+    DigitalPassthrough,
+    /// S/PDIF connector.
+    Spdif,
+    /// Digital display device (e.g HDMI or DisplayPort).
+    Hdmi,
 }
 
 bitflags::bitflags! {
+    /// Set of channels of a stream.
+    ///
+    /// Interleaved samples are ordered by ascending bit position (canonical order), which
+    /// matches the `SPEAKER_*` order of WASAPI. Unassigned channels follow the speaker
+    /// channels, see `channel_index`.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct ChannelMask: u32 {
-        const FRONT_LEFT = 0b0001;
-        const FRONT_RIGHT = 0b0010;
-        const FRONT_CENTER = 0b0100;
+        const FRONT_LEFT = 0x1;
+        const FRONT_RIGHT = 0x2;
+        const FRONT_CENTER = 0x4;
+        const LOW_FREQUENCY = 0x8;
+        const BACK_LEFT = 0x10;
+        const BACK_RIGHT = 0x20;
+        const FRONT_LEFT_OF_CENTER = 0x40;
+        const FRONT_RIGHT_OF_CENTER = 0x80;
+        const BACK_CENTER = 0x100;
+        const SIDE_LEFT = 0x200;
+        const SIDE_RIGHT = 0x400;
+    }
+}
+
+impl ChannelMask {
+    /// Channels without speaker assignment, see `ChannelMask::raw`.
+    ///
+    /// Not part of the speaker flags, `ChannelMask::all()` only covers speaker positions.
+    pub const UNASSIGNED: ChannelMask = ChannelMask { bits: 0xFFFF_0000 };
+
+    /// Maximum number of channels without speaker assignment.
+    pub const MAX_UNASSIGNED: usize = 16;
+
+    /// Channel mask for `num_channels` channels without speaker assignment.
+    ///
+    /// Intended for multichannel interfaces where speaker positions don't apply.
+    /// `num_channels` is clamped to `MAX_UNASSIGNED`.
+    pub fn raw(num_channels: usize) -> Self {
+        let num_channels = num_channels.min(Self::MAX_UNASSIGNED);
+        ChannelMask {
+            bits: (((1u64 << num_channels) - 1) << 16) as u32,
+        }
+    }
+
+    /// Number of channels without speaker assignment.
+    pub fn num_unassigned(&self) -> usize {
+        (self.bits() & Self::UNASSIGNED.bits()).count_ones() as _
+    }
+
+    /// Index of a single channel within an interleaved frame of this channel mask.
+    ///
+    /// Returns `None` if `channel` isn't a single channel contained in the mask.
+    pub fn channel_index(&self, channel: ChannelMask) -> Option<usize> {
+        if channel.bits().count_ones() != 1 || !self.contains(channel) {
+            return None;
+        }
+        Some((self.bits() & (channel.bits() - 1)).count_ones() as _)
+    }
+
+    /// Single channels of the mask in interleaved order.
+    pub fn channels(&self) -> Vec<ChannelMask> {
+        (0..32)
+            .map(|bit| ChannelMask { bits: 1 << bit })
+            .filter(|channel| self.contains(*channel))
+            .collect()
+    }
+
+    /// Short channel names (e.g `FL`), unassigned channels are named `AUX<n>`.
+    fn short_names(&self) -> Vec<String> {
+        let named = CHANNEL_NAMES
+            .iter()
+            .filter(|(channel, _, _)| self.contains(*channel))
+            .map(|(_, _, short)| short.to_string());
+        let unassigned = (0..self.num_unassigned()).map(|i| format!("AUX{}", i));
+        named.chain(unassigned).collect()
+    }
+}
+
+/// Speaker positions with their full and short names.
+const CHANNEL_NAMES: [(ChannelMask, &str, &str); 11] = [
+    (ChannelMask::FRONT_LEFT, "Front Left", "FL"),
+    (ChannelMask::FRONT_RIGHT, "Front Right", "FR"),
+    (ChannelMask::FRONT_CENTER, "Front Center", "FC"),
+    (ChannelMask::LOW_FREQUENCY, "Low Frequency", "LFE"),
+    (ChannelMask::BACK_LEFT, "Back Left", "BL"),
+    (ChannelMask::BACK_RIGHT, "Back Right", "BR"),
+    (
+        ChannelMask::FRONT_LEFT_OF_CENTER,
+        "Front Left of Center",
+        "FLC",
+    ),
+    (
+        ChannelMask::FRONT_RIGHT_OF_CENTER,
+        "Front Right of Center",
+        "FRC",
+    ),
+    (ChannelMask::BACK_CENTER, "Back Center", "BC"),
+    (ChannelMask::SIDE_LEFT, "Side Left", "SL"),
+    (ChannelMask::SIDE_RIGHT, "Side Right", "SR"),
+];
+
+/// Comma separated list of channel names, e.g `Front Left, Front Right`.
+impl fmt::Display for ChannelMask {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        let mut names = CHANNEL_NAMES
+            .iter()
+            .filter(|(channel, _, _)| self.contains(*channel))
+            .map(|(_, name, _)| name.to_string())
+            .collect::<Vec<_>>();
+        let num_unassigned = self.num_unassigned();
+        if num_unassigned > 0 {
+            names.push(format!("{} Unassigned", num_unassigned));
+        }
+
+        if names.is_empty() {
+            write!(fmt, "None")
+        } else {
+            write!(fmt, "{}", names.join(", "))
+        }
     }
 }
 
 bitflags::bitflags! {
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct StreamFlags: u32 {
         const INPUT = 0b01;
         const OUTPUT = 0b10;
+        /// Combined input and output stream.
+        const DUPLEX = Self::INPUT.bits | Self::OUTPUT.bits;
     }
 }
 
+bitflags::bitflags! {
+    /// Default device roles of a physical device.
+    pub struct DeviceRoleFlags: u32 {
+        /// Default device for general (console) usage.
+        const CONSOLE_DEFAULT = 0b01;
+        /// Default device for voice communication.
+        const COMMUNICATIONS_DEFAULT = 0b10;
+    }
+}
+
+bitflags::bitflags! {
+    /// Conversions applied between the stream buffers and the device.
+    ///
+    /// Conversions are done by the audio engine or the library and add latency and CPU load.
+    pub struct ConversionFlags: u32 {
+        /// Sample rate of the stream differs from the device.
+        const SAMPLE_RATE = 0b001;
+        /// Sample format of the stream differs from the device.
+        const FORMAT = 0b010;
+        /// Channel layout of the stream differs from the device (including channel maps and downmixing).
+        const CHANNELS = 0b100;
+    }
+}
+
+/// Number of frames.
+///
+/// Uses `usize` for indexing stream buffers directly. Backends convert frame counts
+/// to their native types with checked conversions.
 pub type Frames = usize;
 
+/// Set of logical processors, bit `n` selects processor `n`.
+pub type CoreMask = u64;
+
+/// Number of physical devices per stream direction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeviceCounts {
+    /// Number of active devices supporting input streams.
+    pub active_inputs: usize,
+    /// Number of active devices supporting output streams.
+    pub active_outputs: usize,
+    /// Number of all known devices supporting input streams.
+    pub total_inputs: usize,
+    /// Number of all known devices supporting output streams.
+    pub total_outputs: usize,
+}
+
+/// Audio engine periods of a physical device in frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnginePeriods {
+    /// Default period of the device.
+    pub default_device_period: Frames,
+    /// Minimum period of the device.
+    pub min_device_period: Frames,
+    /// Default period of the audio engine in concurrent sharing mode.
+    pub default_period: Frames,
+    /// Fundamental period of the audio engine in concurrent sharing mode.
+    ///
+    /// All supported periods are multiples of the fundamental period.
+    pub fundamental_period: Frames,
+    /// Minimum period of the audio engine in concurrent sharing mode.
+    pub min_period: Frames,
+    /// Maximum period of the audio engine in concurrent sharing mode.
+    pub max_period: Frames,
+}
+
+/// Capabilities of a physical device.
+#[derive(Debug, Clone)]
+pub struct DeviceCapabilities {
+    /// Supported sharing modes.
+    pub sharing: SharingModeFlags,
+    /// Sample rates supported in any sharing mode for the default channel layout, ascending.
+    pub sample_rates: Vec<usize>,
+    /// Sample formats supported in any sharing mode for the default sample rate and channel layout.
+    pub formats: Vec<Format>,
+    /// Channel layouts supported in any sharing mode for the default sample rate and format.
+    pub channel_layouts: Vec<ChannelMask>,
+    /// Audio engine periods.
+    pub periods: EnginePeriods,
+}
+
 #[derive(Debug, Clone)]
 pub struct PhysicalDeviceProperties {
     pub device_name: String,
     pub streams: StreamFlags,
     pub form_factor: FormFactor,
+    /// Device supports hardware offloaded streams.
+    pub offload_capable: bool,
+    /// Default roles of the device for its stream direction.
+    ///
+    /// Only supported by WASAPI, other backends report no roles.
+    pub roles: DeviceRoleFlags,
+    /// Identifier of the adapter (e.g sound card) exposing the device.
+    ///
+    /// Endpoints of the same adapter share the same id. Empty if unknown.
+    /// Only supported by WASAPI.
+    pub adapter_id: String,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Format {
     F32,
     I16,
+    /// Signed 24 bit samples, packed into 3 bytes.
+    I24,
+    /// Signed 32 bit samples.
+    ///
+    /// WASAPI maps 32 bit PCM containers to this format, which may hold fewer valid bits
+    /// (e.g 24 bit samples, see `StreamProperties::valid_bits`).
+    I32,
+    /// Unsigned 32 bit samples.
     U32,
 }
 
+impl Format {
+    /// Size of a single sample in bytes.
+    pub const fn bytes_per_sample(&self) -> usize {
+        match *self {
+            Format::F32 | Format::I32 | Format::U32 => 4,
+            Format::I24 => 3,
+            Format::I16 => 2,
+        }
+    }
+
+    /// Size of a single sample in bits.
+    pub const fn bits_per_sample(&self) -> usize {
+        8 * self.bytes_per_sample()
+    }
+
+    /// Byte value for filling buffers with silence.
+    ///
+    /// Zero for signed integer and floating point formats. `None` for unsigned formats,
+    /// which are centered around their mid value (`0x8000_0000` for `U32`) and can't be
+    /// filled with a single byte value.
+    pub const fn silence_byte(&self) -> Option<u8> {
+        match *self {
+            Format::F32 | Format::I16 | Format::I24 | Format::I32 => Some(0),
+            Format::U32 => None,
+        }
+    }
+
+    /// Floating point sample format.
+    pub const fn is_float(&self) -> bool {
+        matches!(*self, Format::F32)
+    }
+
+    /// Signed sample format.
+    pub const fn is_signed(&self) -> bool {
+        match *self {
+            Format::F32 | Format::I16 | Format::I24 | Format::I32 => true,
+            Format::U32 => false,
+        }
+    }
+}
+
+/// Byte order of the samples in memory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+impl Endianness {
+    /// Byte order of the target platform.
+    #[cfg(target_endian = "little")]
+    pub const NATIVE: Endianness = Endianness::Little;
+    /// Byte order of the target platform.
+    #[cfg(target_endian = "big")]
+    pub const NATIVE: Endianness = Endianness::Big;
+}
+
 /// Sample description.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SampleDesc {
     /// Sample Format.
     pub format: Format,
@@ -118,7 +424,8 @@ pub struct SampleDesc {
 ///
 /// Consists of a channel mask and a sample description.
 /// A frame is composed of one samples per channel.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FrameDesc {
     /// Sample Format.
     pub format: Format,
@@ -126,6 +433,8 @@ pub struct FrameDesc {
     pub sample_rate: usize,
     /// Channel Mask.
     pub channels: ChannelMask,
+    /// Byte order of the samples.
+    pub endianness: Endianness,
 }
 
 impl FrameDesc {
@@ -141,6 +450,45 @@ impl FrameDesc {
             sample_rate: self.sample_rate,
         }
     }
+
+    /// Check the frame description for consistency, e.g before probing a device.
+    ///
+    /// Returns `Error::Validation` for a zero sample rate, an empty channel mask or
+    /// channel bits without speaker position (e.g from deserialized masks).
+    pub fn validate(&self) -> Result<()> {
+        if self.sample_rate == 0 {
+            return Error::validation("Sample rate must not be zero");
+        }
+        if self.channels.is_empty() {
+            return Error::validation("Channel mask must not be empty");
+        }
+        if !(self.channels - ChannelMask::all() - ChannelMask::UNASSIGNED).is_empty() {
+            return Error::validation(format!(
+                "Channel mask {:#x} contains undefined channels",
+                self.channels.bits()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Human readable frame description, e.g `48000 Hz, F32, Stereo (FL+FR)`.
+impl fmt::Display for FrameDesc {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        let layout = match self.num_channels() {
+            1 => "Mono".to_string(),
+            2 => "Stereo".to_string(),
+            n => format!("{} Channels", n),
+        };
+        write!(
+            fmt,
+            "{} Hz, {:?}, {} ({})",
+            self.sample_rate,
+            self.format,
+            layout,
+            self.channels.short_names().join("+")
+        )
+    }
 }
 
 /// Properties of the instance implementation.
@@ -154,17 +502,34 @@ pub struct InstanceProperties {
 
     /// Device sharing modes.
     pub sharing: SharingModeFlags,
+
+    /// Devices can be created with input and output channels (`StreamFlags::DUPLEX`).
+    pub supports_duplex: bool,
+
+    /// Backend supports object based spatial audio.
+    ///
+    /// Only WASAPI (`create_spatial_device`), availability further depends on the
+    /// system and the physical device (see `spatial_audio_available`).
+    pub supports_spatial_audio: bool,
 }
 #[derive(Debug, Clone)]
 pub enum Error {
     /// Device Lost
     DeviceLost,
 
+    /// Timeout expired while waiting for the stream buffers.
+    Timeout,
+
     /// Validation error.
     ///
     /// Denote errors caused by incorrect API usage.
     Validation { description: String },
 
+    /// Unsupported device.
+    ///
+    /// The device doesn't expose any format usable by the backend.
+    Unsupported { description: String },
+
     /// Internal implementation errors.
     Internal { cause: String },
 }
@@ -175,9 +540,13 @@ impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
         match *self {
             Error::DeviceLost => writeln!(fmt, "Device lost"),
+            Error::Timeout => writeln!(fmt, "Timeout"),
             Error::Validation { ref description } => {
                 writeln!(fmt, "Validation error: {}", description)
             }
+            Error::Unsupported { ref description } => {
+                writeln!(fmt, "Unsupported: {}", description)
+            }
             Error::Internal { ref cause } => writeln!(fmt, "Internal: {}", cause),
         }
     }
@@ -189,16 +558,72 @@ impl Error {
             description: description.to_string(),
         })
     }
+
+    pub(crate) fn unsupported<O, T: ToString>(description: T) -> Result<O> {
+        Err(Error::Unsupported {
+            description: description.to_string(),
+        })
+    }
 }
 
 pub type Result<T> = result::Result<T, Error>;
 
+/// Device and stream events, see `Instance::set_event_callback`.
+///
+/// Events are backend independent, backends only emit the subset they can observe.
 #[derive(Debug, Clone)]
 pub enum Event {
-    Added(PhysicalDevice),
-    Removed(PhysicalDevice),
-    DefaultInputDevice(Option<PhysicalDevice>),
-    DefaultOutputDevice(Option<PhysicalDevice>),
+    /// Physical device has been added to the system.
+    ///
+    /// The device may not be usable yet, see `DeviceStateChanged`.
+    DeviceAdded(PhysicalDevice),
+    /// Physical device has been removed from the system.
+    ///
+    /// The handle stays valid, but the device won't be enumerated anymore.
+    DeviceRemoved(PhysicalDevice),
+    /// Default input device has been changed, `None` if no input device is available.
+    DefaultInputChanged(Option<PhysicalDevice>),
+    /// Default output device has been changed, `None` if no output device is available.
+    DefaultOutputChanged(Option<PhysicalDevice>),
+    /// Physical device has been enabled or disabled (e.g by unplugging a headset).
+    ///
+    /// Only active devices are enumerated and can be used for creating devices.
+    DeviceStateChanged {
+        device: PhysicalDevice,
+        active: bool,
+    },
+    /// Device stream has been moved to another physical device.
+    StreamRerouted(PhysicalDevice),
+    /// Capture stream has been reset after repeated discontinuities.
+    CaptureRecovered,
+    /// Session volume of a device stream has been changed (e.g by the user in the volume mixer).
+    ///
+    /// `level` is in range `[0.0, 1.0]`. Only supported by WASAPI.
+    VolumeChanged { level: f32, muted: bool },
+}
+
+/// Resampler quality for sample rate conversion done by the audio engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SrcQuality {
+    /// No conversion, the format must be supported by the audio engine.
+    None,
+    /// Conversion using a low quality, linear resampler.
+    Linear,
+    /// Conversion using the default quality resampler of the audio engine.
+    Default,
+}
+
+/// Instance wide strategy for resolving sample rate mismatches of concurrent streams.
+///
+/// Only supported by WASAPI, see `wasapi::Instance::set_resample_policy`.
+/// The crate doesn't provide an own resampler, conversion is always done by the audio engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResamplePolicy {
+    /// Convert with the audio engine, devices without explicit `src_quality`
+    /// use `SrcQuality::Default`.
+    EnginePreferred,
+    /// Only allow the native mix format sample rate, mismatches are rejected on device creation.
+    NativeOnly,
 }
 
 #[derive(Debug, Clone)]
@@ -206,6 +631,75 @@ pub struct DeviceDesc {
     pub physical_device: PhysicalDevice,
     pub sharing: SharingMode,
     pub sample_desc: SampleDesc,
+    /// Don't persist the session volume across application restarts.
+    ///
+    /// Only supported by WASAPI, other backends ignore this flag.
+    pub no_persist: bool,
+    /// Timeout for acquiring the stream buffers if `submit_buffers` is called without timeout.
+    ///
+    /// `None` defaults to twice the buffer duration of the stream, `INFINITE_TIMEOUT`
+    /// will wait indefinitely. Only used by polling instances.
+    pub acquire_timeout: Option<Duration>,
+    /// Sample rate conversion by the audio engine.
+    ///
+    /// Only supported by WASAPI in concurrent sharing mode, other backends ignore this field.
+    /// Conversion only takes place if the requested format differs from the mix format.
+    /// May be overridden by the resample policy of the instance.
+    pub src_quality: SrcQuality,
+    /// Reset input streams after repeated data discontinuities (overruns).
+    ///
+    /// Pending data is dropped for resynchronizing the stream and
+    /// `Event::CaptureRecovered` is emitted. Only supported by WASAPI.
+    pub auto_recover: bool,
+    /// Audio session the stream will be added to.
+    ///
+    /// Streams sharing the same session id are grouped together (e.g in the volume mixer).
+    /// This also applies across processes: helper processes passing the same id share
+    /// the volume mixer entry with the main process (see the `shared_session` example).
+    /// `None` uses the default session of the process. Only supported by WASAPI.
+    pub session_guid: Option<u128>,
+    /// Wait for the stream buffers in an alertable state.
+    ///
+    /// Allows queued asynchronous procedure calls (e.g `QueueUserAPC`) to run on the
+    /// thread calling `submit_buffers`. Only supported by WASAPI.
+    pub alertable_wait: bool,
+    /// Requested latency of the stream buffer.
+    ///
+    /// Translated on a best-effort basis to the native buffering parameters of the backend
+    /// (e.g buffer duration for WASAPI or target length for PulseAudio).
+    /// The achieved latency is reported by `StreamProperties::latency`.
+    /// `None` uses the default buffering of the backend.
+    pub target_latency: Option<Duration>,
+    /// Routing of logical stream channels to physical device channels.
+    ///
+    /// Each entry maps the logical channel at its index to a physical channel index of the
+    /// channel mask used for the stream. The stream callback receives buffers with
+    /// `channel_map.len()` unassigned channels, unmapped physical output channels are silent.
+    /// Only supported by WASAPI.
+    pub channel_map: Option<Vec<usize>>,
+    /// Downmix stereo output streams on mono devices.
+    ///
+    /// The stream callback still receives stereo buffers, which are mixed down with -3dB
+    /// per channel and saturated to the sample range. Requires `F32` or `I16` samples and
+    /// can't be combined with `channel_map`. Only supported by WASAPI.
+    pub auto_downmix: bool,
+    /// Physical device of the input stream for duplex devices.
+    ///
+    /// WASAPI endpoints are either input or output devices, duplex devices open the input
+    /// stream on this device and the output stream on `physical_device`. `None` uses the
+    /// default input device. Ignored by other backends and for non-duplex devices.
+    pub input_device: Option<PhysicalDevice>,
+}
+
+impl DeviceDesc {
+    // Only used by polling backends.
+    #[allow(dead_code)]
+    pub(crate) fn acquire_timeout_ms(&self, properties: &StreamProperties) -> u32 {
+        timeout_ms(
+            self.acquire_timeout
+                .unwrap_or_else(|| 2 * properties.latency()),
+        )
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -217,8 +711,43 @@ pub struct Channels {
 /// Device Stream properties.
 #[derive(Debug, Clone, Copy)]
 pub struct StreamProperties {
+    /// Channel layout of the stream buffers.
     pub channels: ChannelMask,
+    /// Channels requested on device creation.
+    pub requested_channels: ChannelMask,
+    /// Channels the audio engine is running the device with.
+    ///
+    /// Differs from `requested_channels` if the audio engine converts between channel layouts.
+    pub device_channels: ChannelMask,
     pub sample_rate: usize,
+    /// Size of the stream buffer in frames (not bytes).
+    ///
+    /// A frame contains one sample per channel, see `buffer_size_bytes` for the size in bytes.
+    pub buffer_size_frames: Frames,
+    /// Number of valid bits per sample.
+    ///
+    /// May be smaller than the container size of the sample format (e.g 24 bit samples
+    /// padded to 32 bit). The valid bits are stored in the most significant bits.
+    pub valid_bits: u16,
+    /// The device is polled at the device period instead of being signaled by the audio engine.
+    ///
+    /// Only used by WASAPI exclusive streams if the driver doesn't support event driven mode.
+    pub timer_driven: bool,
+    /// Conversions between the stream buffers and the device, see `is_converting`.
+    ///
+    /// Only reported by WASAPI, other backends return empty flags.
+    pub conversions: ConversionFlags,
+}
+
+/// Stream configuration negotiated for a device description.
+#[derive(Debug, Clone, Copy)]
+pub struct NegotiatedConfig {
+    pub sharing: SharingMode,
+    /// Frame format of the stream buffers.
+    pub frame_desc: FrameDesc,
+    /// Number of frames per buffer based on the default device period.
+    ///
+    /// The audio engine may round the buffer size up on device creation.
     pub buffer_size: Frames,
 }
 
@@ -226,6 +755,31 @@ impl StreamProperties {
     pub fn num_channels(&self) -> usize {
         self.channels.bits().count_ones() as _
     }
+
+    /// Check if any conversion takes place between the stream buffers and the device.
+    pub fn is_converting(&self) -> bool {
+        !self.conversions.is_empty()
+    }
+
+    /// Channels of the stream buffers in interleaved order.
+    ///
+    /// Allocates, callbacks should compute the order once and reuse it.
+    pub fn channel_order(&self) -> Vec<ChannelMask> {
+        self.channels.channels()
+    }
+
+    /// Latency of the stream buffer.
+    pub fn latency(&self) -> Duration {
+        if self.sample_rate == 0 {
+            return Duration::from_secs(0);
+        }
+        Duration::from_secs_f64(self.buffer_size_frames as f64 / self.sample_rate as f64)
+    }
+
+    /// Size of the stream buffer in bytes for samples of the given format.
+    pub fn buffer_size_bytes(&self, format: Format) -> usize {
+        self.buffer_size_frames * self.num_channels() * format.bytes_per_sample()
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -237,13 +791,55 @@ pub struct StreamBuffers {
     ///
     /// For streams with empty input channels the pointer will be null.
     /// The buffer pointer is aligned according to the stream format requirements.
+    /// Stronger alignment depends on the backend, see `is_aligned`.
     pub input: *const (),
 
-    /// Input frame buffer.
+    /// Output frame buffer.
     ///
     /// For streams with empty output channels the pointer will be null.
     /// The buffer pointer is aligned according to the stream format requirements.
+    /// Stronger alignment depends on the backend, see `is_aligned`.
     pub output: *mut (),
+
+    /// Number of frames missed before the input buffer.
+    ///
+    /// Non-zero if the input stream had a discontinuity (e.g due to an overrun).
+    /// Inserting this number of silent frames keeps the timeline of the captured data aligned.
+    pub gap_frames: u64,
+
+    /// Capture time of the first input frame.
+    ///
+    /// Position of the system performance counter in 100ns units.
+    /// `None` for output buffers, backends without timestamps and timestamps
+    /// flagged as unreliable by the device.
+    pub capture_time: Option<u64>,
+
+    /// Input buffer is flagged as silent by the device, see `is_silent`.
+    pub silent: bool,
+}
+
+impl StreamBuffers {
+    /// Check if all non-null buffer pointers are aligned to `bytes`.
+    ///
+    /// Alignment beyond the sample format requirements isn't guaranteed by the backends.
+    /// WASAPI buffers are 16 byte aligned, unaligned engine buffers are replaced by an
+    /// aligned scratch buffer, which is copied to the engine buffer after the callback.
+    /// Code relying on aligned SIMD loads should check the alignment and fall back to
+    /// unaligned loads otherwise.
+    ///
+    /// `bytes` **must** be a power of two.
+    pub fn is_aligned(&self, bytes: usize) -> bool {
+        debug_assert!(bytes.is_power_of_two());
+        (self.input as usize).is_multiple_of(bytes) && (self.output as usize).is_multiple_of(bytes)
+    }
+
+    /// Check if the input buffer is guaranteed to contain only silence.
+    ///
+    /// Capture callbacks may skip processing of silent buffers (e.g voice activity detection).
+    /// Backends without silence reporting always return `false`.
+    pub fn is_silent(&self) -> bool {
+        self.silent
+    }
 }
 
 pub struct Stream {
@@ -251,6 +847,110 @@ pub struct Stream {
     pub buffers: StreamBuffers,
 }
 
+impl Stream {
+    /// Input frame buffer as slice of samples.
+    ///
+    /// The slice covers `frames * num_channels` samples, out of bounds access will panic.
+    ///
+    /// ## Validation
+    ///
+    /// - `T` **must** match the sample format of the stream.
+    ///
+    /// # Safety
+    ///
+    /// `buffers.input` **must** point to `frames` frames of the stream format, as passed to
+    /// the stream callback by the backends.
+    pub unsafe fn input_buffer<T>(&self) -> &[T] {
+        debug_assert!(
+            !self.buffers.input.is_null(),
+            "Input buffer accessed for stream without input channels"
+        );
+        debug_assert!(
+            (self.buffers.input as *const T).is_aligned(),
+            "Input buffer isn't aligned for the sample type"
+        );
+        slice::from_raw_parts(
+            self.buffers.input as *const T,
+            self.buffers.frames * self.properties.num_channels(),
+        )
+    }
+
+    /// Output frame buffer as slice of samples.
+    ///
+    /// The slice covers `frames * num_channels` samples, out of bounds access will panic.
+    ///
+    /// ## Validation
+    ///
+    /// - `T` **must** match the sample format of the stream.
+    ///
+    /// # Safety
+    ///
+    /// `buffers.output` **must** point to `frames` frames of the stream format, as passed
+    /// to the stream callback by the backends.
+    pub unsafe fn output_buffer<T>(&mut self) -> &mut [T] {
+        debug_assert!(
+            !self.buffers.output.is_null(),
+            "Output buffer accessed for stream without output channels"
+        );
+        debug_assert!(
+            (self.buffers.output as *mut T).is_aligned(),
+            "Output buffer isn't aligned for the sample type"
+        );
+        slice::from_raw_parts_mut(
+            self.buffers.output as *mut T,
+            self.buffers.frames * self.properties.num_channels(),
+        )
+    }
+
+    /// Input frame buffer as slice of frames with `N` channels each.
+    ///
+    /// ## Validation
+    ///
+    /// - `T` **must** match the sample format of the stream.
+    /// - `N` **must** match the number of channels of the stream, panics otherwise.
+    ///
+    /// # Safety
+    ///
+    /// `buffers.input` **must** point to `frames` frames of the stream format, as passed to
+    /// the stream callback by the backends.
+    pub unsafe fn input_frames<T, const N: usize>(&self) -> &[[T; N]] {
+        assert_eq!(
+            N,
+            self.properties.num_channels(),
+            "Frame size doesn't match the number of stream channels"
+        );
+        debug_assert!(
+            !self.buffers.input.is_null(),
+            "Input buffer accessed for stream without input channels"
+        );
+        slice::from_raw_parts(self.buffers.input as *const [T; N], self.buffers.frames)
+    }
+
+    /// Output frame buffer as slice of frames with `N` channels each.
+    ///
+    /// ## Validation
+    ///
+    /// - `T` **must** match the sample format of the stream.
+    /// - `N` **must** match the number of channels of the stream, panics otherwise.
+    ///
+    /// # Safety
+    ///
+    /// `buffers.output` **must** point to `frames` frames of the stream format, as passed
+    /// to the stream callback by the backends.
+    pub unsafe fn output_frames<T, const N: usize>(&mut self) -> &mut [[T; N]] {
+        assert_eq!(
+            N,
+            self.properties.num_channels(),
+            "Frame size doesn't match the number of stream channels"
+        );
+        debug_assert!(
+            !self.buffers.output.is_null(),
+            "Output buffer accessed for stream without output channels"
+        );
+        slice::from_raw_parts_mut(self.buffers.output as *mut [T; N], self.buffers.frames)
+    }
+}
+
 pub type StreamCallback = Box<dyn FnMut(Stream) + Send>;
 
 pub trait Instance {
@@ -275,6 +975,9 @@ pub trait Instance {
     ///
     /// The list may vary over time when devices get added or removed.
     /// Users may track changes manually by registering an event handler.
+    ///
+    /// The order of the list is deterministic, devices are sorted by a
+    /// backend specific stable key (e.g. endpoint id).
     unsafe fn enumerate_physical_devices(&self) -> Vec<PhysicalDevice>;
 
     /// Get the default physical input device.
@@ -283,6 +986,62 @@ pub trait Instance {
     /// Get the default physical output device.
     unsafe fn default_physical_output_device(&self) -> Option<PhysicalDevice>;
 
+    /// Get the default physical input device or the first device supporting input streams.
+    ///
+    /// # Safety
+    ///
+    /// Same requirements as `default_physical_input_device` and
+    /// `enumerate_physical_devices`.
+    unsafe fn default_input_or_first(&self) -> Option<PhysicalDevice> {
+        self.default_physical_input_device().or_else(|| {
+            self.enumerate_physical_devices()
+                .into_iter()
+                .find(|&device| match self.physical_device_properties(device) {
+                    Ok(properties) => properties.streams.contains(StreamFlags::INPUT),
+                    Err(_) => false,
+                })
+        })
+    }
+
+    /// Get the default physical output device or the first device supporting output streams.
+    ///
+    /// # Safety
+    ///
+    /// Same requirements as `default_physical_output_device` and
+    /// `enumerate_physical_devices`.
+    unsafe fn default_output_or_first(&self) -> Option<PhysicalDevice> {
+        self.default_physical_output_device().or_else(|| {
+            self.enumerate_physical_devices()
+                .into_iter()
+                .find(|&device| match self.physical_device_properties(device) {
+                    Ok(properties) => properties.streams.contains(StreamFlags::OUTPUT),
+                    Err(_) => false,
+                })
+        })
+    }
+
+    /// Get the default concurrent format of the default output device.
+    ///
+    /// Applications should adopt the sample rate of this format instead of assuming
+    /// a fixed rate (e.g 48kHz), which results in pitch-shifted playback on devices
+    /// running at a different rate.
+    ///
+    /// Returns `Error::Unsupported` if there is no default output device.
+    ///
+    /// # Safety
+    ///
+    /// Same requirements as `physical_device_default_concurrent_format`.
+    unsafe fn default_output_format(&self) -> Result<FrameDesc> {
+        match self.default_physical_output_device() {
+            Some(physical_device) => {
+                self.physical_device_default_concurrent_format(physical_device)
+            }
+            None => Err(Error::Unsupported {
+                description: "No default output device".into(),
+            }),
+        }
+    }
+
     /// Get physical device properties.
     ///
     /// ## Validation
@@ -305,6 +1064,44 @@ pub trait Instance {
         frame_desc: FrameDesc,
     ) -> bool;
 
+    /// Query all sample formats supported by a physical device.
+    ///
+    /// Probes each sample format for the given sharing mode, sample rate and channels.
+    ///
+    /// ## Validation
+    ///
+    /// - `physical_device` **must** be a valid handle.
+    unsafe fn physical_device_supported_formats(
+        &self,
+        physical_device: PhysicalDevice,
+        sharing: SharingMode,
+        sample_rate: usize,
+        channels: ChannelMask,
+    ) -> Vec<Format> {
+        [
+            Format::F32,
+            Format::I16,
+            Format::I24,
+            Format::I32,
+            Format::U32,
+        ]
+        .iter()
+        .copied()
+        .filter(|&format| {
+            self.physical_device_supports_format(
+                physical_device,
+                sharing,
+                FrameDesc {
+                    format,
+                    sample_rate,
+                    channels,
+                    endianness: Endianness::NATIVE,
+                },
+            )
+        })
+        .collect()
+    }
+
     /// Get default concurrent mode format.
     ///
     /// Returns the default format used for physical devices when
@@ -339,6 +1136,70 @@ pub trait Instance {
         callback: StreamCallback,
     ) -> Result<Self::Device>;
 
+    /// Create a new logical device matching the default format of a physical device.
+    ///
+    /// Sample format, rate and channels are taken from the default concurrent format.
+    /// For exclusive sharing mode the same format is requested from the device.
+    ///
+    /// ## Validation
+    ///
+    /// - `physical_device` **must** be a valid handle.
+    /// - `streams` **must** be either `StreamFlags::INPUT` or `StreamFlags::OUTPUT`.
+    /// - The device properties **must** include `streams`.
+    unsafe fn create_device_default(
+        &self,
+        physical_device: PhysicalDevice,
+        sharing: SharingMode,
+        streams: StreamFlags,
+        callback: StreamCallback,
+    ) -> Result<Self::Device> {
+        if streams != StreamFlags::INPUT && streams != StreamFlags::OUTPUT {
+            return Error::validation("Exactly one stream direction must be specified");
+        }
+
+        let frame_desc = self.physical_device_default_concurrent_format(physical_device)?;
+        if sharing == SharingMode::Exclusive
+            && !self.physical_device_supports_format(physical_device, sharing, frame_desc)
+        {
+            return Error::validation("Default format isn't supported in exclusive sharing mode");
+        }
+
+        let channels = if streams == StreamFlags::INPUT {
+            Channels {
+                input: frame_desc.channels,
+                output: ChannelMask::empty(),
+            }
+        } else {
+            Channels {
+                input: ChannelMask::empty(),
+                output: frame_desc.channels,
+            }
+        };
+
+        self.create_device(
+            DeviceDesc {
+                physical_device,
+                sharing,
+                sample_desc: SampleDesc {
+                    format: frame_desc.format,
+                    sample_rate: frame_desc.sample_rate,
+                },
+                no_persist: false,
+                acquire_timeout: None,
+                src_quality: SrcQuality::None,
+                auto_recover: false,
+                session_guid: None,
+                alertable_wait: false,
+                target_latency: None,
+                channel_map: None,
+                auto_downmix: false,
+                input_device: None,
+            },
+            channels,
+            callback,
+        )
+    }
+
     /// Create an audio session.
     ///
     /// Audio sessions are needed for ensuring realtime properties for audio streaming.
@@ -351,8 +1212,40 @@ pub trait Instance {
     /// ## Validation
     ///
     /// - `sample_rate` **must** not be `DEFAULT_SAMPLE_RATE`.
-    unsafe fn create_session(&self, sample_rate: usize) -> Result<Self::Session>;
+    unsafe fn create_session(&self, sample_rate: usize) -> Result<Self::Session> {
+        self.create_session_with_affinity(sample_rate, None)
+    }
+
+    /// Create an audio session and pin the current thread to a set of processors.
+    ///
+    /// Hybrid CPUs may schedule the audio thread onto efficiency cores, restricting the
+    /// thread to performance cores avoids missed deadlines. The previous affinity is
+    /// restored when the session is dropped. `None` keeps the current affinity, equal
+    /// to `create_session`.
+    ///
+    /// Backends without sessions return `Error::Unsupported` for a thread affinity.
+    ///
+    /// ## Validation
+    ///
+    /// - `sample_rate` **must** not be `DEFAULT_SAMPLE_RATE`.
+    /// - `thread_affinity` **must** contain at least one processor of the system,
+    ///   returns `Error::Validation` otherwise.
+    /// - The session **should** be dropped on the thread it has been created on.
+    unsafe fn create_session_with_affinity(
+        &self,
+        sample_rate: usize,
+        thread_affinity: Option<CoreMask>,
+    ) -> Result<Self::Session>;
 
+    /// Register a callback for device events.
+    ///
+    /// The callback stays registered until it's replaced or unregistered with `None`,
+    /// no polling is required for receiving events. Dropping the instance unregisters
+    /// the callback.
+    ///
+    /// Events are delivered directly from the backend notification thread and aren't queued.
+    /// For ignoring events caused by a deliberate reconfiguration, unregister the callback
+    /// (`None`) before reconfiguring and register it again afterwards.
     unsafe fn set_event_callback<F>(&mut self, callback: Option<F>) -> Result<()>
     where
         F: FnMut(Event) + Send + 'static;
@@ -370,10 +1263,80 @@ pub trait Device {
     /// It will internally wait for acquiring the streaming buffers, call the stream callback
     /// for reading/writing the buffers and submit these to the audio engine.
     ///
+    /// `timeout` may be `INFINITE_TIMEOUT` or `None` for using the acquire timeout of the
+    /// device (see `DeviceDesc::acquire_timeout`). Returns `Error::Timeout` if no buffers
+    /// could be acquired.
+    /// For devices created with `alertable_wait`, returns early without submitting buffers
+    /// after queued APCs have been executed.
+    ///
     /// ## Validation
     ///
     /// - **Must** only be called for devices, which corresponding instance streaming properties are `Polling`.
-    unsafe fn submit_buffers(&mut self, _timeout_ms: u32) -> Result<()> {
+    /// - **Must** only be called after the device has been started, returns `Error::Validation` otherwise.
+    unsafe fn submit_buffers(&mut self, _timeout: Option<Duration>) -> Result<()> {
         Error::validation("`submit_buffers` not allowed for callback based instances")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unassigned_channels_keep_their_bits() {
+        let channels = ChannelMask::FRONT_LEFT | ChannelMask::raw(2);
+        assert_eq!(channels.bits(), 0x3_0001);
+        assert_eq!(channels.num_unassigned(), 2);
+        assert_eq!(channels.channels().len(), 3);
+        assert!(!ChannelMask::all().intersects(ChannelMask::UNASSIGNED));
+
+        let frame_desc = FrameDesc {
+            format: Format::F32,
+            channels,
+            sample_rate: 48_000,
+            endianness: Endianness::Little,
+        };
+        assert!(frame_desc.validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_frame_descs_are_rejected() {
+        let stereo = FrameDesc {
+            format: Format::F32,
+            channels: ChannelMask::FRONT_LEFT | ChannelMask::FRONT_RIGHT,
+            sample_rate: 48_000,
+            endianness: Endianness::Little,
+        };
+        assert!(stereo.validate().is_ok());
+
+        let invalid = [
+            FrameDesc {
+                sample_rate: 0,
+                ..stereo
+            },
+            FrameDesc {
+                channels: ChannelMask::empty(),
+                ..stereo
+            },
+            FrameDesc {
+                // Bit between the speaker positions and the unassigned channels.
+                channels: stereo.channels | unsafe { ChannelMask::from_bits_unchecked(0x800) },
+                ..stereo
+            },
+        ];
+        for frame_desc in &invalid {
+            match frame_desc.validate() {
+                Err(Error::Validation { .. }) => (),
+                result => panic!("{:?}: {:?}", frame_desc, result),
+            }
+        }
+    }
+
+    #[test]
+    fn silence_bytes() {
+        for &format in &[Format::F32, Format::I16, Format::I24, Format::I32] {
+            assert_eq!(format.silence_byte(), Some(0), "{:?}", format);
+        }
+        assert_eq!(Format::U32.silence_byte(), None);
+    }
+}
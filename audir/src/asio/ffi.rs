@@ -0,0 +1,167 @@
+//! ASIO host interface declarations.
+//!
+//! Drivers are in-process COM servers registered under `HKEY_LOCAL_MACHINE\SOFTWARE\ASIO`.
+//! The driver interface doesn't have an interface id of its own, drivers are created
+//! with their class id as interface id.
+
+#![allow(
+    non_camel_case_types,
+    non_snake_case,
+    non_upper_case_globals,
+    clippy::upper_case_acronyms
+)]
+
+use winapi::ctypes::{c_char, c_long, c_ulong, c_void};
+use winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
+
+pub type ASIOBool = c_long;
+pub type ASIOError = c_long;
+pub type ASIOSampleRate = f64;
+pub type ASIOSampleType = c_long;
+
+pub const ASIOFalse: ASIOBool = 0;
+pub const ASIOTrue: ASIOBool = 1;
+
+pub const ASE_OK: ASIOError = 0;
+pub const ASE_SUCCESS: ASIOError = 0x3f48_47a0;
+
+pub const ASIOSTInt16LSB: ASIOSampleType = 16;
+pub const ASIOSTInt24LSB: ASIOSampleType = 17;
+pub const ASIOSTInt32LSB: ASIOSampleType = 18;
+pub const ASIOSTFloat32LSB: ASIOSampleType = 19;
+
+pub const kAsioSelectorSupported: c_long = 1;
+pub const kAsioEngineVersion: c_long = 2;
+pub const kAsioResetRequest: c_long = 3;
+pub const kAsioBufferSizeChange: c_long = 4;
+pub const kAsioResyncRequest: c_long = 5;
+pub const kAsioLatenciesChanged: c_long = 6;
+
+/// Granularity of drivers only supporting power of two buffer sizes.
+pub const ASIO_GRANULARITY_POWER_OF_TWO: c_long = -1;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ASIOSamples {
+    pub hi: c_ulong,
+    pub lo: c_ulong,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ASIOTimeStamp {
+    pub hi: c_ulong,
+    pub lo: c_ulong,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct ASIOChannelInfo {
+    pub channel: c_long,
+    pub isInput: ASIOBool,
+    pub isActive: ASIOBool,
+    pub channelGroup: c_long,
+    pub type_: ASIOSampleType,
+    pub name: [c_char; 32],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ASIOBufferInfo {
+    pub isInput: ASIOBool,
+    pub channelNum: c_long,
+    /// Double buffer halves, written by the driver on buffer creation.
+    pub buffers: [*mut c_void; 2],
+}
+
+#[repr(C)]
+pub struct ASIOCallbacks {
+    pub bufferSwitch: unsafe extern "C" fn(doubleBufferIndex: c_long, directProcess: ASIOBool),
+    pub sampleRateDidChange: unsafe extern "C" fn(sRate: ASIOSampleRate),
+    pub asioMessage: unsafe extern "C" fn(
+        selector: c_long,
+        value: c_long,
+        message: *mut c_void,
+        opt: *mut f64,
+    ) -> c_long,
+    /// `ASIOTime` isn't used, drivers fall back to `bufferSwitch` without time info support.
+    pub bufferSwitchTimeInfo: unsafe extern "C" fn(
+        params: *mut c_void,
+        doubleBufferIndex: c_long,
+        directProcess: ASIOBool,
+    ) -> *mut c_void,
+}
+
+#[repr(C)]
+pub struct IASIOVtbl {
+    pub parent: IUnknownVtbl,
+    pub init: unsafe extern "system" fn(This: *mut IASIO, sysHandle: *mut c_void) -> ASIOBool,
+    pub getDriverName: unsafe extern "system" fn(This: *mut IASIO, name: *mut c_char),
+    pub getDriverVersion: unsafe extern "system" fn(This: *mut IASIO) -> c_long,
+    pub getErrorMessage: unsafe extern "system" fn(This: *mut IASIO, string: *mut c_char),
+    pub start: unsafe extern "system" fn(This: *mut IASIO) -> ASIOError,
+    pub stop: unsafe extern "system" fn(This: *mut IASIO) -> ASIOError,
+    pub getChannels: unsafe extern "system" fn(
+        This: *mut IASIO,
+        numInputChannels: *mut c_long,
+        numOutputChannels: *mut c_long,
+    ) -> ASIOError,
+    pub getLatencies: unsafe extern "system" fn(
+        This: *mut IASIO,
+        inputLatency: *mut c_long,
+        outputLatency: *mut c_long,
+    ) -> ASIOError,
+    pub getBufferSize: unsafe extern "system" fn(
+        This: *mut IASIO,
+        minSize: *mut c_long,
+        maxSize: *mut c_long,
+        preferredSize: *mut c_long,
+        granularity: *mut c_long,
+    ) -> ASIOError,
+    pub canSampleRate:
+        unsafe extern "system" fn(This: *mut IASIO, sampleRate: ASIOSampleRate) -> ASIOError,
+    pub getSampleRate:
+        unsafe extern "system" fn(This: *mut IASIO, sampleRate: *mut ASIOSampleRate) -> ASIOError,
+    pub setSampleRate:
+        unsafe extern "system" fn(This: *mut IASIO, sampleRate: ASIOSampleRate) -> ASIOError,
+    pub getClockSources: unsafe extern "system" fn(
+        This: *mut IASIO,
+        clocks: *mut c_void,
+        numSources: *mut c_long,
+    ) -> ASIOError,
+    pub setClockSource: unsafe extern "system" fn(This: *mut IASIO, reference: c_long) -> ASIOError,
+    pub getSamplePosition: unsafe extern "system" fn(
+        This: *mut IASIO,
+        sPos: *mut ASIOSamples,
+        tStamp: *mut ASIOTimeStamp,
+    ) -> ASIOError,
+    pub getChannelInfo:
+        unsafe extern "system" fn(This: *mut IASIO, info: *mut ASIOChannelInfo) -> ASIOError,
+    pub createBuffers: unsafe extern "system" fn(
+        This: *mut IASIO,
+        bufferInfos: *mut ASIOBufferInfo,
+        numChannels: c_long,
+        bufferSize: c_long,
+        callbacks: *const ASIOCallbacks,
+    ) -> ASIOError,
+    pub disposeBuffers: unsafe extern "system" fn(This: *mut IASIO) -> ASIOError,
+    pub controlPanel: unsafe extern "system" fn(This: *mut IASIO) -> ASIOError,
+    pub future: unsafe extern "system" fn(
+        This: *mut IASIO,
+        selector: c_long,
+        opt: *mut c_void,
+    ) -> ASIOError,
+    pub outputReady: unsafe extern "system" fn(This: *mut IASIO) -> ASIOError,
+}
+
+#[repr(C)]
+pub struct IASIO {
+    pub lpVtbl: *const IASIOVtbl,
+}
+
+impl std::ops::Deref for IASIO {
+    type Target = IUnknown;
+    fn deref(&self) -> &IUnknown {
+        unsafe { &*(self as *const IASIO as *const IUnknown) }
+    }
+}
@@ -0,0 +1,779 @@
+//! ASIO backend.
+//!
+//! Drivers are listed from the registry and loaded as in-process COM servers, the
+//! interface is declared in `ffi` so building doesn't require the Steinberg ASIO SDK.
+//! ASIO only allows a single loaded driver per process and drives a single stream,
+//! queries for another driver fail while a device is open.
+//!
+//! Only 64 bit targets are supported, 32 bit drivers use the `thiscall` convention.
+
+mod ffi;
+
+use self::ffi::*;
+use crate::{
+    alloc_guard,
+    api::{self, Result},
+    handle::HandleTable,
+};
+use std::{
+    cell::RefCell,
+    ffi::{CStr, OsStr},
+    mem,
+    os::windows::ffi::OsStrExt,
+    ptr,
+    rc::Rc,
+    slice,
+    sync::atomic::{AtomicPtr, Ordering},
+    time::Duration,
+};
+use winapi::ctypes::{c_char, c_long, c_void};
+use winapi::shared::{guiddef::GUID, minwindef::HKEY, winerror, wtypesbase::CLSCTX_INPROC_SERVER};
+use winapi::um::{
+    combaseapi::{CLSIDFromString, CoCreateInstance, CoInitializeEx},
+    objbase::COINIT_APARTMENTTHREADED,
+    winnt::KEY_READ,
+    winreg::{
+        RegCloseKey, RegEnumKeyExW, RegGetValueW, RegOpenKeyExW, HKEY_LOCAL_MACHINE, RRF_RT_REG_SZ,
+    },
+};
+
+/// Stream of the open device.
+///
+/// ASIO callbacks don't carry user data, the stream is shared through a global.
+static STREAM: AtomicPtr<Stream> = AtomicPtr::new(ptr::null_mut());
+
+static CALLBACKS: ASIOCallbacks = ASIOCallbacks {
+    bufferSwitch: buffer_switch,
+    sampleRateDidChange: sample_rate_did_change,
+    asioMessage: asio_message,
+    bufferSwitchTimeInfo: buffer_switch_time_info,
+};
+
+fn wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
+
+fn map_sample_type(ty: ASIOSampleType) -> Option<api::Format> {
+    match ty {
+        ffi::ASIOSTInt16LSB => Some(api::Format::I16),
+        ffi::ASIOSTInt24LSB => Some(api::Format::I24),
+        ffi::ASIOSTInt32LSB => Some(api::Format::I32),
+        ffi::ASIOSTFloat32LSB => Some(api::Format::F32),
+        // Big endian and LSB aligned 32 bit containers (e.g `ASIOSTInt32LSB24`).
+        _ => None,
+    }
+}
+
+/// Copy the samples of a single channel into an interleaved buffer.
+fn interleave(src: &[u8], dst: &mut [u8], channel: usize, num_channels: usize, bytes: usize) {
+    for (frame, sample) in src.chunks_exact(bytes).enumerate() {
+        let offset = (frame * num_channels + channel) * bytes;
+        dst[offset..offset + bytes].copy_from_slice(sample);
+    }
+}
+
+/// Copy the samples of a single channel out of an interleaved buffer.
+fn deinterleave(src: &[u8], dst: &mut [u8], channel: usize, num_channels: usize, bytes: usize) {
+    for (frame, sample) in dst.chunks_exact_mut(bytes).enumerate() {
+        let offset = (frame * num_channels + channel) * bytes;
+        sample.copy_from_slice(&src[offset..offset + bytes]);
+    }
+}
+
+/// Buffer size limits of a driver in frames.
+#[derive(Debug, Copy, Clone)]
+struct BufferSize {
+    min: usize,
+    max: usize,
+    preferred: usize,
+    granularity: c_long,
+}
+
+impl BufferSize {
+    /// Closest supported buffer size for the target latency, the preferred size otherwise.
+    fn frames(&self, target_latency: Option<Duration>, sample_rate: usize) -> usize {
+        let target = match target_latency {
+            Some(latency) => (latency.as_secs_f64() * sample_rate as f64).ceil() as usize,
+            None => return self.preferred,
+        };
+        let frames = match self.granularity {
+            ffi::ASIO_GRANULARITY_POWER_OF_TWO => target.next_power_of_two(),
+            granularity if granularity > 0 => {
+                let granularity = granularity as usize;
+                self.min + target.saturating_sub(self.min).div_ceil(granularity) * granularity
+            }
+            // Fixed buffer size.
+            _ => self.preferred,
+        };
+        frames.clamp(self.min, self.max)
+    }
+}
+
+/// Loaded driver, released on drop.
+struct Driver(*mut IASIO);
+
+impl Driver {
+    unsafe fn load(clsid: &GUID) -> Result<Self> {
+        let mut raw = ptr::null_mut();
+        // Drivers use their class id as interface id.
+        let hr = CoCreateInstance(
+            clsid,
+            ptr::null_mut(),
+            CLSCTX_INPROC_SERVER,
+            clsid,
+            &mut raw,
+        );
+        if !winerror::SUCCEEDED(hr) || raw.is_null() {
+            return Err(api::Error::Internal {
+                cause: format!("failed to load driver: {:#x}", hr),
+            });
+        }
+
+        let driver = Driver(raw as *mut IASIO);
+        if (driver.vtbl().init)(driver.0, ptr::null_mut()) == ASIOFalse {
+            return Err(api::Error::Internal {
+                cause: format!("failed to initialize driver: {}", driver.error_message()),
+            });
+        }
+        Ok(driver)
+    }
+
+    unsafe fn vtbl(&self) -> &IASIOVtbl {
+        &*(*self.0).lpVtbl
+    }
+
+    unsafe fn error_message(&self) -> String {
+        let mut message = [0 as c_char; 128];
+        (self.vtbl().getErrorMessage)(self.0, message.as_mut_ptr());
+        CStr::from_ptr(message.as_ptr())
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    unsafe fn check(&self, err: ASIOError) -> Result<()> {
+        match err {
+            ffi::ASE_OK | ffi::ASE_SUCCESS => Ok(()),
+            _ => Err(api::Error::Internal {
+                cause: format!("{} ({})", self.error_message(), err),
+            }),
+        }
+    }
+
+    /// Number of input and output channels.
+    unsafe fn channels(&self) -> Result<(usize, usize)> {
+        let (mut inputs, mut outputs) = (0, 0);
+        self.check((self.vtbl().getChannels)(self.0, &mut inputs, &mut outputs))?;
+        Ok((inputs as _, outputs as _))
+    }
+
+    unsafe fn sample_type(&self, channel: usize, is_input: bool) -> Result<ASIOSampleType> {
+        let mut info: ASIOChannelInfo = mem::zeroed();
+        info.channel = channel as _;
+        info.isInput = is_input as _;
+        self.check((self.vtbl().getChannelInfo)(self.0, &mut info))?;
+        Ok(info.type_)
+    }
+
+    unsafe fn can_sample_rate(&self, sample_rate: usize) -> bool {
+        (self.vtbl().canSampleRate)(self.0, sample_rate as _) == ASE_OK
+    }
+
+    unsafe fn sample_rate(&self) -> Result<usize> {
+        let mut sample_rate = 0.0;
+        self.check((self.vtbl().getSampleRate)(self.0, &mut sample_rate))?;
+        Ok(sample_rate as _)
+    }
+
+    unsafe fn buffer_size(&self) -> Result<BufferSize> {
+        let (mut min, mut max, mut preferred, mut granularity) = (0, 0, 0, 0);
+        self.check((self.vtbl().getBufferSize)(
+            self.0,
+            &mut min,
+            &mut max,
+            &mut preferred,
+            &mut granularity,
+        ))?;
+        Ok(BufferSize {
+            min: min as _,
+            max: max as _,
+            preferred: preferred as _,
+            granularity,
+        })
+    }
+}
+
+impl Drop for Driver {
+    fn drop(&mut self) {
+        unsafe {
+            (*self.0).Release();
+        }
+    }
+}
+
+pub(crate) struct DriverInfo {
+    name: String,
+    clsid: GUID,
+}
+
+/// List the drivers registered under `HKEY_LOCAL_MACHINE\SOFTWARE\ASIO`.
+pub(crate) unsafe fn enumerate_drivers() -> Vec<DriverInfo> {
+    let mut key: HKEY = ptr::null_mut();
+    let path = wide("SOFTWARE\\ASIO");
+    if RegOpenKeyExW(HKEY_LOCAL_MACHINE, path.as_ptr(), 0, KEY_READ, &mut key) != 0 {
+        return Vec::new();
+    }
+
+    let clsid_value = wide("CLSID");
+    let mut drivers = Vec::new();
+    for index in 0.. {
+        let mut name = [0u16; 256];
+        let mut name_len = name.len() as u32;
+        let status = RegEnumKeyExW(
+            key,
+            index,
+            name.as_mut_ptr(),
+            &mut name_len,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        );
+        if status != 0 {
+            break;
+        }
+
+        let mut clsid_str = [0u16; 64];
+        let mut clsid_size = mem::size_of_val(&clsid_str) as u32;
+        let status = RegGetValueW(
+            key,
+            name.as_ptr(),
+            clsid_value.as_ptr(),
+            RRF_RT_REG_SZ,
+            ptr::null_mut(),
+            clsid_str.as_mut_ptr() as *mut c_void,
+            &mut clsid_size,
+        );
+        if status != 0 {
+            continue;
+        }
+        let mut clsid = mem::zeroed();
+        if !winerror::SUCCEEDED(CLSIDFromString(clsid_str.as_ptr(), &mut clsid)) {
+            continue;
+        }
+
+        drivers.push(DriverInfo {
+            name: String::from_utf16_lossy(&name[..name_len as usize]),
+            clsid,
+        });
+    }
+    RegCloseKey(key);
+
+    drivers
+}
+
+pub struct Instance {
+    drivers: HandleTable<DriverInfo>,
+    /// The single loaded driver, shared with the open device.
+    loaded: RefCell<Option<(api::PhysicalDevice, Rc<Driver>)>>,
+}
+
+impl Instance {
+    /// Load the driver of a physical device, replacing the currently loaded driver.
+    unsafe fn driver(&self, physical_device: api::PhysicalDevice) -> Result<Rc<Driver>> {
+        let mut loaded = self.loaded.borrow_mut();
+        if let Some((device, driver)) = &*loaded {
+            if *device == physical_device {
+                return Ok(driver.clone());
+            }
+            if Rc::strong_count(driver) > 1 {
+                return api::Error::validation(
+                    "Only a single ASIO driver can be loaded, the current driver is in use by a device",
+                );
+            }
+        }
+
+        let info = match self.drivers.get(physical_device) {
+            Some(info) => info,
+            None => return api::Error::validation("Invalid physical device handle"),
+        };
+        // Unload the previous driver first.
+        *loaded = None;
+        let driver = Rc::new(Driver::load(&info.clsid)?);
+        *loaded = Some((physical_device, driver.clone()));
+        Ok(driver)
+    }
+}
+
+impl api::Instance for Instance {
+    type Device = Device;
+    type Session = ();
+
+    unsafe fn properties() -> api::InstanceProperties {
+        api::InstanceProperties {
+            driver_id: api::DriverId::Asio,
+            stream_mode: api::StreamMode::Callback,
+            sharing: api::SharingModeFlags::EXCLUSIVE,
+            supports_duplex: true,
+            supports_spatial_audio: false,
+        }
+    }
+
+    unsafe fn create(_: &str) -> Self {
+        // Drivers expect to be loaded from a single threaded apartment.
+        CoInitializeEx(ptr::null_mut(), COINIT_APARTMENTTHREADED);
+
+        let mut drivers = HandleTable::default();
+        for driver in enumerate_drivers() {
+            drivers.insert(driver);
+        }
+        Instance {
+            drivers,
+            loaded: RefCell::new(None),
+        }
+    }
+
+    unsafe fn enumerate_physical_devices(&self) -> Vec<api::PhysicalDevice> {
+        self.drivers.iter().map(|(handle, _)| handle).collect()
+    }
+
+    /// ASIO has no default driver.
+    unsafe fn default_physical_input_device(&self) -> Option<api::PhysicalDevice> {
+        None
+    }
+
+    /// ASIO has no default driver.
+    unsafe fn default_physical_output_device(&self) -> Option<api::PhysicalDevice> {
+        None
+    }
+
+    unsafe fn physical_device_properties(
+        &self,
+        physical_device: api::PhysicalDevice,
+    ) -> Result<api::PhysicalDeviceProperties> {
+        let (inputs, outputs) = self.driver(physical_device)?.channels()?;
+        let mut streams = api::StreamFlags::empty();
+        if inputs > 0 {
+            streams |= api::StreamFlags::INPUT;
+        }
+        if outputs > 0 {
+            streams |= api::StreamFlags::OUTPUT;
+        }
+
+        Ok(api::PhysicalDeviceProperties {
+            device_name: self.drivers.get(physical_device).unwrap().name.clone(),
+            streams,
+            form_factor: api::FormFactor::Unknown,
+            offload_capable: false,
+            roles: api::DeviceRoleFlags::empty(),
+            adapter_id: String::new(),
+        })
+    }
+
+    unsafe fn physical_device_supports_format(
+        &self,
+        physical_device: api::PhysicalDevice,
+        sharing: api::SharingMode,
+        frame_desc: api::FrameDesc,
+    ) -> bool {
+        if sharing != api::SharingMode::Exclusive
+            || frame_desc.endianness != api::Endianness::Little
+        {
+            return false;
+        }
+        let driver = match self.driver(physical_device) {
+            Ok(driver) => driver,
+            Err(_) => return false,
+        };
+        let (inputs, outputs) = match driver.channels() {
+            Ok(channels) => channels,
+            Err(_) => return false,
+        };
+        if frame_desc.num_channels() > inputs.max(outputs) {
+            return false;
+        }
+
+        // Sample types are fixed by the driver.
+        let ty = driver.sample_type(0, outputs == 0);
+        driver.can_sample_rate(frame_desc.sample_rate)
+            && ty.ok().and_then(map_sample_type) == Some(frame_desc.format)
+    }
+
+    /// ASIO has no concurrent mode, reports the current format of the driver.
+    unsafe fn physical_device_default_concurrent_format(
+        &self,
+        physical_device: api::PhysicalDevice,
+    ) -> Result<api::FrameDesc> {
+        let driver = self.driver(physical_device)?;
+        let (inputs, outputs) = driver.channels()?;
+        let is_input = outputs == 0;
+        let ty = driver.sample_type(0, is_input)?;
+        let format = match map_sample_type(ty) {
+            Some(format) => format,
+            None => {
+                return Err(api::Error::Unsupported {
+                    description: format!("ASIO sample type {} isn't supported", ty),
+                })
+            }
+        };
+
+        Ok(api::FrameDesc {
+            format,
+            channels: api::ChannelMask::raw(if is_input { inputs } else { outputs }),
+            sample_rate: driver.sample_rate()?,
+            endianness: api::Endianness::Little,
+        })
+    }
+
+    unsafe fn create_device(
+        &self,
+        desc: api::DeviceDesc,
+        channels: api::Channels,
+        callback: api::StreamCallback,
+    ) -> Result<Device> {
+        if desc.sharing != api::SharingMode::Exclusive {
+            return api::Error::validation("ASIO devices only support exclusive sharing");
+        }
+        let num_inputs = channels.input.bits().count_ones() as usize;
+        let num_outputs = channels.output.bits().count_ones() as usize;
+        if num_inputs > 0 && num_outputs > 0 && channels.input != channels.output {
+            return api::Error::validation(
+                "Duplex devices require the same input and output channels",
+            );
+        }
+
+        let driver = self.driver(desc.physical_device)?;
+        let (max_inputs, max_outputs) = driver.channels()?;
+        if num_inputs > max_inputs || num_outputs > max_outputs {
+            return api::Error::validation(format!(
+                "Driver only supports {} input and {} output channels",
+                max_inputs, max_outputs
+            ));
+        }
+
+        let sample_rate = desc.sample_desc.sample_rate;
+        if !driver.can_sample_rate(sample_rate) {
+            return api::Error::validation(format!(
+                "Sample rate {} isn't supported by the driver",
+                sample_rate
+            ));
+        }
+        driver.check((driver.vtbl().setSampleRate)(driver.0, sample_rate as _))?;
+
+        let format = desc.sample_desc.format;
+        for &(is_input, count) in &[(true, num_inputs), (false, num_outputs)] {
+            for channel in 0..count {
+                if map_sample_type(driver.sample_type(channel, is_input)?) != Some(format) {
+                    return api::Error::validation(format!(
+                        "Sample format {:?} isn't supported by the driver",
+                        format
+                    ));
+                }
+            }
+        }
+
+        // Buffer sizes are fixed by the driver, the target latency selects within its limits.
+        let frames = driver
+            .buffer_size()?
+            .frames(desc.target_latency, sample_rate);
+        let buffer_info = |is_input: bool, channel: usize| ASIOBufferInfo {
+            isInput: if is_input { ASIOTrue } else { ASIOFalse },
+            channelNum: channel as _,
+            buffers: [ptr::null_mut(); 2],
+        };
+        let buffer_infos = (0..num_inputs)
+            .map(|channel| buffer_info(true, channel))
+            .chain((0..num_outputs).map(|channel| buffer_info(false, channel)))
+            .collect::<Vec<_>>();
+
+        let stream_channels = if num_inputs > 0 {
+            channels.input
+        } else {
+            channels.output
+        };
+        let properties = api::StreamProperties {
+            channels: stream_channels,
+            requested_channels: stream_channels,
+            device_channels: stream_channels,
+            sample_rate,
+            buffer_size_frames: frames,
+            valid_bits: format.bits_per_sample() as _,
+            timer_driven: false,
+            conversions: api::ConversionFlags::empty(),
+        };
+
+        // Scratch buffers are stored as `u32` for aligning the samples.
+        let scratch = |num_channels: usize| {
+            vec![0u32; (num_channels * frames * format.bytes_per_sample()).div_ceil(4)]
+        };
+        let stream = Box::into_raw(Box::new(Stream {
+            driver: driver.0,
+            buffer_infos,
+            num_inputs,
+            format,
+            frames,
+            input: scratch(num_inputs),
+            output: scratch(num_outputs),
+            callback,
+            properties,
+        }));
+        if STREAM
+            .compare_exchange(ptr::null_mut(), stream, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            drop(Box::from_raw(stream));
+            return api::Error::validation("Only a single ASIO device can be open at a time");
+        }
+
+        let err = (driver.vtbl().createBuffers)(
+            driver.0,
+            (*stream).buffer_infos.as_mut_ptr(),
+            (num_inputs + num_outputs) as _,
+            frames as _,
+            &CALLBACKS,
+        );
+        if let Err(err) = driver.check(err) {
+            STREAM.store(ptr::null_mut(), Ordering::Release);
+            drop(Box::from_raw(stream));
+            return Err(err);
+        }
+
+        Ok(Device {
+            driver,
+            stream,
+            properties,
+        })
+    }
+
+    unsafe fn create_session_with_affinity(
+        &self,
+        _sample_rate: usize,
+        thread_affinity: Option<api::CoreMask>,
+    ) -> Result<Self::Session> {
+        if thread_affinity.is_some() {
+            return api::Error::unsupported("Thread affinity isn't supported by the ASIO backend");
+        }
+        Ok(())
+    }
+
+    unsafe fn set_event_callback<F>(&mut self, _callback: Option<F>) -> Result<()>
+    where
+        F: FnMut(api::Event) + Send + 'static,
+    {
+        Err(api::Error::Unsupported {
+            description: "ASIO doesn't report device events".into(),
+        })
+    }
+}
+
+/// Stream state accessed by the driver callbacks.
+struct Stream {
+    driver: *mut IASIO,
+    /// Input channels first, followed by the output channels.
+    buffer_infos: Vec<ASIOBufferInfo>,
+    num_inputs: usize,
+    format: api::Format,
+    frames: usize,
+    /// Interleaved input samples passed to the stream callback.
+    input: Vec<u32>,
+    /// Interleaved output samples written by the stream callback.
+    output: Vec<u32>,
+    callback: api::StreamCallback,
+    properties: api::StreamProperties,
+}
+
+impl Stream {
+    /// Run the stream callback for one half of the driver double buffers.
+    ///
+    /// ASIO buffers aren't interleaved, samples are copied from and to the scratch buffers.
+    unsafe fn process(&mut self, index: usize) {
+        let bytes = self.format.bytes_per_sample();
+        let (inputs, outputs) = self.buffer_infos.split_at(self.num_inputs);
+
+        let input = slice::from_raw_parts_mut(
+            self.input.as_mut_ptr() as *mut u8,
+            inputs.len() * self.frames * bytes,
+        );
+        for (channel, info) in inputs.iter().enumerate() {
+            let src = slice::from_raw_parts(info.buffers[index] as *const u8, self.frames * bytes);
+            interleave(src, input, channel, inputs.len(), bytes);
+        }
+
+        let stream = api::Stream {
+            properties: self.properties,
+            buffers: api::StreamBuffers {
+                frames: self.frames,
+                input: if inputs.is_empty() {
+                    ptr::null()
+                } else {
+                    self.input.as_ptr() as _
+                },
+                output: if outputs.is_empty() {
+                    ptr::null_mut()
+                } else {
+                    self.output.as_mut_ptr() as _
+                },
+                gap_frames: 0,
+                capture_time: None,
+                silent: false,
+            },
+        };
+        let callback = &mut self.callback;
+        alloc_guard::guarded(|| callback(stream));
+
+        let output = slice::from_raw_parts(
+            self.output.as_ptr() as *const u8,
+            outputs.len() * self.frames * bytes,
+        );
+        for (channel, info) in outputs.iter().enumerate() {
+            let dst =
+                slice::from_raw_parts_mut(info.buffers[index] as *mut u8, self.frames * bytes);
+            deinterleave(output, dst, channel, outputs.len(), bytes);
+        }
+
+        // Optional, lowers the output latency for supporting drivers.
+        ((*(*self.driver).lpVtbl).outputReady)(self.driver);
+    }
+}
+
+unsafe extern "C" fn buffer_switch(index: c_long, _direct_process: ASIOBool) {
+    if let Some(stream) = STREAM.load(Ordering::Acquire).as_mut() {
+        stream.process(index as usize & 1);
+    }
+}
+
+unsafe extern "C" fn buffer_switch_time_info(
+    params: *mut c_void,
+    index: c_long,
+    direct_process: ASIOBool,
+) -> *mut c_void {
+    buffer_switch(index, direct_process);
+    params
+}
+
+unsafe extern "C" fn sample_rate_did_change(sample_rate: ASIOSampleRate) {
+    log::warn!("ASIO sample rate changed to {}", sample_rate);
+}
+
+unsafe extern "C" fn asio_message(
+    selector: c_long,
+    value: c_long,
+    _message: *mut c_void,
+    _opt: *mut f64,
+) -> c_long {
+    match selector {
+        ffi::kAsioSelectorSupported => matches!(
+            value,
+            ffi::kAsioEngineVersion
+                | ffi::kAsioResetRequest
+                | ffi::kAsioResyncRequest
+                | ffi::kAsioLatenciesChanged
+        ) as _,
+        ffi::kAsioEngineVersion => 2,
+        ffi::kAsioResetRequest => {
+            log::warn!("ASIO driver requested a reset, the device needs to be recreated");
+            1
+        }
+        ffi::kAsioResyncRequest | ffi::kAsioLatenciesChanged => 1,
+        // Buffer size changes require recreating the buffers.
+        ffi::kAsioBufferSizeChange => 0,
+        _ => 0,
+    }
+}
+
+pub struct Device {
+    driver: Rc<Driver>,
+    stream: *mut Stream,
+    properties: api::StreamProperties,
+}
+
+impl api::Device for Device {
+    unsafe fn start(&self) {
+        if let Err(err) = self.driver.check((self.driver.vtbl().start)(self.driver.0)) {
+            log::error!("failed to start ASIO device: {:?}", err);
+        }
+    }
+
+    unsafe fn stop(&self) {
+        (self.driver.vtbl().stop)(self.driver.0);
+    }
+
+    unsafe fn stream_properties(&self) -> api::StreamProperties {
+        self.properties
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        unsafe {
+            (self.driver.vtbl().stop)(self.driver.0);
+            (self.driver.vtbl().disposeBuffers)(self.driver.0);
+            // No callbacks are running after disposing the buffers.
+            STREAM.store(ptr::null_mut(), Ordering::Release);
+            drop(Box::from_raw(self.stream));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interleave_round_trip() {
+        let left = [1u8, 2, 3, 4];
+        let right = [5u8, 6, 7, 8];
+        let mut interleaved = [0u8; 8];
+        interleave(&left, &mut interleaved, 0, 2, 2);
+        interleave(&right, &mut interleaved, 1, 2, 2);
+        assert_eq!(interleaved, [1, 2, 5, 6, 3, 4, 7, 8]);
+
+        let mut channel = [0u8; 4];
+        deinterleave(&interleaved, &mut channel, 1, 2, 2);
+        assert_eq!(channel, right);
+    }
+
+    #[test]
+    fn buffer_size_selection() {
+        let power_of_two = BufferSize {
+            min: 64,
+            max: 2048,
+            preferred: 256,
+            granularity: ffi::ASIO_GRANULARITY_POWER_OF_TWO,
+        };
+        assert_eq!(power_of_two.frames(None, 48_000), 256);
+        assert_eq!(
+            power_of_two.frames(Some(Duration::from_millis(5)), 48_000),
+            256
+        );
+        assert_eq!(
+            power_of_two.frames(Some(Duration::from_secs(1)), 48_000),
+            2048
+        );
+
+        let linear = BufferSize {
+            granularity: 32,
+            ..power_of_two
+        };
+        assert_eq!(linear.frames(Some(Duration::from_millis(5)), 48_000), 256);
+        assert_eq!(linear.frames(Some(Duration::from_micros(100)), 48_000), 64);
+
+        let fixed = BufferSize {
+            min: 512,
+            max: 512,
+            preferred: 512,
+            granularity: 0,
+        };
+        assert_eq!(fixed.frames(Some(Duration::from_millis(1)), 48_000), 512);
+    }
+
+    #[test]
+    fn sample_types() {
+        assert_eq!(
+            map_sample_type(ffi::ASIOSTFloat32LSB),
+            Some(api::Format::F32)
+        );
+        assert_eq!(map_sample_type(ffi::ASIOSTInt32LSB), Some(api::Format::I32));
+        // `ASIOSTInt32LSB24` stores the valid bits in the least significant bits.
+        assert_eq!(map_sample_type(27), None);
+    }
+}
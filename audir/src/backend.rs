@@ -0,0 +1,86 @@
+//! Runtime selection of the compiled in backends.
+
+use crate::api::{self, DriverId, Result};
+
+/// Instance of one of the compiled in backends, see `create_instance`.
+pub enum BackendInstance {
+    #[cfg(all(windows, feature = "wasapi"))]
+    Wasapi(crate::wasapi::Instance),
+    #[cfg(all(windows, target_pointer_width = "64", feature = "asio"))]
+    Asio(crate::asio::Instance),
+    #[cfg(all(target_os = "linux", feature = "pulse"))]
+    PulseAudio(crate::pulse::Instance),
+    #[cfg(all(target_os = "android", feature = "opensles"))]
+    OpenSLES(crate::opensles::Instance),
+    #[cfg(all(target_os = "android", feature = "aaudio"))]
+    AAudio(crate::aaudio::Instance),
+    #[cfg(feature = "null")]
+    Null(crate::null::Instance),
+}
+
+/// List the backends compiled into the crate for the current target.
+///
+/// Backends are ordered by preference, platform backends before the null backend.
+/// ASIO is only listed if at least one driver is installed, availability of the other
+/// audio services isn't checked at runtime.
+pub fn available_backends() -> Vec<DriverId> {
+    #[allow(unused_mut)]
+    let mut backends = vec![
+        #[cfg(all(windows, feature = "wasapi"))]
+        DriverId::Wasapi,
+        #[cfg(all(windows, target_pointer_width = "64", feature = "asio"))]
+        DriverId::Asio,
+        #[cfg(all(target_os = "linux", feature = "pulse"))]
+        DriverId::PulseAudio,
+        #[cfg(all(target_os = "android", feature = "aaudio"))]
+        DriverId::AAudio,
+        #[cfg(all(target_os = "android", feature = "opensles"))]
+        DriverId::OpenSLES,
+        #[cfg(feature = "null")]
+        DriverId::Null,
+    ];
+    // Only reads the driver registry, no driver is loaded.
+    #[cfg(all(windows, target_pointer_width = "64", feature = "asio"))]
+    if unsafe { crate::asio::enumerate_drivers() }.is_empty() {
+        backends.retain(|&driver| driver != DriverId::Asio);
+    }
+    backends
+}
+
+/// Create an instance of the requested backend.
+///
+/// Returns `Error::Unsupported` if the backend isn't compiled into the crate
+/// (see `available_backends`).
+///
+/// # Safety
+///
+/// Same requirements as `Instance::create` of the backend, the instance **must** outlive
+/// all its child objects.
+#[allow(unused_variables)]
+pub unsafe fn create_instance(driver: DriverId, name: &str) -> Result<BackendInstance> {
+    match driver {
+        #[cfg(all(windows, feature = "wasapi"))]
+        DriverId::Wasapi => Ok(BackendInstance::Wasapi(api::Instance::create(name))),
+        #[cfg(all(windows, target_pointer_width = "64", feature = "asio"))]
+        DriverId::Asio => Ok(BackendInstance::Asio(api::Instance::create(name))),
+        #[cfg(all(target_os = "linux", feature = "pulse"))]
+        DriverId::PulseAudio => Ok(BackendInstance::PulseAudio(api::Instance::create(name))),
+        #[cfg(all(target_os = "android", feature = "opensles"))]
+        DriverId::OpenSLES => Ok(BackendInstance::OpenSLES(api::Instance::create(name))),
+        #[cfg(all(target_os = "android", feature = "aaudio"))]
+        DriverId::AAudio => Ok(BackendInstance::AAudio(api::Instance::create(name))),
+        #[cfg(feature = "null")]
+        DriverId::Null => Ok(BackendInstance::Null(api::Instance::create(name))),
+        #[cfg(any(
+            all(target_os = "linux", any(feature = "alsa", feature = "jack")),
+            all(target_os = "macos", feature = "coreaudio")
+        ))]
+        DriverId::Alsa | DriverId::Jack | DriverId::CoreAudio => Err(api::Error::Unsupported {
+            description: format!("Backend {:?} isn't implemented yet", driver),
+        }),
+        #[allow(unreachable_patterns)]
+        _ => Err(api::Error::Unsupported {
+            description: format!("Backend {:?} isn't compiled in", driver),
+        }),
+    }
+}
@@ -0,0 +1,325 @@
+//! Stream callback adapters.
+
+use crate::api::{ChannelMask, Format, Frames, Stream, StreamCallback};
+use std::{ptr, slice};
+
+/// Fill level of the output stream buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillLevel {
+    /// Number of queued frames dropped below the low-water mark.
+    Low,
+    /// Number of queued frames is between the low- and high-water mark.
+    Normal,
+    /// Number of queued frames rose above the high-water mark.
+    High,
+}
+
+/// Output stream callback adapter monitoring the buffer fill level.
+///
+/// The fill level is derived from the number of frames still queued in the stream buffer
+/// when the callback gets invoked (buffer size minus requested frames).
+/// Producers with varying latency (e.g network streams) can adjust their read-ahead
+/// based on the signaled fill level changes.
+#[derive(Debug, Clone)]
+pub struct AdaptiveFeeder {
+    low_water: Frames,
+    high_water: Frames,
+    level: FillLevel,
+}
+
+impl AdaptiveFeeder {
+    /// Create a new feeder with the low- and high-water mark in frames.
+    pub fn new(low_water: Frames, high_water: Frames) -> Self {
+        assert!(low_water <= high_water);
+        AdaptiveFeeder {
+            low_water,
+            high_water,
+            level: FillLevel::Normal,
+        }
+    }
+
+    /// Current fill level.
+    pub fn level(&self) -> FillLevel {
+        self.level
+    }
+
+    /// Update the fill level with the number of queued frames.
+    ///
+    /// Returns the new fill level if it changed.
+    pub fn update(&mut self, queued: Frames) -> Option<FillLevel> {
+        let level = if queued < self.low_water {
+            FillLevel::Low
+        } else if queued > self.high_water {
+            FillLevel::High
+        } else {
+            FillLevel::Normal
+        };
+
+        if level != self.level {
+            self.level = level;
+            Some(level)
+        } else {
+            None
+        }
+    }
+
+    /// Wrap an output stream callback.
+    ///
+    /// `signal` will be called before the stream callback whenever the fill level changes.
+    pub fn wrap<F, S>(mut self, mut callback: F, mut signal: S) -> StreamCallback
+    where
+        F: FnMut(Stream) + Send + 'static,
+        S: FnMut(FillLevel) + Send + 'static,
+    {
+        Box::new(move |stream: Stream| {
+            let queued = stream
+                .properties
+                .buffer_size_frames
+                .saturating_sub(stream.buffers.frames);
+            if let Some(level) = self.update(queued) {
+                signal(level);
+            }
+            callback(stream);
+        })
+    }
+}
+
+/// Wrap a stream callback routing logical channels to physical channels.
+///
+/// The wrapped callback receives buffers with one unassigned channel per `map` entry.
+/// Input samples are gathered from the mapped physical channels, output samples are
+/// scattered to the mapped physical channels and remaining physical channels are silenced.
+/// `format` **must** match the sample format of the stream.
+pub fn map_channels(
+    mut callback: StreamCallback,
+    map: Vec<usize>,
+    format: Format,
+) -> StreamCallback {
+    let bytes_per_sample = format.bytes_per_sample();
+    // Stored as `u32` for aligning the samples.
+    let mut input_scratch = Vec::<u32>::new();
+    let mut output_scratch = Vec::<u32>::new();
+
+    Box::new(move |mut stream: Stream| {
+        let frames = stream.buffers.frames;
+        let physical = stream.properties.num_channels();
+        let logical = map.len();
+        let len = (frames * logical * bytes_per_sample).div_ceil(4);
+
+        let input = stream.buffers.input as *const u8;
+        if !input.is_null() {
+            input_scratch.resize(len, 0);
+            let scratch = input_scratch.as_mut_ptr() as *mut u8;
+            for frame in 0..frames {
+                for (channel, &mapped) in map.iter().enumerate() {
+                    unsafe {
+                        ptr::copy_nonoverlapping(
+                            input.add((frame * physical + mapped) * bytes_per_sample),
+                            scratch.add((frame * logical + channel) * bytes_per_sample),
+                            bytes_per_sample,
+                        );
+                    }
+                }
+            }
+            stream.buffers.input = input_scratch.as_ptr() as _;
+        }
+
+        let output = stream.buffers.output as *mut u8;
+        if !output.is_null() {
+            output_scratch.clear();
+            output_scratch.resize(len, 0);
+            stream.buffers.output = output_scratch.as_mut_ptr() as _;
+        }
+
+        stream.properties.channels = ChannelMask::raw(logical);
+        callback(stream);
+
+        if !output.is_null() {
+            let scratch = output_scratch.as_ptr() as *const u8;
+            unsafe {
+                ptr::write_bytes(output, 0, frames * physical * bytes_per_sample);
+                for frame in 0..frames {
+                    for (channel, &mapped) in map.iter().enumerate() {
+                        ptr::copy_nonoverlapping(
+                            scratch.add((frame * logical + channel) * bytes_per_sample),
+                            output.add((frame * physical + mapped) * bytes_per_sample),
+                            bytes_per_sample,
+                        );
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Wrap a stream callback rendering stereo frames into a mono output stream.
+///
+/// The wrapped callback receives stereo output buffers (`FRONT_LEFT | FRONT_RIGHT`).
+/// Both channels are attenuated by -3dB, summed and saturated to the sample range.
+/// Only `F32` and `I16` samples are supported, other formats output silence.
+///
+/// The stereo scratch buffer is preallocated for `buffer_size_frames` frames
+/// (see `StreamProperties::buffer_size_frames`), larger buffers reallocate in the callback.
+pub fn downmix_stereo(
+    mut callback: StreamCallback,
+    format: Format,
+    buffer_size_frames: Frames,
+) -> StreamCallback {
+    // Stored as `u32` for aligning the samples.
+    let scratch_len = move |frames: Frames| (2 * frames * format.bytes_per_sample()).div_ceil(4);
+    let mut scratch = Vec::<u32>::with_capacity(scratch_len(buffer_size_frames));
+
+    Box::new(move |mut stream: Stream| {
+        let frames = stream.buffers.frames;
+        let output = stream.buffers.output as *mut u8;
+        if output.is_null() {
+            callback(stream);
+            return;
+        }
+
+        scratch.clear();
+        scratch.resize(scratch_len(frames), 0);
+        stream.buffers.output = scratch.as_mut_ptr() as _;
+        stream.properties.channels = ChannelMask::FRONT_LEFT | ChannelMask::FRONT_RIGHT;
+        callback(stream);
+
+        let gain = std::f32::consts::FRAC_1_SQRT_2;
+        unsafe {
+            match format {
+                Format::F32 => {
+                    let src = slice::from_raw_parts(scratch.as_ptr() as *const f32, 2 * frames);
+                    let dst = slice::from_raw_parts_mut(output as *mut f32, frames);
+                    for (dst, src) in dst.iter_mut().zip(src.chunks_exact(2)) {
+                        *dst = ((src[0] + src[1]) * gain).clamp(-1.0, 1.0);
+                    }
+                }
+                Format::I16 => {
+                    let src = slice::from_raw_parts(scratch.as_ptr() as *const i16, 2 * frames);
+                    let dst = slice::from_raw_parts_mut(output as *mut i16, frames);
+                    for (dst, src) in dst.iter_mut().zip(src.chunks_exact(2)) {
+                        // Float to int casts saturate.
+                        *dst = ((src[0] as f32 + src[1] as f32) * gain) as i16;
+                    }
+                }
+                _ => ptr::write_bytes(output, 0, frames * format.bytes_per_sample()),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{ConversionFlags, StreamBuffers, StreamProperties};
+
+    fn properties(channels: ChannelMask, format: Format, frames: Frames) -> StreamProperties {
+        StreamProperties {
+            channels,
+            requested_channels: channels,
+            device_channels: channels,
+            sample_rate: 48_000,
+            buffer_size_frames: frames,
+            valid_bits: format.bits_per_sample() as _,
+            timer_driven: false,
+            conversions: ConversionFlags::empty(),
+        }
+    }
+
+    /// Render `stereo` through the downmix into a mono buffer.
+    fn downmix<T: Copy + Default + Send + 'static>(format: Format, stereo: Vec<[T; 2]>) -> Vec<T> {
+        let frames = stereo.len();
+        let mut callback = downmix_stereo(
+            Box::new(move |mut stream: Stream| unsafe {
+                assert_eq!(
+                    stream.properties.channels,
+                    ChannelMask::FRONT_LEFT | ChannelMask::FRONT_RIGHT
+                );
+                stream.output_frames::<T, 2>().copy_from_slice(&stereo);
+            }),
+            format,
+            frames,
+        );
+
+        let mut mono = vec![T::default(); frames];
+        callback(Stream {
+            properties: properties(ChannelMask::FRONT_CENTER, format, frames),
+            buffers: StreamBuffers {
+                frames,
+                input: ptr::null(),
+                output: mono.as_mut_ptr() as _,
+                gap_frames: 0,
+                capture_time: None,
+                silent: false,
+            },
+        });
+        mono
+    }
+
+    #[test]
+    fn feeder_signals_level_changes() {
+        let mut feeder = AdaptiveFeeder::new(256, 768);
+        assert_eq!(feeder.level(), FillLevel::Normal);
+
+        // Water marks belong to the normal range.
+        assert_eq!(feeder.update(256), None);
+        assert_eq!(feeder.update(768), None);
+
+        assert_eq!(feeder.update(255), Some(FillLevel::Low));
+        assert_eq!(feeder.update(0), None);
+        assert_eq!(feeder.level(), FillLevel::Low);
+
+        // Rising from low to high skips the normal level.
+        assert_eq!(feeder.update(769), Some(FillLevel::High));
+        assert_eq!(feeder.update(1024), None);
+        assert_eq!(feeder.update(512), Some(FillLevel::Normal));
+        assert_eq!(feeder.level(), FillLevel::Normal);
+    }
+
+    #[test]
+    fn feeder_derives_queued_frames_from_the_requested_frames() {
+        use std::sync::{Arc, Mutex};
+
+        let levels = Arc::new(Mutex::new(Vec::new()));
+        let signaled = levels.clone();
+        let mut callback = AdaptiveFeeder::new(256, 768)
+            .wrap(|_| (), move |level| signaled.lock().unwrap().push(level));
+
+        // Frames requested by the callback are free, the rest of the buffer is queued.
+        for &frames in &[100, 900, 1024, 1000] {
+            callback(Stream {
+                properties: properties(ChannelMask::FRONT_CENTER, Format::F32, 1024),
+                buffers: StreamBuffers {
+                    frames,
+                    input: ptr::null(),
+                    output: ptr::null_mut(),
+                    gap_frames: 0,
+                    capture_time: None,
+                    silent: false,
+                },
+            });
+        }
+        assert_eq!(*levels.lock().unwrap(), [FillLevel::High, FillLevel::Low]);
+    }
+
+    #[test]
+    fn downmix_attenuates_by_3db() {
+        let mono = downmix::<f32>(Format::F32, vec![[0.5, 0.5], [0.5, -0.5], [1.0, 0.0]]);
+        let gain = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((mono[0] - gain).abs() < 1e-6);
+        assert_eq!(mono[1], 0.0);
+        assert!((mono[2] - gain).abs() < 1e-6);
+
+        // Correlated full scale channels exceed the sample range.
+        let mono = downmix::<f32>(Format::F32, vec![[1.0, 1.0], [-1.0, -1.0]]);
+        assert_eq!(mono, [1.0, -1.0]);
+    }
+
+    #[test]
+    fn downmix_saturates_i16() {
+        let mono = downmix::<i16>(
+            Format::I16,
+            vec![[1000, 1000], [30_000, 30_000], [-30_000, -30_000]],
+        );
+        assert_eq!(mono, [1414, i16::MAX, i16::MIN]);
+    }
+}
@@ -1,45 +1,87 @@
 #![allow(unused)]
 
-use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
 
 pub type RawHandle = u64;
 
-#[repr(transparent)]
-#[derive(Debug, PartialEq, Eq)]
-pub struct Handle<T>(*mut T);
+/// Table of reference counted objects addressed by raw handles.
+///
+/// Objects are never removed, raw handles stay valid for the lifetime of the table.
+/// Unknown raw handles (e.g from another table) are rejected on lookup instead of
+/// being dereferenced. The raw handle `0` is never valid.
+#[derive(Debug)]
+pub struct HandleTable<T> {
+    objects: Vec<Arc<T>>,
+}
+
+impl<T> Default for HandleTable<T> {
+    fn default() -> Self {
+        HandleTable {
+            objects: Vec::new(),
+        }
+    }
+}
 
-impl<T> Handle<T> {
-    pub fn new(v: T) -> Self {
-        let handle = Box::new(v);
-        Handle(Box::into_raw(handle))
+impl<T> HandleTable<T> {
+    /// Insert a new object and return its raw handle.
+    pub fn insert(&mut self, v: T) -> RawHandle {
+        self.objects.push(Arc::new(v));
+        self.objects.len() as _
     }
 
-    pub fn raw(self) -> RawHandle {
-        self.0 as _
+    /// Look up the object of a raw handle.
+    ///
+    /// The returned object stays alive even if the table gets dropped.
+    pub fn get(&self, handle: RawHandle) -> Option<Arc<T>> {
+        let index = (handle as usize).checked_sub(1)?;
+        self.objects.get(index).cloned()
     }
 
-    pub fn from_raw(handle: RawHandle) -> Self {
-        Handle(handle as _)
+    /// Iterate over all objects together with their raw handles.
+    pub fn iter(&self) -> impl Iterator<Item = (RawHandle, &Arc<T>)> {
+        self.objects
+            .iter()
+            .enumerate()
+            .map(|(i, object)| (i as RawHandle + 1, object))
     }
 }
 
-impl<T> Copy for Handle<T> {}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl<T> Clone for Handle<T> {
-    fn clone(&self) -> Self {
-        Handle(self.0)
+    #[test]
+    fn handles_stay_valid() {
+        let mut table = HandleTable::default();
+        let a = table.insert(1);
+        let b = table.insert(2);
+
+        assert_eq!(table.get(a).as_deref(), Some(&1));
+        assert_eq!(table.get(b).as_deref(), Some(&2));
+        assert_eq!(
+            table.iter().map(|(handle, _)| handle).collect::<Vec<_>>(),
+            [a, b]
+        );
     }
-}
 
-impl<T> Deref for Handle<T> {
-    type Target = T;
-    fn deref(&self) -> &Self::Target {
-        unsafe { &*self.0 }
+    #[test]
+    fn dangling_handles_are_rejected() {
+        let mut table = HandleTable::default();
+        let handle = table.insert(String::from("device"));
+
+        assert!(table.get(0).is_none());
+        assert!(table.get(handle + 1).is_none());
+        assert!(table.get(!0).is_none());
+        // Pointer-like values from other tables don't alias any object.
+        assert!(table.get(&table as *const _ as RawHandle).is_none());
     }
-}
 
-impl<T> DerefMut for Handle<T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { &mut *self.0 }
+    #[test]
+    fn objects_outlive_the_table() {
+        let mut table = HandleTable::default();
+        let handle = table.insert(String::from("device"));
+        let object = table.get(handle).unwrap();
+        drop(table);
+        assert_eq!(*object, "device");
     }
 }
@@ -1,18 +1,33 @@
-#[cfg(windows)]
+// Unsafe functions document their requirements in `## Validation` sections, which are the
+// safety contract of the API: violating a validation requirement is undefined behavior
+// unless the function states that an error is returned instead.
+#![allow(clippy::missing_safety_doc)]
+
+#[cfg(all(windows, feature = "wasapi"))]
 pub mod wasapi;
 
-#[cfg(target_os = "linux")]
+#[cfg(all(windows, target_pointer_width = "64", feature = "asio"))]
+pub mod asio;
+
+#[cfg(all(target_os = "linux", feature = "pulse"))]
 pub mod pulse;
 
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "opensles"))]
 pub mod opensles;
 
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "aaudio"))]
 pub mod aaudio;
 
+#[cfg(feature = "null")]
 pub mod null;
 
+mod alloc_guard;
 pub(crate) mod api;
+mod backend;
+pub mod convert;
 mod handle;
 
+#[cfg(feature = "alloc-guard")]
+pub use crate::alloc_guard::AllocGuard;
 pub use crate::api::*;
+pub use crate::backend::*;
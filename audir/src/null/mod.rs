@@ -1,7 +1,39 @@
 use crate::{api, api::Result};
+use std::{
+    cell::Cell,
+    ptr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 const NULL_DEVICE: api::PhysicalDevice = 0;
-pub struct Instance;
+
+/// Virtual clock in nanoseconds.
+type Clock = Arc<AtomicU64>;
+
+/// Null instance.
+///
+/// Time doesn't advance on its own, the virtual clock is driven by `advance`
+/// for deterministic testing of time dependent logic.
+pub struct Instance {
+    clock: Clock,
+}
+
+impl Instance {
+    /// Move the virtual clock forward.
+    pub fn advance(&self, duration: Duration) {
+        self.clock
+            .fetch_add(duration.as_nanos() as u64, Ordering::AcqRel);
+    }
+
+    /// Time elapsed on the virtual clock since instance creation.
+    pub fn now(&self) -> Duration {
+        Duration::from_nanos(self.clock.load(Ordering::Acquire))
+    }
+}
 
 impl api::Instance for Instance {
     type Device = Device;
@@ -12,11 +44,15 @@ impl api::Instance for Instance {
             driver_id: api::DriverId::Null,
             stream_mode: api::StreamMode::Callback,
             sharing: api::SharingModeFlags::all(),
+            supports_duplex: false,
+            supports_spatial_audio: false,
         }
     }
 
     unsafe fn create(_: &str) -> Self {
-        Instance
+        Instance {
+            clock: Arc::new(AtomicU64::new(0)),
+        }
     }
 
     unsafe fn enumerate_physical_devices(&self) -> Vec<api::PhysicalDevice> {
@@ -39,6 +75,9 @@ impl api::Instance for Instance {
             device_name: "null".into(),
             streams: api::StreamFlags::all(),
             form_factor: api::FormFactor::Unknown,
+            offload_capable: false,
+            adapter_id: String::new(),
+            roles: api::DeviceRoleFlags::empty(),
         })
     }
 
@@ -59,19 +98,38 @@ impl api::Instance for Instance {
             format: api::Format::F32,
             sample_rate: 0,
             channels: api::ChannelMask::empty(),
+            endianness: api::Endianness::NATIVE,
         })
     }
 
     unsafe fn create_device(
         &self,
-        _: api::DeviceDesc,
-        _: api::Channels,
-        _: api::StreamCallback,
+        desc: api::DeviceDesc,
+        channels: api::Channels,
+        callback: api::StreamCallback,
     ) -> Result<Self::Device> {
-        Ok(Device)
+        Ok(Device {
+            clock: self.clock.clone(),
+            sample_rate: desc.sample_desc.sample_rate,
+            format: desc.sample_desc.format,
+            channels,
+            started: Cell::new(None),
+            elapsed: Cell::new(0),
+            submitted: 0,
+            input: Vec::new(),
+            output: Vec::new(),
+            callback,
+        })
     }
 
-    unsafe fn create_session(&self, _sample_rate: usize) -> Result<Self::Session> {
+    unsafe fn create_session_with_affinity(
+        &self,
+        _sample_rate: usize,
+        thread_affinity: Option<api::CoreMask>,
+    ) -> Result<Self::Session> {
+        if thread_affinity.is_some() {
+            return api::Error::unsupported("Thread affinity isn't supported by the null backend");
+        }
         Ok(())
     }
 
@@ -83,22 +141,118 @@ impl api::Instance for Instance {
     }
 }
 
-pub struct Device;
+/// Null device.
+///
+/// `submit_buffers` invokes the stream callback with the frames processed on the
+/// virtual clock since the last submission. Input buffers are silent, output is discarded.
+pub struct Device {
+    clock: Clock,
+    sample_rate: usize,
+    format: api::Format,
+    channels: api::Channels,
+    /// Virtual time the device has been started at.
+    started: Cell<Option<u64>>,
+    /// Virtual time the device has been running before the last start.
+    elapsed: Cell<u64>,
+    /// Position up to which frames have been passed to the callback.
+    submitted: api::Frames,
+    /// Scratch buffers, `u64` for sample alignment.
+    input: Vec<u64>,
+    output: Vec<u64>,
+    callback: api::StreamCallback,
+}
+
+impl Device {
+    /// Number of frames processed while the device was running.
+    ///
+    /// Derived from the virtual clock of the instance and the requested sample rate.
+    pub fn position(&self) -> api::Frames {
+        let elapsed = self.running_time().as_nanos();
+        (elapsed * self.sample_rate as u128 / 1_000_000_000) as _
+    }
+
+    /// Replace the stream callback.
+    ///
+    /// The new callback is invoked starting with the next `submit_buffers` call.
+    pub fn set_callback(&mut self, callback: api::StreamCallback) {
+        self.callback = callback;
+    }
+
+    /// Virtual time the device has been running.
+    pub fn running_time(&self) -> Duration {
+        let now = self.clock.load(Ordering::Acquire);
+        let running = self.started.get().map_or(0, |started| now - started);
+        Duration::from_nanos(self.elapsed.get() + running)
+    }
+}
 
 impl api::Device for Device {
-    unsafe fn start(&self) {}
+    unsafe fn start(&self) {
+        if self.started.get().is_none() {
+            self.started.set(Some(self.clock.load(Ordering::Acquire)));
+        }
+    }
 
-    unsafe fn stop(&self) {}
+    unsafe fn stop(&self) {
+        if let Some(started) = self.started.take() {
+            let now = self.clock.load(Ordering::Acquire);
+            self.elapsed.set(self.elapsed.get() + now - started);
+        }
+    }
 
     unsafe fn stream_properties(&self) -> api::StreamProperties {
+        let channels = self.channels.input | self.channels.output;
         api::StreamProperties {
-            channels: api::ChannelMask::empty(),
-            sample_rate: 0,
-            buffer_size: 0,
+            channels,
+            requested_channels: channels,
+            device_channels: channels,
+            sample_rate: self.sample_rate,
+            buffer_size_frames: 0,
+            valid_bits: 0,
+            timer_driven: false,
+            conversions: api::ConversionFlags::empty(),
         }
     }
 
-    unsafe fn submit_buffers(&mut self, _: u32) -> api::Result<()> {
+    unsafe fn submit_buffers(&mut self, _: Option<Duration>) -> api::Result<()> {
+        let position = self.position();
+        let frames = position - self.submitted;
+        if frames == 0 {
+            return Ok(());
+        }
+
+        let properties = self.stream_properties();
+        let bytes_per_sample = self.format.bytes_per_sample();
+        let buffer_len = move |channels: api::ChannelMask| {
+            let num_channels = channels.bits().count_ones() as usize;
+            (frames * num_channels * bytes_per_sample).div_ceil(8)
+        };
+        self.input.clear();
+        self.input.resize(buffer_len(self.channels.input), 0);
+        self.output.resize(buffer_len(self.channels.output), 0);
+
+        let buffers = api::StreamBuffers {
+            frames,
+            input: if self.channels.input.is_empty() {
+                ptr::null()
+            } else {
+                self.input.as_ptr() as _
+            },
+            output: if self.channels.output.is_empty() {
+                ptr::null_mut()
+            } else {
+                self.output.as_mut_ptr() as _
+            },
+            gap_frames: 0,
+            capture_time: None,
+            silent: false,
+        };
+        (self.callback)(api::Stream {
+            properties,
+            buffers,
+        });
+        self.submitted = position;
+
         Ok(())
     }
 }
@@ -1,4 +1,4 @@
-use crate::{api, api::Result};
+use crate::{alloc_guard, api, api::Result};
 use audir_sles as sles;
 use std::os::raw::c_void;
 use std::ptr;
@@ -40,6 +40,8 @@ impl api::Instance for Instance {
             driver_id: api::DriverId::OpenSLES,
             stream_mode: api::StreamMode::Callback,
             sharing: api::SharingModeFlags::CONCURRENT,
+            supports_duplex: false,
+            supports_spatial_audio: false,
         }
     }
 
@@ -87,6 +89,9 @@ impl api::Instance for Instance {
             device_name: "default".into(),
             streams: api::StreamFlags::INPUT | api::StreamFlags::OUTPUT,
             form_factor: api::FormFactor::Unknown,
+            offload_capable: false,
+            adapter_id: String::new(),
+            roles: api::DeviceRoleFlags::empty(),
         })
     }
 
@@ -178,7 +183,7 @@ impl api::Instance for Instance {
 
                 create_player(&mut format_source as *mut _ as _);
             }
-            api::Format::U32 => {
+            api::Format::I32 => {
                 let mut format_source = sles::SLDataFormat_PCM {
                     formatType: sles::SL_DATAFORMAT_PCM as _,
                     numChannels: num_channels as _,
@@ -211,19 +216,20 @@ impl api::Instance for Instance {
             &mut state as *mut _ as _,
         );
 
+        let silence = match desc.sample_desc.format.silence_byte() {
+            Some(byte) => u32::from_ne_bytes([byte; 4]),
+            // Buffers are stored as `u32`, matching the unsigned sample size.
+            None => 0x8000_0000,
+        };
         let buffers = (0..BUFFER_CHAIN_SIZE)
-            .map(|_| {
-                let buffer_size = num_channels as usize * BUFFER_NUM_FRAMES;
-                let mut buffer = Vec::<u32>::with_capacity(buffer_size);
-                buffer.set_len(buffer_size);
-                buffer
-            })
+            .map(|_| vec![silence; num_channels as usize * BUFFER_NUM_FRAMES])
             .collect();
 
         let frame_desc = api::FrameDesc {
             format: desc.sample_desc.format,
             channels: channels.output,
             sample_rate: desc.sample_desc.sample_rate,
+            endianness: api::Endianness::Little,
         };
 
         let data = Box::new(CallbackData {
@@ -243,18 +249,26 @@ impl api::Instance for Instance {
                 let stream = api::Stream {
                     properties: api::StreamProperties {
                         channels: data.frame_desc.channels,
+                        requested_channels: data.frame_desc.channels,
+                        device_channels: data.frame_desc.channels,
                         sample_rate: data.frame_desc.sample_rate,
-                        buffer_size: BUFFER_NUM_FRAMES,
+                        buffer_size_frames: BUFFER_NUM_FRAMES,
+                        valid_bits: data.frame_desc.format.bits_per_sample() as _,
+                        timer_driven: false,
+                        conversions: api::ConversionFlags::empty(),
                     },
                     buffers: api::StreamBuffers {
                         output: buffer.as_mut_ptr() as _,
                         input: ptr::null(),
                         frames: buffer.len()
                             / data.frame_desc.channels.bits().count_ones() as usize,
+                        gap_frames: 0,
+                        capture_time: None,
+                        silent: false,
                     },
                 };
 
-                (data.callback)(stream); // TODO: sizeof u32
+                alloc_guard::guarded(|| (data.callback)(stream)); // TODO: sizeof u32
                 ((**queue).Enqueue).unwrap()(
                     queue,
                     buffer.as_mut_ptr() as _,
@@ -279,7 +293,16 @@ impl api::Instance for Instance {
         })
     }
 
-    unsafe fn create_session(&self, _: usize) -> Result<()> {
+    unsafe fn create_session_with_affinity(
+        &self,
+        _sample_rate: usize,
+        thread_affinity: Option<api::CoreMask>,
+    ) -> Result<Self::Session> {
+        if thread_affinity.is_some() {
+            return api::Error::unsupported(
+                "Thread affinity isn't supported by the OpenSLES backend",
+            );
+        }
         Ok(())
     }
 
@@ -317,8 +340,13 @@ impl api::Device for Device {
     unsafe fn stream_properties(&self) -> api::StreamProperties {
         api::StreamProperties {
             channels: self.frame_desc.channels,
+            requested_channels: self.frame_desc.channels,
+            device_channels: self.frame_desc.channels,
             sample_rate: self.frame_desc.sample_rate,
-            buffer_size: BUFFER_NUM_FRAMES,
+            buffer_size_frames: BUFFER_NUM_FRAMES,
+            valid_bits: self.frame_desc.format.bits_per_sample() as _,
+            timer_driven: false,
+            conversions: api::ConversionFlags::empty(),
         }
     }
 }
@@ -1,9 +1,27 @@
-use crate::{api, api::Result, handle::Handle};
+use crate::{alloc_guard, api, api::Result, handle::HandleTable};
 use libpulse_sys as pulse;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::ffi::c_void;
 use std::ffi::CStr;
+use std::mem;
 use std::ptr;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub struct Session {
+    /// Thread affinity before creating the session.
+    prev_affinity: Option<libc::cpu_set_t>,
+}
+
+impl std::ops::Drop for Session {
+    fn drop(&mut self) {
+        if let Some(affinity) = self.prev_affinity.take() {
+            unsafe {
+                libc::sched_setaffinity(0, mem::size_of_val(&affinity), &affinity);
+            }
+        }
+    }
+}
 
 struct PhysicalDevice {
     device_name: String,
@@ -12,13 +30,20 @@ struct PhysicalDevice {
     channels: api::ChannelMask,
 }
 
-type PhysicalDeviceMap = HashMap<String, Handle<PhysicalDevice>>;
+/// Physical devices by name, collected during enumeration.
+type PhysicalDeviceMap = BTreeMap<String, PhysicalDevice>;
 
 impl PhysicalDevice {
     fn default_format(&self) -> Result<api::FrameDesc> {
-        let format = match self.sample_spec.format {
-            pulse::pa_sample_format_t::F32le => api::Format::F32,
-            pulse::pa_sample_format_t::S16le => api::Format::I16,
+        let (format, endianness) = match self.sample_spec.format {
+            pulse::pa_sample_format_t::F32le => (api::Format::F32, api::Endianness::Little),
+            pulse::pa_sample_format_t::F32be => (api::Format::F32, api::Endianness::Big),
+            pulse::pa_sample_format_t::S16le => (api::Format::I16, api::Endianness::Little),
+            pulse::pa_sample_format_t::S16be => (api::Format::I16, api::Endianness::Big),
+            pulse::pa_sample_format_t::S24le => (api::Format::I24, api::Endianness::Little),
+            pulse::pa_sample_format_t::S24be => (api::Format::I24, api::Endianness::Big),
+            pulse::pa_sample_format_t::S32le => (api::Format::I32, api::Endianness::Little),
+            pulse::pa_sample_format_t::S32be => (api::Format::I32, api::Endianness::Big),
             format => {
                 return Err(api::Error::Internal {
                     cause: format!("unhandled format: {:?}", format),
@@ -30,20 +55,96 @@ impl PhysicalDevice {
             format,
             channels: self.channels,
             sample_rate: self.sample_spec.rate as _,
+            endianness,
         })
     }
 }
 
+/// Map a channel map to a channel mask.
+///
+/// Positions without channel mask counterpart (e.g aux or top channels) are mapped to
+/// channels without speaker assignment, see `ChannelMask::raw`.
 fn map_channels(channel_map: &pulse::pa_channel_map) -> api::ChannelMask {
     let mut channels = api::ChannelMask::empty();
+    let mut num_unassigned = 0;
     for i in 0..channel_map.channels {
         channels |= match channel_map.map[i as usize] {
             pulse::PA_CHANNEL_POSITION_FRONT_LEFT => api::ChannelMask::FRONT_LEFT,
             pulse::PA_CHANNEL_POSITION_FRONT_RIGHT => api::ChannelMask::FRONT_RIGHT,
-            pos => panic!("unsupported {:?}", pos),
+            pulse::PA_CHANNEL_POSITION_FRONT_CENTER => api::ChannelMask::FRONT_CENTER,
+            pulse::PA_CHANNEL_POSITION_LFE => api::ChannelMask::LOW_FREQUENCY,
+            pulse::PA_CHANNEL_POSITION_REAR_LEFT => api::ChannelMask::BACK_LEFT,
+            pulse::PA_CHANNEL_POSITION_REAR_RIGHT => api::ChannelMask::BACK_RIGHT,
+            pulse::PA_CHANNEL_POSITION_FRONT_LEFT_OF_CENTER => {
+                api::ChannelMask::FRONT_LEFT_OF_CENTER
+            }
+            pulse::PA_CHANNEL_POSITION_FRONT_RIGHT_OF_CENTER => {
+                api::ChannelMask::FRONT_RIGHT_OF_CENTER
+            }
+            pulse::PA_CHANNEL_POSITION_REAR_CENTER => api::ChannelMask::BACK_CENTER,
+            pulse::PA_CHANNEL_POSITION_SIDE_LEFT => api::ChannelMask::SIDE_LEFT,
+            pulse::PA_CHANNEL_POSITION_SIDE_RIGHT => api::ChannelMask::SIDE_RIGHT,
+            _ => {
+                num_unassigned += 1;
+                api::ChannelMask::empty()
+            }
         };
     }
-    channels
+    channels | api::ChannelMask::raw(num_unassigned)
+}
+
+/// Speaker positions in channel mask bit order.
+const SPEAKER_POSITIONS: [pulse::pa_channel_position_t; 11] = [
+    pulse::PA_CHANNEL_POSITION_FRONT_LEFT,
+    pulse::PA_CHANNEL_POSITION_FRONT_RIGHT,
+    pulse::PA_CHANNEL_POSITION_FRONT_CENTER,
+    pulse::PA_CHANNEL_POSITION_LFE,
+    pulse::PA_CHANNEL_POSITION_REAR_LEFT,
+    pulse::PA_CHANNEL_POSITION_REAR_RIGHT,
+    pulse::PA_CHANNEL_POSITION_FRONT_LEFT_OF_CENTER,
+    pulse::PA_CHANNEL_POSITION_FRONT_RIGHT_OF_CENTER,
+    pulse::PA_CHANNEL_POSITION_REAR_CENTER,
+    pulse::PA_CHANNEL_POSITION_SIDE_LEFT,
+    pulse::PA_CHANNEL_POSITION_SIDE_RIGHT,
+];
+
+/// Positions for channels without speaker assignment.
+const AUX_POSITIONS: [pulse::pa_channel_position_t; api::ChannelMask::MAX_UNASSIGNED] = [
+    pulse::PA_CHANNEL_POSITION_AUX0,
+    pulse::PA_CHANNEL_POSITION_AUX1,
+    pulse::PA_CHANNEL_POSITION_AUX2,
+    pulse::PA_CHANNEL_POSITION_AUX3,
+    pulse::PA_CHANNEL_POSITION_AUX4,
+    pulse::PA_CHANNEL_POSITION_AUX5,
+    pulse::PA_CHANNEL_POSITION_AUX6,
+    pulse::PA_CHANNEL_POSITION_AUX7,
+    pulse::PA_CHANNEL_POSITION_AUX8,
+    pulse::PA_CHANNEL_POSITION_AUX9,
+    pulse::PA_CHANNEL_POSITION_AUX10,
+    pulse::PA_CHANNEL_POSITION_AUX11,
+    pulse::PA_CHANNEL_POSITION_AUX12,
+    pulse::PA_CHANNEL_POSITION_AUX13,
+    pulse::PA_CHANNEL_POSITION_AUX14,
+    pulse::PA_CHANNEL_POSITION_AUX15,
+];
+
+/// Map a channel mask to a channel map, inverse of `map_channels`.
+///
+/// Positions follow the canonical interleaved order, unassigned channels are
+/// mapped to aux positions.
+fn map_channel_mask(channels: api::ChannelMask) -> pulse::pa_channel_map {
+    let mut channel_map = pulse::pa_channel_map::default();
+    for (i, &position) in SPEAKER_POSITIONS.iter().enumerate() {
+        if channels.bits() & (1 << i) != 0 {
+            channel_map.map[channel_map.channels as usize] = position;
+            channel_map.channels += 1;
+        }
+    }
+    for &position in &AUX_POSITIONS[..channels.num_unassigned()] {
+        channel_map.map[channel_map.channels as usize] = position;
+        channel_map.channels += 1;
+    }
+    channel_map
 }
 
 extern "C" fn sink_info_cb(
@@ -72,13 +173,11 @@ extern "C" fn sink_info_cb(
 
             device.streams |= api::StreamFlags::OUTPUT;
         })
-        .or_insert_with(|| {
-            Handle::new(PhysicalDevice {
-                device_name,
-                streams: api::StreamFlags::OUTPUT,
-                sample_spec: info.sample_spec,
-                channels: map_channels(&info.channel_map),
-            })
+        .or_insert_with(|| PhysicalDevice {
+            device_name,
+            streams: api::StreamFlags::OUTPUT,
+            sample_spec: info.sample_spec,
+            channels: map_channels(&info.channel_map),
         });
 }
 
@@ -112,39 +211,44 @@ extern "C" fn source_info_cb(
 
             device.streams |= api::StreamFlags::INPUT;
         })
-        .or_insert_with(|| {
-            Handle::new(PhysicalDevice {
-                device_name,
-                streams: api::StreamFlags::INPUT,
-                sample_spec: info.sample_spec,
-                channels: map_channels(&info.channel_map),
-            })
+        .or_insert_with(|| PhysicalDevice {
+            device_name,
+            streams: api::StreamFlags::INPUT,
+            sample_spec: info.sample_spec,
+            channels: map_channels(&info.channel_map),
         });
 }
 
-fn map_format(format: api::Format) -> pulse::pa_sample_format_t {
+fn map_format(format: api::Format) -> Result<pulse::pa_sample_format_t> {
     match format {
-        api::Format::I16 => pulse::pa_sample_format_t::S16le,
-        api::Format::F32 => pulse::pa_sample_format_t::F32le,
-        _ => unimplemented!(),
+        api::Format::I16 => Ok(pulse::pa_sample_format_t::S16le),
+        api::Format::F32 => Ok(pulse::pa_sample_format_t::F32le),
+        api::Format::I24 => Ok(pulse::pa_sample_format_t::S24le),
+        api::Format::I32 => Ok(pulse::pa_sample_format_t::S32le),
+        api::Format::U32 => Err(api::Error::Unsupported {
+            description: "unsigned 32 bit samples aren't supported by PulseAudio".into(),
+        }),
     }
 }
 
 pub struct Instance {
     mainloop: *mut pulse::pa_mainloop,
     context: *mut pulse::pa_context,
-    physical_devices: PhysicalDeviceMap,
+    physical_devices: HandleTable<PhysicalDevice>,
+    physical_device_ids: BTreeMap<String, api::PhysicalDevice>,
 }
 
 impl api::Instance for Instance {
     type Device = Device;
-    type Session = (); // TODO
+    type Session = Session;
 
     unsafe fn properties() -> api::InstanceProperties {
         api::InstanceProperties {
             driver_id: api::DriverId::PulseAudio,
             stream_mode: api::StreamMode::Polling,
             sharing: api::SharingModeFlags::CONCURRENT,
+            supports_duplex: false,
+            supports_spatial_audio: false,
         }
     }
 
@@ -181,44 +285,48 @@ impl api::Instance for Instance {
         );
         Self::await_operation(mainloop, operation);
 
+        let mut table = HandleTable::default();
+        let physical_device_ids = physical_devices
+            .into_iter()
+            .map(|(name, device)| (name, table.insert(device)))
+            .collect();
+
         Instance {
             mainloop,
             context,
-            physical_devices,
+            physical_devices: table,
+            physical_device_ids,
         }
     }
 
     unsafe fn enumerate_physical_devices(&self) -> Vec<api::PhysicalDevice> {
         self.physical_devices
-            .values()
-            .map(|device| device.raw())
+            .iter()
+            .map(|(handle, _)| handle)
             .collect()
     }
 
     unsafe fn default_physical_input_device(&self) -> Option<api::PhysicalDevice> {
-        self.physical_devices
-            .get("default")
-            .filter(|device| device.streams.contains(api::StreamFlags::INPUT))
-            .map(|device| device.raw())
+        self.default_physical_device(api::StreamFlags::INPUT)
     }
 
     unsafe fn default_physical_output_device(&self) -> Option<api::PhysicalDevice> {
-        self.physical_devices
-            .get("default")
-            .filter(|device| device.streams.contains(api::StreamFlags::OUTPUT))
-            .map(|device| device.raw())
+        self.default_physical_device(api::StreamFlags::OUTPUT)
     }
 
     unsafe fn physical_device_properties(
         &self,
         physical_device: api::PhysicalDevice,
     ) -> Result<api::PhysicalDeviceProperties> {
-        let physical_device = Handle::<PhysicalDevice>::from_raw(physical_device);
+        let physical_device = self.physical_device(physical_device)?;
 
         Ok(api::PhysicalDeviceProperties {
             device_name: physical_device.device_name.clone(),
             streams: physical_device.streams,
             form_factor: api::FormFactor::Unknown, // TODO?
+            offload_capable: false,
+            adapter_id: String::new(),
+            roles: api::DeviceRoleFlags::empty(),
         })
     }
 
@@ -226,7 +334,7 @@ impl api::Instance for Instance {
         &self,
         _physical_device: api::PhysicalDevice,
         sharing: api::SharingMode,
-        _frame_desc: api::FrameDesc,
+        frame_desc: api::FrameDesc,
     ) -> bool {
         if sharing == api::SharingMode::Exclusive {
             // concurrent only
@@ -234,15 +342,14 @@ impl api::Instance for Instance {
         }
 
         // TODO: supporting everything?
-        return true;
+        map_format(frame_desc.format).is_ok()
     }
 
     unsafe fn physical_device_default_concurrent_format(
         &self,
         physical_device: api::PhysicalDevice,
     ) -> Result<api::FrameDesc> {
-        let physical_device = Handle::<PhysicalDevice>::from_raw(physical_device);
-        physical_device.default_format()
+        self.physical_device(physical_device)?.default_format()
     }
 
     unsafe fn create_device(
@@ -253,22 +360,32 @@ impl api::Instance for Instance {
     ) -> Result<Self::Device> {
         let stream = if !channels.output.is_empty() {
             let spec = pulse::pa_sample_spec {
-                format: map_format(desc.sample_desc.format),
+                format: map_format(desc.sample_desc.format)?,
                 channels: channels.output.bits().count_ones() as _,
                 rate: desc.sample_desc.sample_rate as _,
             };
 
+            let channel_map = map_channel_mask(channels.output);
             let stream = dbg!(pulse::pa_stream_new(
                 self.context,
                 b"audir\0".as_ptr() as _,
                 &spec,
-                ptr::null()
-            )); // TODO: name, channel map
+                &channel_map
+            )); // TODO: name
 
             // TODO
+            let tlength = match desc.target_latency {
+                Some(latency) => pulse::pa_usec_to_bytes(latency.as_micros() as _, &spec) as u32,
+                None => !0,
+            };
+            let flags = if desc.target_latency.is_some() {
+                pulse::PA_STREAM_ADJUST_LATENCY
+            } else {
+                0
+            };
             let attribs = pulse::pa_buffer_attr {
                 maxlength: !0,
-                tlength: !0,
+                tlength,
                 prebuf: !0,
                 minreq: !0,
                 fragsize: !0,
@@ -278,7 +395,7 @@ impl api::Instance for Instance {
                 stream,
                 ptr::null(),
                 &attribs,
-                0,
+                flags,
                 ptr::null(),
                 ptr::null_mut(),
             ));
@@ -299,17 +416,58 @@ impl api::Instance for Instance {
         let sample_spec = &*pulse::pa_stream_get_sample_spec(stream);
         let frame_size = pulse::pa_frame_size(sample_spec);
 
-        Ok(Device {
+        let mut device = Device {
+            acquire_timeout: api::INFINITE_TIMEOUT_MS,
             mainloop: self.mainloop,
             stream,
             cur_buffer: ptr::null_mut(),
             frame_size,
             callback,
-        })
+        };
+        device.acquire_timeout = desc.acquire_timeout_ms(&api::Device::stream_properties(&device));
+
+        Ok(device)
     }
 
-    unsafe fn create_session(&self, _sample_rate: usize) -> Result<Self::Session> {
-        Ok(())
+    unsafe fn create_session_with_affinity(
+        &self,
+        sample_rate: usize,
+        thread_affinity: Option<api::CoreMask>,
+    ) -> Result<Session> {
+        if sample_rate == api::DEFAULT_SAMPLE_RATE {
+            return api::Error::validation(
+                "Default sample rate can't be used for session creation",
+            );
+        }
+
+        let mut session = Session {
+            prev_affinity: None,
+        };
+        if let Some(mask) = thread_affinity {
+            let mut prev = mem::zeroed::<libc::cpu_set_t>();
+            if libc::sched_getaffinity(0, mem::size_of_val(&prev), &mut prev) != 0 {
+                return Err(api::Error::Internal {
+                    cause: format!(
+                        "failed to query the thread affinity: {}",
+                        std::io::Error::last_os_error()
+                    ),
+                });
+            }
+
+            let mut set = mem::zeroed::<libc::cpu_set_t>();
+            for cpu in (0..64).filter(|cpu| mask & (1 << cpu) != 0) {
+                libc::CPU_SET(cpu, &mut set);
+            }
+            if libc::sched_setaffinity(0, mem::size_of_val(&set), &set) != 0 {
+                return api::Error::validation(format!(
+                    "Thread affinity {:#x} doesn't contain any processor of the system",
+                    mask
+                ));
+            }
+            session.prev_affinity = Some(prev);
+        }
+
+        Ok(session)
     }
 
     unsafe fn set_event_callback<F>(&mut self, _callback: Option<F>) -> Result<()>
@@ -321,6 +479,22 @@ impl api::Instance for Instance {
 }
 
 impl Instance {
+    /// Look up a physical device, rejecting unknown handles.
+    fn physical_device(&self, handle: api::PhysicalDevice) -> Result<Arc<PhysicalDevice>> {
+        match self.physical_devices.get(handle) {
+            Some(device) => Ok(device),
+            None => api::Error::validation("Invalid physical device handle"),
+        }
+    }
+
+    fn default_physical_device(&self, streams: api::StreamFlags) -> Option<api::PhysicalDevice> {
+        let handle = *self.physical_device_ids.get("default")?;
+        self.physical_devices
+            .get(handle)
+            .filter(|device| device.streams.contains(streams))
+            .map(|_| handle)
+    }
+
     unsafe fn await_operation(
         mainloop: *mut pulse::pa_mainloop,
         operation: *mut pulse::pa_operation,
@@ -337,6 +511,7 @@ impl Instance {
 }
 
 pub struct Device {
+    acquire_timeout: u32,
     mainloop: *mut pulse::pa_mainloop,
     stream: *mut pulse::pa_stream,
     cur_buffer: *mut c_void,
@@ -345,6 +520,13 @@ pub struct Device {
 }
 
 impl Device {
+    /// Replace the stream callback.
+    ///
+    /// The new callback is invoked starting with the next `submit_buffers` call.
+    pub fn set_callback(&mut self, callback: api::StreamCallback) {
+        self.callback = callback;
+    }
+
     unsafe fn acquire_buffers(&mut self, timeout_ms: u32) -> Result<api::StreamBuffers> {
         let mut size = loop {
             let size = pulse::pa_stream_writable_size(self.stream);
@@ -364,6 +546,9 @@ impl Device {
             input: ptr::null(),
             output: data as _,
             frames: (size / self.frame_size) as _,
+            gap_frames: 0,
+            capture_time: None,
+            silent: false,
         })
     }
 
@@ -396,20 +581,54 @@ impl api::Device for Device {
         let sample_spec = &*pulse::pa_stream_get_sample_spec(stream);
         let channel_map = &*pulse::pa_stream_get_channel_map(stream);
 
+        let channels = map_channels(channel_map);
         api::StreamProperties {
-            channels: map_channels(channel_map),
+            channels,
+            requested_channels: channels,
+            device_channels: channels,
             sample_rate: sample_spec.rate as _,
-            buffer_size: buffer_attrs.minreq as _,
+            buffer_size_frames: buffer_attrs.minreq as _,
+            valid_bits: 8 * pulse::pa_sample_size(sample_spec) as u16,
+            timer_driven: false,
+            conversions: api::ConversionFlags::empty(),
         }
     }
 
-    unsafe fn submit_buffers(&mut self, timeout_ms: u32) -> Result<()> {
+    unsafe fn submit_buffers(&mut self, timeout: Option<Duration>) -> Result<()> {
+        let timeout_ms = timeout.map_or(self.acquire_timeout, api::timeout_ms);
         let buffers = self.acquire_buffers(timeout_ms)?;
         let properties = self.stream_properties();
-        (self.callback)(api::Stream {
-            properties,
-            buffers,
+        let callback = &mut self.callback;
+        alloc_guard::guarded(|| {
+            callback(api::Stream {
+                properties,
+                buffers,
+            })
         });
         self.release_buffers(buffers.frames)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_masks_round_trip() {
+        let surround = api::ChannelMask::FRONT_LEFT
+            | api::ChannelMask::FRONT_RIGHT
+            | api::ChannelMask::FRONT_CENTER
+            | api::ChannelMask::LOW_FREQUENCY
+            | api::ChannelMask::SIDE_LEFT
+            | api::ChannelMask::SIDE_RIGHT;
+        let channel_map = map_channel_mask(surround | api::ChannelMask::raw(2));
+        assert_eq!(channel_map.channels, 8);
+        assert_eq!(channel_map.map[3], pulse::PA_CHANNEL_POSITION_LFE);
+        assert_eq!(channel_map.map[4], pulse::PA_CHANNEL_POSITION_SIDE_LEFT);
+        assert_eq!(channel_map.map[7], pulse::PA_CHANNEL_POSITION_AUX1);
+        assert_eq!(
+            map_channels(&channel_map),
+            surround | api::ChannelMask::raw(2)
+        );
+    }
+}
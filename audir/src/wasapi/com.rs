@@ -1,4 +1,5 @@
 use super::WasapiResult;
+use crate::api;
 use std::{
     fmt,
     hash::{Hash, Hasher},
@@ -6,8 +7,9 @@ use std::{
     ptr,
 };
 use winapi::ctypes::c_void;
-use winapi::shared::guiddef;
-use winapi::um::unknwnbase::IUnknown;
+use winapi::shared::winerror::HRESULT;
+use winapi::shared::{guiddef, winerror};
+use winapi::um::{audioclient::*, unknwnbase::IUnknown};
 use winapi::Interface;
 
 #[repr(transparent)]
@@ -57,6 +59,13 @@ impl<T: Interface> WeakPtr<T> {
         (obj, hr)
     }
 
+    // Adds a new reference to the live object, which needs to be
+    // released by an explicit destroy call.
+    pub unsafe fn add_ref(self) -> Self {
+        self.as_unknown().AddRef();
+        self
+    }
+
     // Destroying one instance of the WeakPtr will invalidate all
     // copies and clones.
     pub unsafe fn destroy(self) {
@@ -116,3 +125,440 @@ impl PartialEq for Guid {
 }
 
 impl Eq for Guid {}
+
+/// Convert from the `u128` representation, most significant byte first
+/// (`0x00112233_4455_6677_8899_AABBCCDDEEFF` maps to `{00112233-4455-6677-8899-AABBCCDDEEFF}`).
+impl From<u128> for Guid {
+    fn from(value: u128) -> Self {
+        Guid(guiddef::GUID {
+            Data1: (value >> 96) as _,
+            Data2: (value >> 80) as _,
+            Data3: (value >> 64) as _,
+            Data4: (value as u64).to_be_bytes(),
+        })
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let guid = &self.0;
+        write!(
+            f,
+            "{{{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}}}",
+            guid.Data1,
+            guid.Data2,
+            guid.Data3,
+            guid.Data4[0],
+            guid.Data4[1],
+            guid.Data4[2],
+            guid.Data4[3],
+            guid.Data4[4],
+            guid.Data4[5],
+            guid.Data4[6],
+            guid.Data4[7],
+        )
+    }
+}
+
+/// Typed audio client and COM errors.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WasapiError {
+    NotInitialized,
+    AlreadyInitialized,
+    WrongEndpointType,
+    DeviceInvalidated,
+    NotStopped,
+    BufferTooLarge,
+    OutOfOrder,
+    UnsupportedFormat,
+    InvalidSize,
+    DeviceInUse,
+    BufferOperationPending,
+    ThreadNotRegistered,
+    ExclusiveModeNotAllowed,
+    EndpointCreateFailed,
+    ServiceNotRunning,
+    EventHandleNotExpected,
+    ExclusiveModeOnly,
+    BufferDurationPeriodNotEqual,
+    EventHandleNotSet,
+    IncorrectBufferSize,
+    BufferSizeError,
+    CpuUsageExceeded,
+    BufferError,
+    BufferSizeNotAligned,
+    InvalidDevicePeriod,
+    InvalidStreamFlag,
+    EndpointOffloadNotCapable,
+    OutOfOffloadResources,
+    OffloadModeOnly,
+    NonOffloadModeOnly,
+    ResourcesInvalidated,
+    RawModeUnsupported,
+    NotImplemented,
+    OutOfMemory,
+    InvalidArg,
+    NoInterface,
+    Pointer,
+    Fail,
+    AccessDenied,
+    /// Unknown error code.
+    Other(HRESULT),
+}
+
+impl WasapiError {
+    /// Interpret an `HRESULT`, success codes are mapped to `Ok`.
+    pub fn from_hr(hr: HRESULT) -> Result<(), WasapiError> {
+        if winerror::SUCCEEDED(hr) {
+            return Ok(());
+        }
+
+        Err(match hr {
+            AUDCLNT_E_NOT_INITIALIZED => WasapiError::NotInitialized,
+            AUDCLNT_E_ALREADY_INITIALIZED => WasapiError::AlreadyInitialized,
+            AUDCLNT_E_WRONG_ENDPOINT_TYPE => WasapiError::WrongEndpointType,
+            AUDCLNT_E_DEVICE_INVALIDATED => WasapiError::DeviceInvalidated,
+            AUDCLNT_E_NOT_STOPPED => WasapiError::NotStopped,
+            AUDCLNT_E_BUFFER_TOO_LARGE => WasapiError::BufferTooLarge,
+            AUDCLNT_E_OUT_OF_ORDER => WasapiError::OutOfOrder,
+            AUDCLNT_E_UNSUPPORTED_FORMAT => WasapiError::UnsupportedFormat,
+            AUDCLNT_E_INVALID_SIZE => WasapiError::InvalidSize,
+            AUDCLNT_E_DEVICE_IN_USE => WasapiError::DeviceInUse,
+            AUDCLNT_E_BUFFER_OPERATION_PENDING => WasapiError::BufferOperationPending,
+            AUDCLNT_E_THREAD_NOT_REGISTERED => WasapiError::ThreadNotRegistered,
+            AUDCLNT_E_EXCLUSIVE_MODE_NOT_ALLOWED => WasapiError::ExclusiveModeNotAllowed,
+            AUDCLNT_E_ENDPOINT_CREATE_FAILED => WasapiError::EndpointCreateFailed,
+            AUDCLNT_E_SERVICE_NOT_RUNNING => WasapiError::ServiceNotRunning,
+            AUDCLNT_E_EVENTHANDLE_NOT_EXPECTED => WasapiError::EventHandleNotExpected,
+            AUDCLNT_E_EXCLUSIVE_MODE_ONLY => WasapiError::ExclusiveModeOnly,
+            AUDCLNT_E_BUFDURATION_PERIOD_NOT_EQUAL => WasapiError::BufferDurationPeriodNotEqual,
+            AUDCLNT_E_EVENTHANDLE_NOT_SET => WasapiError::EventHandleNotSet,
+            AUDCLNT_E_INCORRECT_BUFFER_SIZE => WasapiError::IncorrectBufferSize,
+            AUDCLNT_E_BUFFER_SIZE_ERROR => WasapiError::BufferSizeError,
+            AUDCLNT_E_CPUUSAGE_EXCEEDED => WasapiError::CpuUsageExceeded,
+            AUDCLNT_E_BUFFER_ERROR => WasapiError::BufferError,
+            AUDCLNT_E_BUFFER_SIZE_NOT_ALIGNED => WasapiError::BufferSizeNotAligned,
+            AUDCLNT_E_INVALID_DEVICE_PERIOD => WasapiError::InvalidDevicePeriod,
+            AUDCLNT_E_INVALID_STREAM_FLAG => WasapiError::InvalidStreamFlag,
+            AUDCLNT_E_ENDPOINT_OFFLOAD_NOT_CAPABLE => WasapiError::EndpointOffloadNotCapable,
+            AUDCLNT_E_OUT_OF_OFFLOAD_RESOURCES => WasapiError::OutOfOffloadResources,
+            AUDCLNT_E_OFFLOAD_MODE_ONLY => WasapiError::OffloadModeOnly,
+            AUDCLNT_E_NONOFFLOAD_MODE_ONLY => WasapiError::NonOffloadModeOnly,
+            AUDCLNT_E_RESOURCES_INVALIDATED => WasapiError::ResourcesInvalidated,
+            AUDCLNT_E_RAW_MODE_UNSUPPORTED => WasapiError::RawModeUnsupported,
+            winerror::E_NOTIMPL => WasapiError::NotImplemented,
+            winerror::E_OUTOFMEMORY => WasapiError::OutOfMemory,
+            winerror::E_INVALIDARG => WasapiError::InvalidArg,
+            winerror::E_NOINTERFACE => WasapiError::NoInterface,
+            winerror::E_POINTER => WasapiError::Pointer,
+            winerror::E_FAIL => WasapiError::Fail,
+            winerror::E_ACCESSDENIED => WasapiError::AccessDenied,
+            _ => WasapiError::Other(hr),
+        })
+    }
+}
+
+impl From<WasapiError> for api::Error {
+    fn from(err: WasapiError) -> Self {
+        match err {
+            WasapiError::DeviceInvalidated
+            | WasapiError::ResourcesInvalidated
+            | WasapiError::ServiceNotRunning => api::Error::DeviceLost,
+            WasapiError::Other(hr) => api::Error::Internal {
+                cause: format!("{:#x}", hr),
+            },
+            err => api::Error::Internal {
+                cause: format!("{:?}", err),
+            },
+        }
+    }
+}
+
+/// COM objects counting their references for checking release paths.
+#[cfg(test)]
+pub mod mock {
+    use super::WeakPtr;
+    use std::collections::VecDeque;
+    use winapi::ctypes::c_void;
+    use winapi::shared::{
+        guiddef::{GUID, LPCGUID, REFIID},
+        minwindef::{BYTE, DWORD, ULONG},
+        mmreg::WAVEFORMATEX,
+        winerror,
+    };
+    use winapi::um::audioclient::{
+        IAudioCaptureClient, IAudioCaptureClientVtbl, IAudioClient, IAudioClientVtbl,
+    };
+    use winapi::um::audiosessiontypes::AUDCLNT_SHAREMODE;
+    use winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
+    use winapi::um::winnt::{HANDLE, HRESULT};
+
+    #[repr(C)]
+    pub struct MockUnknown {
+        vtbl: *const IUnknownVtbl,
+        pub refcount: i32,
+        pub releases: u32,
+    }
+
+    static VTBL: IUnknownVtbl = IUnknownVtbl {
+        QueryInterface: query_interface,
+        AddRef: add_ref,
+        Release: release,
+    };
+
+    impl MockUnknown {
+        /// Create a new object holding a single reference.
+        pub fn new() -> Box<Self> {
+            Box::new(MockUnknown {
+                vtbl: &VTBL,
+                refcount: 1,
+                releases: 0,
+            })
+        }
+
+        pub fn as_ptr<T>(&mut self) -> WeakPtr<T> {
+            unsafe { WeakPtr::from_raw(self as *mut _ as *mut T) }
+        }
+    }
+
+    unsafe extern "system" fn query_interface(
+        this: *mut IUnknown,
+        _riid: REFIID,
+        object: *mut *mut c_void,
+    ) -> winerror::HRESULT {
+        add_ref(this);
+        *object = this as _;
+        winerror::S_OK
+    }
+
+    unsafe extern "system" fn add_ref(this: *mut IUnknown) -> ULONG {
+        let this = &mut *(this as *mut MockUnknown);
+        this.refcount += 1;
+        this.refcount as _
+    }
+
+    // The object isn't freed, releasing below zero shows up in the refcount.
+    unsafe extern "system" fn release(this: *mut IUnknown) -> ULONG {
+        let this = &mut *(this as *mut MockUnknown);
+        this.refcount -= 1;
+        this.releases += 1;
+        this.refcount as _
+    }
+
+    /// Audio client recording the session of `Initialize`, other methods aren't implemented.
+    ///
+    /// Shares the layout prefix and reference counting of `MockUnknown`.
+    #[repr(C)]
+    pub struct MockAudioClient {
+        vtbl: *const IAudioClientVtbl,
+        pub refcount: i32,
+        pub releases: u32,
+        /// Session passed to `Initialize`, `None` if not initialized or without session.
+        pub session_guid: Option<GUID>,
+    }
+
+    static CLIENT_VTBL: IAudioClientVtbl = IAudioClientVtbl {
+        parent: IUnknownVtbl {
+            QueryInterface: query_interface,
+            AddRef: add_ref,
+            Release: release,
+        },
+        Initialize: client_initialize,
+        GetBufferSize: client_get_buffer_size,
+        GetStreamLatency: client_get_stream_latency,
+        GetCurrentPadding: client_get_current_padding,
+        IsFormatSupported: client_is_format_supported,
+        GetMixFormat: client_get_mix_format,
+        GetDevicePeriod: client_get_device_period,
+        Start: client_start,
+        Stop: client_stop,
+        Reset: client_reset,
+        SetEventHandle: client_set_event_handle,
+        GetService: client_get_service,
+    };
+
+    macro_rules! not_implemented {
+        ($($name:ident($($arg:ty),*);)*) => {
+            $(
+                unsafe extern "system" fn $name(_: *mut IAudioClient, $(_: $arg),*) -> HRESULT {
+                    winerror::E_NOTIMPL
+                }
+            )*
+        };
+    }
+
+    not_implemented! {
+        client_get_buffer_size(*mut u32);
+        client_get_stream_latency(*mut i64);
+        client_get_current_padding(*mut u32);
+        client_is_format_supported(AUDCLNT_SHAREMODE, *const WAVEFORMATEX, *mut *mut WAVEFORMATEX);
+        client_get_mix_format(*mut *mut WAVEFORMATEX);
+        client_get_device_period(*mut i64, *mut i64);
+        client_start();
+        client_stop();
+        client_reset();
+        client_set_event_handle(HANDLE);
+        client_get_service(REFIID, *mut *mut c_void);
+    }
+
+    impl MockAudioClient {
+        /// Create a new object holding a single reference.
+        pub fn new() -> Box<Self> {
+            Box::new(MockAudioClient {
+                vtbl: &CLIENT_VTBL,
+                refcount: 1,
+                releases: 0,
+                session_guid: None,
+            })
+        }
+
+        pub fn as_ptr(&mut self) -> WeakPtr<IAudioClient> {
+            unsafe { WeakPtr::from_raw(self as *mut _ as *mut _) }
+        }
+    }
+
+    unsafe extern "system" fn client_initialize(
+        this: *mut IAudioClient,
+        _share_mode: AUDCLNT_SHAREMODE,
+        _stream_flags: DWORD,
+        _buffer_duration: i64,
+        _periodicity: i64,
+        _format: *const WAVEFORMATEX,
+        session_guid: LPCGUID,
+    ) -> HRESULT {
+        let this = &mut *(this as *mut MockAudioClient);
+        this.session_guid = session_guid.as_ref().copied();
+        winerror::S_OK
+    }
+
+    /// Captured packet returned by `MockCaptureClient`.
+    pub struct MockPacket {
+        pub data: Vec<u8>,
+        pub frames: u32,
+        pub flags: DWORD,
+        pub qpc_position: u64,
+    }
+
+    /// Capture client returning queued packets.
+    ///
+    /// Shares the layout prefix and reference counting of `MockUnknown`.
+    #[repr(C)]
+    pub struct MockCaptureClient {
+        vtbl: *const IAudioCaptureClientVtbl,
+        pub refcount: i32,
+        pub releases: u32,
+        pub packets: VecDeque<MockPacket>,
+        /// Device position of the next packet in frames.
+        pub position: u64,
+    }
+
+    static CAPTURE_VTBL: IAudioCaptureClientVtbl = IAudioCaptureClientVtbl {
+        parent: IUnknownVtbl {
+            QueryInterface: query_interface,
+            AddRef: add_ref,
+            Release: release,
+        },
+        GetBuffer: capture_get_buffer,
+        ReleaseBuffer: capture_release_buffer,
+        GetNextPacketSize: capture_next_packet_size,
+    };
+
+    impl MockCaptureClient {
+        /// Create a new object holding a single reference.
+        pub fn new(packets: Vec<MockPacket>) -> Box<Self> {
+            Box::new(MockCaptureClient {
+                vtbl: &CAPTURE_VTBL,
+                refcount: 1,
+                releases: 0,
+                packets: packets.into(),
+                position: 0,
+            })
+        }
+
+        pub fn as_ptr(&mut self) -> WeakPtr<IAudioCaptureClient> {
+            unsafe { WeakPtr::from_raw(self as *mut _ as *mut _) }
+        }
+    }
+
+    unsafe extern "system" fn capture_get_buffer(
+        this: *mut IAudioCaptureClient,
+        data: *mut *mut BYTE,
+        frames: *mut u32,
+        flags: *mut DWORD,
+        position: *mut u64,
+        qpc_position: *mut u64,
+    ) -> winerror::HRESULT {
+        let this = &mut *(this as *mut MockCaptureClient);
+        *position = this.position;
+        match this.packets.front_mut() {
+            Some(packet) => {
+                *data = packet.data.as_mut_ptr();
+                *frames = packet.frames;
+                *flags = packet.flags;
+                *qpc_position = packet.qpc_position;
+                winerror::S_OK
+            }
+            None => {
+                *frames = 0;
+                *flags = 0;
+                winapi::um::audioclient::AUDCLNT_S_BUFFER_EMPTY
+            }
+        }
+    }
+
+    unsafe extern "system" fn capture_release_buffer(
+        this: *mut IAudioCaptureClient,
+        frames: u32,
+    ) -> winerror::HRESULT {
+        let this = &mut *(this as *mut MockCaptureClient);
+        if frames > 0 {
+            this.packets.pop_front();
+            this.position += frames as u64;
+        }
+        winerror::S_OK
+    }
+
+    unsafe extern "system" fn capture_next_packet_size(
+        this: *mut IAudioCaptureClient,
+        frames: *mut u32,
+    ) -> winerror::HRESULT {
+        let this = &*(this as *mut MockCaptureClient);
+        *frames = this.packets.front().map_or(0, |packet| packet.frames);
+        winerror::S_OK
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockUnknown;
+    use winapi::um::audioclient::IAudioClient;
+
+    #[test]
+    fn copies_share_a_reference() {
+        let mut object = MockUnknown::new();
+        let ptr = object.as_ptr::<IAudioClient>();
+        let copy = ptr;
+        unsafe { copy.destroy() };
+        assert_eq!(object.refcount, 0);
+        assert_eq!(object.releases, 1);
+        assert!(!ptr.is_null());
+    }
+
+    #[test]
+    fn add_ref_and_cast_require_destroy() {
+        let mut object = MockUnknown::new();
+        let ptr = object.as_ptr::<IAudioClient>();
+        unsafe {
+            let (cast, hr) = ptr.cast::<IAudioClient>();
+            assert_eq!(hr, winapi::shared::winerror::S_OK);
+            let added = ptr.add_ref();
+            assert_eq!(object.refcount, 3);
+
+            cast.destroy();
+            added.destroy();
+            ptr.destroy();
+        }
+        assert_eq!(object.refcount, 0);
+        assert_eq!(object.releases, 3);
+    }
+}
@@ -0,0 +1,223 @@
+//! COM objects receiving device and session notifications.
+
+// The `ComImpl` derive drops the boxed object in `Release` without binding the result.
+// Lint attributes on the structs don't apply to the derived impls.
+#![allow(unused_must_use)]
+
+use super::ffi::{AudioSessionDisconnectReason, IAudioSessionEvents, IAudioSessionEventsVtbl};
+use super::{
+    string_from_wstr, EventCallback, Instance, InstanceRaw, PhysicalDeviceMap, PhysicalDeviceRaw,
+};
+use crate::api;
+use std::sync::{Arc, Mutex};
+use winapi::shared::{
+    guiddef::LPCGUID,
+    minwindef::{BOOL, DWORD, FALSE},
+    winerror,
+    wtypes::PROPERTYKEY,
+};
+use winapi::um::{
+    audiosessiontypes::*,
+    mmdeviceapi::*,
+    winnt::{HRESULT, LPCWSTR},
+};
+
+#[repr(C)]
+#[derive(com_impl::ComImpl)]
+#[interfaces(IMMNotificationClient)]
+pub struct NotificationClient {
+    vtbl: com_impl::VTable<IMMNotificationClientVtbl>,
+    refcount: com_impl::Refcount,
+    instance: InstanceRaw,
+    physical_devices: Arc<Mutex<PhysicalDeviceMap>>,
+    cb: EventCallback,
+}
+
+impl NotificationClient {
+    pub(super) fn new(
+        instance: InstanceRaw,
+        physical_devices: Arc<Mutex<PhysicalDeviceMap>>,
+        cb: EventCallback,
+    ) -> *mut Self {
+        Self::create_raw(instance, physical_devices, cb)
+    }
+
+    /// Look up the handle of a known physical device.
+    unsafe fn physical_device(&self, id: LPCWSTR) -> Option<api::PhysicalDevice> {
+        let id = string_from_wstr(id);
+        self.physical_devices.lock().unwrap().ids.get(&id).copied()
+    }
+
+    /// Drop the cached capabilities of a known physical device.
+    unsafe fn invalidate_capabilities(&self, id: LPCWSTR) {
+        let id = string_from_wstr(id);
+        let physical_devices = self.physical_devices.lock().unwrap();
+        if let Some(&handle) = physical_devices.ids.get(&id) {
+            if let Ok(physical_device) = physical_devices.get(handle) {
+                physical_device.invalidate_capabilities();
+            }
+        }
+    }
+
+    /// Add or update the physical device of the notified endpoint.
+    ///
+    /// `reactivate` replaces the audio client of known active devices,
+    /// e.g clients of devices which were inactive in between are stale.
+    unsafe fn refresh_physical_device(
+        &self,
+        id: LPCWSTR,
+        reactivate: bool,
+    ) -> Option<api::PhysicalDevice> {
+        let mut device = PhysicalDeviceRaw::null();
+        let hr = self.instance.GetDevice(id, device.mut_void() as *mut _);
+        if !winerror::SUCCEEDED(hr) {
+            return None;
+        }
+
+        let (endpoint, hr) = device.cast::<IMMEndpoint>();
+        if !winerror::SUCCEEDED(hr) {
+            device.destroy();
+            return None;
+        }
+        let mut flow = eAll;
+        endpoint.GetDataFlow(&mut flow);
+        endpoint.destroy();
+
+        let mut physical_devices = self.physical_devices.lock().unwrap();
+        Some(Instance::insert_physical_device(
+            &mut physical_devices,
+            device,
+            flow,
+            reactivate,
+        ))
+    }
+
+    fn emit(&self, event: api::Event) {
+        if let Some(callback) = self.cb.lock().unwrap().as_mut() {
+            callback(event);
+        }
+    }
+}
+
+#[com_impl::com_impl]
+unsafe impl IMMNotificationClient for NotificationClient {
+    unsafe fn on_device_state_changed(&self, pwstrDeviceId: LPCWSTR, state: DWORD) -> HRESULT {
+        self.invalidate_capabilities(pwstrDeviceId);
+        let active = state & DEVICE_STATE_ACTIVE != 0;
+        if let Some(device) = self.refresh_physical_device(pwstrDeviceId, active) {
+            self.emit(api::Event::DeviceStateChanged { device, active });
+        }
+        winerror::S_OK
+    }
+
+    unsafe fn on_device_added(&self, pwstrDeviceId: LPCWSTR) -> HRESULT {
+        if let Some(device) = self.refresh_physical_device(pwstrDeviceId, false) {
+            self.emit(api::Event::DeviceAdded(device));
+        }
+        winerror::S_OK
+    }
+
+    unsafe fn on_device_removed(&self, pwstrDeviceId: LPCWSTR) -> HRESULT {
+        // Handles stay valid, removed devices are filtered out by their state on enumeration.
+        if let Some(device) = self.physical_device(pwstrDeviceId) {
+            self.emit(api::Event::DeviceRemoved(device));
+        }
+        winerror::S_OK
+    }
+
+    unsafe fn on_default_device_changed(
+        &self,
+        flow: EDataFlow,
+        role: ERole,
+        pwstrDefaultDeviceId: LPCWSTR,
+    ) -> HRESULT {
+        // Only the console role is reported, flow distinguishes input and output defaults.
+        if role == eConsole {
+            let device = if pwstrDefaultDeviceId.is_null() {
+                None
+            } else {
+                self.refresh_physical_device(pwstrDefaultDeviceId, false)
+            };
+            match flow {
+                eCapture => self.emit(api::Event::DefaultInputChanged(device)),
+                eRender => self.emit(api::Event::DefaultOutputChanged(device)),
+                _ => (),
+            }
+        }
+
+        winerror::S_OK
+    }
+
+    unsafe fn on_property_value_changed(
+        &self,
+        pwstrDeviceId: LPCWSTR,
+        _key: PROPERTYKEY,
+    ) -> HRESULT {
+        // Properties include the device format, capabilities are probed again on demand.
+        self.invalidate_capabilities(pwstrDeviceId);
+        winerror::S_OK
+    }
+}
+
+#[repr(C)]
+#[derive(com_impl::ComImpl)]
+#[interfaces(IAudioSessionEvents)]
+pub struct SessionEvents {
+    vtbl: com_impl::VTable<IAudioSessionEventsVtbl>,
+    refcount: com_impl::Refcount,
+    cb: EventCallback,
+}
+
+impl SessionEvents {
+    pub(super) fn new(cb: EventCallback) -> *mut Self {
+        Self::create_raw(cb)
+    }
+}
+
+#[com_impl::com_impl]
+unsafe impl IAudioSessionEvents for SessionEvents {
+    unsafe fn on_display_name_changed(&self, _name: LPCWSTR, _context: LPCGUID) -> HRESULT {
+        winerror::S_OK
+    }
+
+    unsafe fn on_icon_path_changed(&self, _path: LPCWSTR, _context: LPCGUID) -> HRESULT {
+        winerror::S_OK
+    }
+
+    unsafe fn on_simple_volume_changed(
+        &self,
+        volume: f32,
+        mute: BOOL,
+        _context: LPCGUID,
+    ) -> HRESULT {
+        if let Some(callback) = &mut *self.cb.lock().unwrap() {
+            callback(api::Event::VolumeChanged {
+                level: volume,
+                muted: mute != FALSE,
+            });
+        }
+        winerror::S_OK
+    }
+
+    unsafe fn on_channel_volume_changed(
+        &self,
+        _channel_count: DWORD,
+        _volumes: *const f32,
+        _changed_channel: DWORD,
+        _context: LPCGUID,
+    ) -> HRESULT {
+        winerror::S_OK
+    }
+
+    unsafe fn on_grouping_param_changed(&self, _param: LPCGUID, _context: LPCGUID) -> HRESULT {
+        winerror::S_OK
+    }
+
+    unsafe fn on_state_changed(&self, _state: AudioSessionState) -> HRESULT {
+        winerror::S_OK
+    }
+
+    unsafe fn on_session_disconnected(&self, _reason: AudioSessionDisconnectReason) -> HRESULT {
+        winerror::S_OK
+    }
+}
@@ -1,4 +1,5 @@
 use std::ptr;
+use winapi::shared::minwindef::TRUE;
 use winapi::um::{handleapi, synchapi, winnt};
 
 #[derive(Copy, Clone)]
@@ -20,4 +21,9 @@ impl Fence {
     pub unsafe fn wait(&self, timeout_ms: u32) -> u32 {
         synchapi::WaitForSingleObject(self.0, timeout_ms)
     }
+
+    /// Wait in an alertable state, returns `WAIT_IO_COMPLETION` if an APC has been executed.
+    pub unsafe fn wait_alertable(&self, timeout_ms: u32) -> u32 {
+        synchapi::WaitForSingleObjectEx(self.0, timeout_ms, TRUE)
+    }
 }
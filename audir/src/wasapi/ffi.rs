@@ -0,0 +1,262 @@
+//! Audio client declarations missing in `winapi`.
+
+#![allow(non_camel_case_types, non_snake_case)]
+
+use winapi::ctypes::c_void;
+use winapi::shared::{
+    basetsd::UINT32,
+    guiddef::{GUID, LPCGUID, REFIID},
+    minwindef::{BOOL, BYTE, DWORD},
+    mmreg::WAVEFORMATEX,
+    winerror::HRESULT,
+};
+use winapi::um::{
+    audioclient::{IAudioClient, IAudioClientVtbl},
+    audiosessiontypes::{AudioSessionState, AUDIO_STREAM_CATEGORY},
+    propidl::PROPVARIANT,
+    strmif::REFERENCE_TIME,
+    unknwnbase::{IUnknown, IUnknownVtbl},
+    winnt::{HANDLE, LPCWSTR, LPWSTR},
+};
+use winapi::RIDL;
+
+pub type AUDCLNT_STREAMOPTIONS = u32;
+
+pub const AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY: DWORD = 0x0800_0000;
+pub const AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM: DWORD = 0x8000_0000;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct AudioClientProperties {
+    pub cbSize: UINT32,
+    pub bIsOffload: BOOL,
+    pub eCategory: AUDIO_STREAM_CATEGORY,
+    pub Options: AUDCLNT_STREAMOPTIONS,
+}
+
+RIDL! {#[uuid(0x726778cd, 0xf60a, 0x4eda, 0x82, 0xde, 0xe4, 0x76, 0x10, 0xcd, 0x78, 0xaa)]
+interface IAudioClient2(IAudioClient2Vtbl): IAudioClient(IAudioClientVtbl) {
+    fn IsOffloadCapable(
+        Category: AUDIO_STREAM_CATEGORY,
+        pbOffloadCapable: *mut BOOL,
+    ) -> HRESULT,
+    fn SetClientProperties(
+        pProperties: *const AudioClientProperties,
+    ) -> HRESULT,
+    fn GetBufferSizeLimits(
+        pFormat: *const WAVEFORMATEX,
+        bEventDriven: BOOL,
+        phnsMinBufferDuration: *mut REFERENCE_TIME,
+        phnsMaxBufferDuration: *mut REFERENCE_TIME,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0x7ed4ee07, 0x8e67, 0x4cd4, 0x8c, 0x1a, 0x2b, 0x7a, 0x59, 0x87, 0xad, 0x42)]
+interface IAudioClient3(IAudioClient3Vtbl): IAudioClient2(IAudioClient2Vtbl) {
+    fn GetSharedModeEnginePeriod(
+        pFormat: *const WAVEFORMATEX,
+        pDefaultPeriodInFrames: *mut UINT32,
+        pFundamentalPeriodInFrames: *mut UINT32,
+        pMinPeriodInFrames: *mut UINT32,
+        pMaxPeriodInFrames: *mut UINT32,
+    ) -> HRESULT,
+    fn GetCurrentSharedModeEnginePeriod(
+        ppFormat: *mut *mut WAVEFORMATEX,
+        pCurrentPeriodInFrames: *mut UINT32,
+    ) -> HRESULT,
+    fn InitializeSharedAudioStream(
+        StreamFlags: DWORD,
+        PeriodInFrames: UINT32,
+        pFormat: *const WAVEFORMATEX,
+        AudioSessionGuid: LPCGUID,
+    ) -> HRESULT,
+}}
+
+pub type AudioObjectType = u32;
+
+pub const AudioObjectType_None: AudioObjectType = 0;
+pub const AudioObjectType_Dynamic: AudioObjectType = 1 << 0;
+
+#[repr(C, packed(1))]
+#[derive(Copy, Clone)]
+pub struct SpatialAudioObjectRenderStreamActivationParams {
+    pub ObjectFormat: *const WAVEFORMATEX,
+    pub StaticObjectTypeMask: AudioObjectType,
+    pub MinDynamicObjectCount: UINT32,
+    pub MaxDynamicObjectCount: UINT32,
+    pub Category: AUDIO_STREAM_CATEGORY,
+    pub EventHandle: HANDLE,
+    pub NotifyObject: *mut c_void,
+}
+
+RIDL! {#[uuid(0xdcdaa858, 0x895a, 0x4a22, 0xa5, 0xeb, 0x67, 0xbd, 0xa5, 0x06, 0x09, 0x6d)]
+interface IAudioFormatEnumerator(IAudioFormatEnumeratorVtbl): IUnknown(IUnknownVtbl) {
+    fn GetCount(
+        count: *mut UINT32,
+    ) -> HRESULT,
+    fn GetFormat(
+        index: UINT32,
+        format: *mut *mut WAVEFORMATEX,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0xcce0b8f2, 0x8d4d, 0x4efb, 0xa8, 0xcf, 0x3d, 0x6e, 0xcf, 0x1c, 0x30, 0xe0)]
+interface ISpatialAudioObjectBase(ISpatialAudioObjectBaseVtbl): IUnknown(IUnknownVtbl) {
+    fn GetBuffer(
+        buffer: *mut *mut BYTE,
+        bufferLength: *mut UINT32,
+    ) -> HRESULT,
+    fn SetEndOfStream(
+        frameCount: UINT32,
+    ) -> HRESULT,
+    fn IsActive(
+        isActive: *mut BOOL,
+    ) -> HRESULT,
+    fn GetAudioObjectType(
+        audioObjectType: *mut AudioObjectType,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0xdde28967, 0x521b, 0x46e5, 0x8f, 0x00, 0xbd, 0x6f, 0x2b, 0xc8, 0xab, 0x1d)]
+interface ISpatialAudioObject(ISpatialAudioObjectVtbl): ISpatialAudioObjectBase(ISpatialAudioObjectBaseVtbl) {
+    fn SetPosition(
+        x: f32,
+        y: f32,
+        z: f32,
+    ) -> HRESULT,
+    fn SetVolume(
+        volume: f32,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0xfeaaf403, 0xc1d8, 0x450d, 0xaa, 0x05, 0xe0, 0xcc, 0xee, 0x75, 0x02, 0xa8)]
+interface ISpatialAudioObjectRenderStreamBase(ISpatialAudioObjectRenderStreamBaseVtbl): IUnknown(IUnknownVtbl) {
+    fn GetAvailableDynamicObjectCount(
+        value: *mut UINT32,
+    ) -> HRESULT,
+    fn GetService(
+        riid: REFIID,
+        service: *mut *mut c_void,
+    ) -> HRESULT,
+    fn Start() -> HRESULT,
+    fn Stop() -> HRESULT,
+    fn Reset() -> HRESULT,
+    fn BeginUpdatingAudioObjects(
+        availableDynamicObjectCount: *mut UINT32,
+        frameCountPerBuffer: *mut UINT32,
+    ) -> HRESULT,
+    fn EndUpdatingAudioObjects() -> HRESULT,
+}}
+
+RIDL! {#[uuid(0xbab5f473, 0xb423, 0x477b, 0x85, 0xf5, 0xb5, 0xa3, 0x32, 0xa0, 0x41, 0x53)]
+interface ISpatialAudioObjectRenderStream(ISpatialAudioObjectRenderStreamVtbl):
+    ISpatialAudioObjectRenderStreamBase(ISpatialAudioObjectRenderStreamBaseVtbl) {
+    fn ActivateSpatialAudioObject(
+        audioObjectType: AudioObjectType,
+        audioObject: *mut *mut ISpatialAudioObject,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0xbbf8e066, 0xaaaa, 0x49be, 0x9a, 0x4d, 0xfd, 0x2a, 0x85, 0x8e, 0xa2, 0x7f)]
+interface ISpatialAudioClient(ISpatialAudioClientVtbl): IUnknown(IUnknownVtbl) {
+    fn GetStaticObjectPosition(
+        audioObjectType: AudioObjectType,
+        x: *mut f32,
+        y: *mut f32,
+        z: *mut f32,
+    ) -> HRESULT,
+    fn GetNativeStaticObjectTypeMask(
+        mask: *mut AudioObjectType,
+    ) -> HRESULT,
+    fn GetMaxDynamicObjectCount(
+        value: *mut UINT32,
+    ) -> HRESULT,
+    fn GetSupportedAudioObjectFormatEnumerator(
+        enumerator: *mut *mut IAudioFormatEnumerator,
+    ) -> HRESULT,
+    fn GetMaxFrameCount(
+        objectFormat: *const WAVEFORMATEX,
+        frameCountPerBuffer: *mut UINT32,
+    ) -> HRESULT,
+    fn IsAudioObjectFormatSupported(
+        objectFormat: *const WAVEFORMATEX,
+    ) -> HRESULT,
+    fn IsSpatialAudioStreamAvailable(
+        streamUuid: REFIID,
+        auxiliaryInfo: *const PROPVARIANT,
+    ) -> HRESULT,
+    fn ActivateSpatialAudioStream(
+        activationParams: *const PROPVARIANT,
+        riid: REFIID,
+        stream: *mut *mut c_void,
+    ) -> HRESULT,
+}}
+
+pub type AudioSessionDisconnectReason = u32;
+
+RIDL! {#[uuid(0x24918acc, 0x64b3, 0x37c1, 0x8c, 0xa9, 0x74, 0xa6, 0x6e, 0x99, 0x57, 0xa8)]
+interface IAudioSessionEvents(IAudioSessionEventsVtbl): IUnknown(IUnknownVtbl) {
+    fn OnDisplayNameChanged(
+        NewDisplayName: LPCWSTR,
+        EventContext: LPCGUID,
+    ) -> HRESULT,
+    fn OnIconPathChanged(
+        NewIconPath: LPCWSTR,
+        EventContext: LPCGUID,
+    ) -> HRESULT,
+    fn OnSimpleVolumeChanged(
+        NewVolume: f32,
+        NewMute: BOOL,
+        EventContext: LPCGUID,
+    ) -> HRESULT,
+    fn OnChannelVolumeChanged(
+        ChannelCount: DWORD,
+        NewChannelVolumeArray: *const f32,
+        ChangedChannel: DWORD,
+        EventContext: LPCGUID,
+    ) -> HRESULT,
+    fn OnGroupingParamChanged(
+        NewGroupingParam: LPCGUID,
+        EventContext: LPCGUID,
+    ) -> HRESULT,
+    fn OnStateChanged(
+        NewState: AudioSessionState,
+    ) -> HRESULT,
+    fn OnSessionDisconnected(
+        DisconnectReason: AudioSessionDisconnectReason,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0xf4b1a599, 0x7266, 0x4319, 0xa8, 0xca, 0xe7, 0x0a, 0xcb, 0x11, 0xe8, 0xcd)]
+interface IAudioSessionControl(IAudioSessionControlVtbl): IUnknown(IUnknownVtbl) {
+    fn GetState(
+        pRetVal: *mut AudioSessionState,
+    ) -> HRESULT,
+    fn GetDisplayName(
+        pRetVal: *mut LPWSTR,
+    ) -> HRESULT,
+    fn SetDisplayName(
+        Value: LPCWSTR,
+        EventContext: LPCGUID,
+    ) -> HRESULT,
+    fn GetIconPath(
+        pRetVal: *mut LPWSTR,
+    ) -> HRESULT,
+    fn SetIconPath(
+        Value: LPCWSTR,
+        EventContext: LPCGUID,
+    ) -> HRESULT,
+    fn GetGroupingParam(
+        pRetVal: *mut GUID,
+    ) -> HRESULT,
+    fn SetGroupingParam(
+        Override: LPCGUID,
+        EventContext: LPCGUID,
+    ) -> HRESULT,
+    fn RegisterAudioSessionNotification(
+        NewNotifications: *mut IAudioSessionEvents,
+    ) -> HRESULT,
+    fn UnregisterAudioSessionNotification(
+        NewNotifications: *mut IAudioSessionEvents,
+    ) -> HRESULT,
+}}
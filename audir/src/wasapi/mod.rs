@@ -1,32 +1,73 @@
 #![allow(non_upper_case_globals)]
 
 pub mod com;
+mod events;
 mod fence;
+mod ffi;
+mod monitor;
+mod spatial;
 
-use self::fence::*;
+pub use self::monitor::Monitor;
+pub use self::spatial::{SpatialCallback, SpatialDevice, SpatialObject, SpatialStream};
+use self::{events::*, fence::*, ffi::*};
 
 pub use winapi::shared::winerror::HRESULT;
 pub type WasapiResult<T> = (T, HRESULT);
 
-use com::{Guid, WeakPtr};
+use com::{Guid, WasapiError, WeakPtr};
 use std::{
-    collections::HashMap, ffi::OsString, mem, os::windows::ffi::OsStringExt, ptr, slice,
-    sync::Mutex,
+    cell::Cell,
+    collections::BTreeMap,
+    convert::TryFrom,
+    ffi::{OsStr, OsString},
+    mem,
+    os::windows::ffi::{OsStrExt, OsStringExt},
+    ptr, slice,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread, time,
 };
 use winapi::shared::{
-    devpkey::*, ksmedia, minwindef::DWORD, mmreg::*, winerror, wtypes::PROPERTYKEY,
+    basetsd::DWORD_PTR,
+    devpkey::*,
+    ksmedia,
+    minwindef::{DWORD, FALSE, TRUE},
+    mmreg::*,
+    winerror,
+    wtypes::VT_UI4,
 };
 use winapi::um::{
-    audioclient::*, audiosessiontypes::*, combaseapi::*, coml2api::STGM_READ, mmdeviceapi::*,
-    objbase::COINIT_MULTITHREADED, propsys::*, winnt::*,
+    audioclient::*,
+    audiosessiontypes::*,
+    combaseapi::*,
+    coml2api::STGM_READ,
+    devicetopology::{IConnector, IDeviceTopology},
+    endpointvolume::IAudioMeterInformation,
+    mmdeviceapi::*,
+    objbase::COINIT_MULTITHREADED,
+    processthreadsapi::GetCurrentThread,
+    propsys::*,
+    strmif::REFERENCE_TIME,
+    synchapi::WaitForMultipleObjectsEx,
+    winbase::{
+        SetThreadAffinityMask, WAIT_ABANDONED_0, WAIT_FAILED, WAIT_IO_COMPLETION, WAIT_OBJECT_0,
+    },
+    winnt::*,
 };
 use winapi::Interface;
 
 use crate::{
+    alloc_guard,
     api::{self, Result},
-    handle::Handle,
+    convert,
+    handle::HandleTable,
 };
 
+/// Number of consecutive discontinuities before an input stream is reset (see `DeviceDesc::auto_recover`).
+const RECOVER_DISCONTINUITIES: u32 = 3;
+
 unsafe fn string_from_wstr(os_str: *const WCHAR) -> String {
     let mut len = 0;
     while *os_str.offset(len) != 0 {
@@ -36,84 +77,89 @@ unsafe fn string_from_wstr(os_str: *const WCHAR) -> String {
     string.into_string().unwrap()
 }
 
-#[repr(C)]
-#[derive(com_impl::ComImpl)]
-#[interfaces(IMMNotificationClient)]
-pub struct NotificationClient {
-    vtbl: com_impl::VTable<IMMNotificationClientVtbl>,
-    refcount: com_impl::Refcount,
-    cb: Box<dyn FnMut(api::Event)>,
-}
-
-#[com_impl::com_impl]
-unsafe impl IMMNotificationClient for NotificationClient {
-    unsafe fn on_device_state_changed(&self, pwstrDeviceId: LPCWSTR, state: DWORD) -> HRESULT {
-        println!("changed {} to {}", string_from_wstr(pwstrDeviceId), state);
-        winerror::S_OK
-    }
-
-    unsafe fn on_device_added(&self, pwstrDeviceId: LPCWSTR) -> HRESULT {
-        println!("added {}", string_from_wstr(pwstrDeviceId));
-        winerror::S_OK
-    }
+type EventCallback = Arc<Mutex<Option<Box<dyn FnMut(api::Event) + Send>>>>;
 
-    unsafe fn on_device_removed(&self, pwstrDeviceId: LPCWSTR) -> HRESULT {
-        println!("removed {}", string_from_wstr(pwstrDeviceId));
-        winerror::S_OK
-    }
+/// Session event registration of a device stream.
+#[derive(Copy, Clone)]
+struct SessionNotifier {
+    control: WeakPtr<IAudioSessionControl>,
+    events: WeakPtr<IAudioSessionEvents>,
+}
 
-    unsafe fn on_default_device_changed(
-        &self,
-        _flow: EDataFlow,
-        role: ERole,
-        pwstrDefaultDeviceId: LPCWSTR,
-    ) -> HRESULT {
-        if role == eConsole {
-            println!("default {:?} ({})", pwstrDefaultDeviceId, role);
-        }
+impl SessionNotifier {
+    /// Forward session volume changes of an initialized audio client to the event callback.
+    unsafe fn register(client: WeakPtr<IAudioClient>, callback: EventCallback) -> Option<Self> {
+        let control = get_service::<IAudioSessionControl>(client, "IAudioSessionControl").ok()?;
 
-        winerror::S_OK
+        let events = WeakPtr::from_raw(SessionEvents::new(callback) as *mut IAudioSessionEvents);
+        control.RegisterAudioSessionNotification(events.as_mut_ptr());
+        Some(SessionNotifier { control, events })
     }
 
-    unsafe fn on_property_value_changed(
-        &self,
-        _pwstrDeviceId: LPCWSTR,
-        _key: PROPERTYKEY,
-    ) -> HRESULT {
-        winerror::S_OK
+    unsafe fn unregister(self) {
+        self.control
+            .UnregisterAudioSessionNotification(self.events.as_mut_ptr());
+        self.events.destroy();
+        self.control.destroy();
     }
 }
 
+/// Speaker positions of the channel mask and their WASAPI counterparts.
+const SPEAKERS: [(api::ChannelMask, DWORD); 11] = [
+    (api::ChannelMask::FRONT_LEFT, SPEAKER_FRONT_LEFT),
+    (api::ChannelMask::FRONT_RIGHT, SPEAKER_FRONT_RIGHT),
+    (api::ChannelMask::FRONT_CENTER, SPEAKER_FRONT_CENTER),
+    (api::ChannelMask::LOW_FREQUENCY, SPEAKER_LOW_FREQUENCY),
+    (api::ChannelMask::BACK_LEFT, SPEAKER_BACK_LEFT),
+    (api::ChannelMask::BACK_RIGHT, SPEAKER_BACK_RIGHT),
+    (
+        api::ChannelMask::FRONT_LEFT_OF_CENTER,
+        SPEAKER_FRONT_LEFT_OF_CENTER,
+    ),
+    (
+        api::ChannelMask::FRONT_RIGHT_OF_CENTER,
+        SPEAKER_FRONT_RIGHT_OF_CENTER,
+    ),
+    (api::ChannelMask::BACK_CENTER, SPEAKER_BACK_CENTER),
+    (api::ChannelMask::SIDE_LEFT, SPEAKER_SIDE_LEFT),
+    (api::ChannelMask::SIDE_RIGHT, SPEAKER_SIDE_RIGHT),
+];
+
+/// Map a frame description to a wave format.
+///
+/// Single entry point for building stream formats, used for format probing and
+/// device creation alike. Block alignment and byte rate are derived from the
+/// number of channels of the channel mask, including unassigned channels.
 fn map_frame_desc(frame_desc: &api::FrameDesc) -> Option<WAVEFORMATEXTENSIBLE> {
-    let (format_tag, sub_format, bytes_per_sample) = match frame_desc.format {
-        api::Format::F32 => (
-            WAVE_FORMAT_EXTENSIBLE,
-            ksmedia::KSDATAFORMAT_SUBTYPE_IEEE_FLOAT,
-            4,
-        ),
-        api::Format::U32 => return None,
-        _ => unimplemented!(),
+    // PCM samples are always signed.
+    if frame_desc.endianness != api::Endianness::Little || frame_desc.format == api::Format::U32 {
+        return None;
+    }
+
+    let sub_format = if frame_desc.format.is_float() {
+        ksmedia::KSDATAFORMAT_SUBTYPE_IEEE_FLOAT
+    } else {
+        ksmedia::KSDATAFORMAT_SUBTYPE_PCM
     };
 
-    let mut channel_mask = 0;
-    {
-        let channels = frame_desc.channels;
-        if channels.contains(api::ChannelMask::FRONT_LEFT) {
-            channel_mask |= SPEAKER_FRONT_LEFT;
-        }
-        if channels.contains(api::ChannelMask::FRONT_RIGHT) {
-            channel_mask |= SPEAKER_FRONT_RIGHT;
-        }
-        if channels.contains(api::ChannelMask::FRONT_CENTER) {
-            channel_mask |= SPEAKER_FRONT_CENTER;
-        }
-    }
+    // Unassigned channels only contribute to the channel count.
+    let channel_mask = SPEAKERS
+        .iter()
+        .filter(|&&(channel, _)| frame_desc.channels.contains(channel))
+        .fold(0, |mask, &(_, speaker)| mask | speaker);
 
+    // Undefined channel bits are rejected by `FrameDesc::validate`.
     let num_channels = frame_desc.num_channels();
-    let bits_per_sample = 8 * bytes_per_sample;
+    debug_assert_eq!(
+        channel_mask.count_ones() as usize + frame_desc.channels.num_unassigned(),
+        num_channels
+    );
+
+    let bytes_per_sample = frame_desc.format.bytes_per_sample();
+    let bits_per_sample = frame_desc.format.bits_per_sample();
 
     let format = WAVEFORMATEX {
-        wFormatTag: format_tag,
+        wFormatTag: WAVE_FORMAT_EXTENSIBLE,
         nChannels: num_channels as _,
         nSamplesPerSec: frame_desc.sample_rate as _,
         nAvgBytesPerSec: (num_channels * frame_desc.sample_rate * bytes_per_sample) as _,
@@ -136,31 +182,72 @@ unsafe fn map_waveformat(format: *const WAVEFORMATEX) -> Result<api::FrameDesc>
         WAVE_FORMAT_EXTENSIBLE => {
             let wave_format_ex = &*(format as *const WAVEFORMATEXTENSIBLE);
             let subformat = Guid(wave_format_ex.SubFormat);
-            let samples = wave_format_ex.Samples;
+            // The container size determines the sample format, `Samples` only denotes
+            // the valid bits (e.g 24 bit samples in 32 bit containers).
+            let bits = wave_format.wBitsPerSample;
             let format =
-                if subformat == Guid(ksmedia::KSDATAFORMAT_SUBTYPE_IEEE_FLOAT) && samples == 32 {
+                if subformat == Guid(ksmedia::KSDATAFORMAT_SUBTYPE_IEEE_FLOAT) && bits == 32 {
                     api::Format::F32
+                } else if subformat == Guid(ksmedia::KSDATAFORMAT_SUBTYPE_PCM) {
+                    match bits {
+                        16 => api::Format::I16,
+                        24 => api::Format::I24,
+                        32 => api::Format::I32,
+                        _ => {
+                            return Err(api::Error::Internal {
+                                cause: "unsupported format".into(),
+                            })
+                        }
+                    }
                 } else {
                     return Err(api::Error::Internal {
                         cause: "unsupported format".into(),
                     }); // TODO
                 };
 
-            let mut channels = api::ChannelMask::empty();
-            if wave_format_ex.dwChannelMask & SPEAKER_FRONT_LEFT != 0 {
-                channels |= api::ChannelMask::FRONT_LEFT;
-            }
-            if wave_format_ex.dwChannelMask & SPEAKER_FRONT_RIGHT != 0 {
-                channels |= api::ChannelMask::FRONT_RIGHT;
-            }
-            if wave_format_ex.dwChannelMask & SPEAKER_FRONT_CENTER != 0 {
-                channels |= api::ChannelMask::FRONT_CENTER;
-            }
+            let mut channels = SPEAKERS
+                .iter()
+                .filter(|&&(_, speaker)| wave_format_ex.dwChannelMask & speaker != 0)
+                .fold(api::ChannelMask::empty(), |mask, &(channel, _)| {
+                    mask | channel
+                });
+            // Remaining channels have no (supported) speaker assignment.
+            let num_unassigned = (wave_format.nChannels as usize)
+                .saturating_sub(channels.bits().count_ones() as usize);
+            channels |= api::ChannelMask::raw(num_unassigned);
+
+            Ok(api::FrameDesc {
+                format,
+                channels,
+                sample_rate: wave_format.nSamplesPerSec as _,
+                endianness: api::Endianness::Little,
+            })
+        }
+        WAVE_FORMAT_PCM | WAVE_FORMAT_IEEE_FLOAT => {
+            let format = match (wave_format.wFormatTag, wave_format.wBitsPerSample) {
+                (WAVE_FORMAT_IEEE_FLOAT, 32) => api::Format::F32,
+                (WAVE_FORMAT_PCM, 16) => api::Format::I16,
+                (WAVE_FORMAT_PCM, 24) => api::Format::I24,
+                (WAVE_FORMAT_PCM, 32) => api::Format::I32,
+                _ => {
+                    return Err(api::Error::Internal {
+                        cause: "unsupported format".into(),
+                    })
+                }
+            };
+
+            // Formats without channel mask use the default speaker assignment.
+            let channels = match wave_format.nChannels {
+                1 => api::ChannelMask::FRONT_CENTER,
+                2 => api::ChannelMask::FRONT_LEFT | api::ChannelMask::FRONT_RIGHT,
+                n => api::ChannelMask::raw(n as _),
+            };
 
             Ok(api::FrameDesc {
                 format,
                 channels,
                 sample_rate: wave_format.nSamplesPerSec as _,
+                endianness: api::Endianness::Little,
             })
         }
         _ => Err(api::Error::Internal {
@@ -169,6 +256,204 @@ unsafe fn map_waveformat(format: *const WAVEFORMATEX) -> Result<api::FrameDesc>
     }
 }
 
+/// Valid bits per sample of a wave format.
+unsafe fn valid_bits(format: *const WAVEFORMATEX) -> u16 {
+    if (*format).wFormatTag == WAVE_FORMAT_EXTENSIBLE {
+        (*(format as *const WAVEFORMATEXTENSIBLE)).Samples
+    } else {
+        (*format).wBitsPerSample
+    }
+}
+
+/// Query the format of an audio client.
+///
+/// Uses the mix format if available, otherwise falls back to probing common
+/// formats in exclusive mode (e.g for virtual devices without mix format).
+unsafe fn client_format(audio_client: WeakPtr<IAudioClient>) -> Result<api::FrameDesc> {
+    let mut mix_format = ptr::null_mut();
+    let hr = audio_client.GetMixFormat(&mut mix_format);
+    if winerror::SUCCEEDED(hr) && !mix_format.is_null() {
+        let frame_desc = map_waveformat(mix_format);
+        CoTaskMemFree(mix_format as _);
+        if frame_desc.is_ok() {
+            return frame_desc;
+        }
+    }
+
+    const SAMPLE_RATES: [usize; 3] = [48_000, 44_100, 96_000];
+    const FORMATS: [api::Format; 4] = [
+        api::Format::F32,
+        api::Format::I24,
+        api::Format::I32,
+        api::Format::I16,
+    ];
+    let layouts = [
+        api::ChannelMask::FRONT_LEFT | api::ChannelMask::FRONT_RIGHT,
+        api::ChannelMask::FRONT_CENTER,
+    ];
+    for &channels in &layouts {
+        for &sample_rate in &SAMPLE_RATES {
+            for &format in &FORMATS {
+                let frame_desc = api::FrameDesc {
+                    format,
+                    sample_rate,
+                    channels,
+                    endianness: api::Endianness::Little,
+                };
+                let wave_format = match map_frame_desc(&frame_desc) {
+                    Some(format) => format,
+                    None => continue,
+                };
+                let hr = audio_client.IsFormatSupported(
+                    AUDCLNT_SHAREMODE_EXCLUSIVE,
+                    &wave_format as *const _ as _,
+                    ptr::null_mut(),
+                );
+                if hr == winerror::S_OK {
+                    return Ok(frame_desc);
+                }
+            }
+        }
+    }
+
+    Err(api::Error::Unsupported {
+        description: "no mix format or supported exclusive format available".into(),
+    })
+}
+
+unsafe fn endpoint_peak(device: PhysicalDeviceRaw) -> Result<f32> {
+    let mut meter = WeakPtr::<IAudioMeterInformation>::null();
+    let hr = device.Activate(
+        &IAudioMeterInformation::uuidof(),
+        CLSCTX_ALL,
+        ptr::null_mut(),
+        meter.mut_void() as *mut _,
+    );
+    WasapiError::from_hr(hr)?;
+
+    let mut peak = 0.0;
+    let hr = meter.GetPeakValue(&mut peak);
+    meter.destroy();
+    WasapiError::from_hr(hr)?;
+
+    Ok(peak)
+}
+
+/// Query the id of the adapter device an endpoint is connected to.
+unsafe fn adapter_id(device: PhysicalDeviceRaw) -> Result<String> {
+    let mut topology = WeakPtr::<IDeviceTopology>::null();
+    let hr = device.Activate(
+        &IDeviceTopology::uuidof(),
+        CLSCTX_ALL,
+        ptr::null_mut(),
+        topology.mut_void() as *mut _,
+    );
+    WasapiError::from_hr(hr)?;
+
+    let mut connector = WeakPtr::<IConnector>::null();
+    let hr = topology.GetConnector(0, connector.mut_void() as *mut _);
+    topology.destroy();
+    WasapiError::from_hr(hr)?;
+
+    let mut id = ptr::null_mut();
+    let hr = connector.GetDeviceIdConnectedTo(&mut id);
+    connector.destroy();
+    WasapiError::from_hr(hr)?;
+
+    let adapter_id = string_from_wstr(id);
+    CoTaskMemFree(id as _);
+    Ok(adapter_id)
+}
+
+/// Query a service interface of an initialized audio client.
+///
+/// Returns `Error::Internal` naming the interface if the service isn't available.
+unsafe fn get_service<T: Interface>(
+    client: WeakPtr<IAudioClient>,
+    name: &str,
+) -> Result<WeakPtr<T>> {
+    let mut service = WeakPtr::<T>::null();
+    let hr = client.GetService(&T::uuidof(), service.mut_void() as _);
+    match WasapiError::from_hr(hr) {
+        Ok(()) if !service.is_null() => Ok(service),
+        Ok(()) => Err(api::Error::Internal {
+            cause: format!("`{}` service is null", name),
+        }),
+        Err(err) => match api::Error::from(err) {
+            api::Error::Internal { cause } => Err(api::Error::Internal {
+                cause: format!("failed to query `{}` service: {}", name, cause),
+            }),
+            err => Err(err),
+        },
+    }
+}
+
+/// Create a new audio client for a physical device.
+unsafe fn activate_client(device: PhysicalDeviceRaw) -> Result<WeakPtr<IAudioClient>> {
+    let mut client = WeakPtr::<IAudioClient>::null();
+    let hr = device.Activate(
+        &IAudioClient::uuidof(),
+        CLSCTX_ALL,
+        ptr::null_mut(),
+        client.mut_void() as *mut _,
+    );
+    if !winerror::SUCCEEDED(hr) {
+        return Err(api::Error::DeviceLost);
+    }
+    Ok(client)
+}
+
+/// Capture time of a capture packet, `None` if flagged as unreliable.
+fn capture_time(flags: DWORD, qpc_position: u64) -> Option<u64> {
+    if flags & AUDCLNT_BUFFERFLAGS_TIMESTAMP_ERROR != 0 {
+        None
+    } else {
+        Some(qpc_position)
+    }
+}
+
+/// Convert a frame count for audio client calls.
+fn frames_to_u32(frames: api::Frames) -> Result<u32> {
+    u32::try_from(frames).map_err(|_| api::Error::Internal {
+        cause: format!("frame count {} exceeds audio client limits", frames),
+    })
+}
+
+/// Convert a frame count returned by the audio client, lossless on all supported targets.
+fn frames_from_u32(frames: u32) -> api::Frames {
+    frames as _
+}
+
+/// Convert a buffer duration range in 100ns units to frames.
+///
+/// Bounds are rounded towards the inside of the range, both bounds are valid buffer sizes.
+fn buffer_range_frames(
+    min_duration: REFERENCE_TIME,
+    max_duration: REFERENCE_TIME,
+    sample_rate: usize,
+) -> (api::Frames, api::Frames) {
+    let rate = sample_rate as u64;
+    let min_frames = (min_duration as u64 * rate).div_ceil(10_000_000);
+    let max_frames = max_duration as u64 * rate / 10_000_000;
+    (min_frames as _, max_frames as _)
+}
+
+fn map_form_factor(form_factor: EndpointFormFactor) -> api::FormFactor {
+    match form_factor {
+        RemoteNetworkDevice => api::FormFactor::Remote,
+        Speakers => api::FormFactor::Speakers,
+        LineLevel => api::FormFactor::LineLevel,
+        Headphones => api::FormFactor::Headphones,
+        Microphone => api::FormFactor::Microphone,
+        Headset => api::FormFactor::Headset,
+        Handset => api::FormFactor::Handset,
+        UnknownDigitalPassthrough => api::FormFactor::DigitalPassthrough,
+        SPDIF => api::FormFactor::Spdif,
+        DigitalAudioDisplayDevice => api::FormFactor::Hdmi,
+        _ => api::FormFactor::Unknown,
+    }
+}
+
 fn map_sharing_mode(sharing: api::SharingMode) -> AUDCLNT_SHAREMODE {
     match sharing {
         api::SharingMode::Exclusive => AUDCLNT_SHAREMODE_EXCLUSIVE,
@@ -178,13 +463,126 @@ fn map_sharing_mode(sharing: api::SharingMode) -> AUDCLNT_SHAREMODE {
 
 type InstanceRaw = WeakPtr<IMMDeviceEnumerator>;
 type PhysicalDeviceRaw = WeakPtr<IMMDevice>;
+
+/// Shared reference of the audio client of a physical device.
+///
+/// The reference is released when the last handle is dropped. Streams created on the
+/// client hold their own reference (see `WeakPtr::add_ref`).
+struct AudioClientRef(WeakPtr<IAudioClient>);
+
+impl AudioClientRef {
+    // Shared with the notification client, interfaces of the multithreaded apartment
+    // can be used from the notification thread.
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn new(audio_client: WeakPtr<IAudioClient>) -> Arc<Self> {
+        Arc::new(AudioClientRef(audio_client))
+    }
+}
+
+impl std::ops::Deref for AudioClientRef {
+    type Target = WeakPtr<IAudioClient>;
+    fn deref(&self) -> &WeakPtr<IAudioClient> {
+        &self.0
+    }
+}
+
+impl std::ops::Drop for AudioClientRef {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { self.0.destroy() };
+        }
+    }
+}
+
+/// Physical device shared between the instance, its notification client and devices.
+///
+/// Mutable state is synchronized as notifications update devices from another thread.
 struct PhysicalDevice {
     device: PhysicalDeviceRaw,
-    audio_client: WeakPtr<IAudioClient>,
-    streams: api::StreamFlags,
+    /// Audio client used for queries, null if the device has never been active.
+    ///
+    /// Re-activating the device replaces the client, the previous client is released
+    /// once queries running on other threads are done with it.
+    audio_client: Mutex<Arc<AudioClientRef>>,
+    streams: AtomicU32,
+    offload_capable: AtomicBool,
+    capabilities: Mutex<Option<api::DeviceCapabilities>>,
 }
 
 impl PhysicalDevice {
+    unsafe fn new(device: PhysicalDeviceRaw, streams: api::StreamFlags) -> Self {
+        PhysicalDevice {
+            device,
+            audio_client: Mutex::new(AudioClientRef::new(WeakPtr::null())),
+            streams: AtomicU32::new(streams.bits()),
+            offload_capable: AtomicBool::new(false),
+            capabilities: Mutex::new(None),
+        }
+    }
+
+    /// Create a new audio client for an active device.
+    unsafe fn activate(&self) {
+        let mut audio_client = WeakPtr::<IAudioClient>::null();
+        let hr = self.device.Activate(
+            &IAudioClient::uuidof(),
+            CLSCTX_ALL,
+            ptr::null_mut(),
+            audio_client.mut_void() as *mut _,
+        );
+        if !winerror::SUCCEEDED(hr) {
+            return;
+        }
+
+        let (audio_client2, hr) = audio_client.cast::<IAudioClient2>();
+        if winerror::SUCCEEDED(hr) {
+            let mut capable = FALSE;
+            let hr = audio_client2.IsOffloadCapable(AudioCategory_Media, &mut capable);
+            self.offload_capable.store(
+                winerror::SUCCEEDED(hr) && capable != FALSE,
+                Ordering::Release,
+            );
+            audio_client2.destroy();
+        }
+        self.replace_audio_client(audio_client);
+        self.invalidate_capabilities();
+    }
+
+    /// Drop the cached capabilities, e.g after the device format changed.
+    fn invalidate_capabilities(&self) {
+        *self.capabilities.lock().unwrap() = None;
+    }
+
+    /// Replace the audio client used for queries, taking ownership of `audio_client`.
+    fn replace_audio_client(&self, audio_client: WeakPtr<IAudioClient>) {
+        *self.audio_client.lock().unwrap() = AudioClientRef::new(audio_client);
+    }
+
+    /// Current audio client, null if the device has never been active.
+    ///
+    /// The client stays alive while the returned reference is held.
+    fn audio_client(&self) -> Arc<AudioClientRef> {
+        self.audio_client.lock().unwrap().clone()
+    }
+
+    /// Current audio client, `Error::DeviceLost` if the device has never been active.
+    ///
+    /// Handles of inactive devices are reported by device events (e.g `DeviceAdded`).
+    fn active_audio_client(&self) -> Result<Arc<AudioClientRef>> {
+        let audio_client = self.audio_client();
+        if audio_client.is_null() {
+            return Err(api::Error::DeviceLost);
+        }
+        Ok(audio_client)
+    }
+
+    fn streams(&self) -> api::StreamFlags {
+        api::StreamFlags::from_bits_truncate(self.streams.load(Ordering::Acquire))
+    }
+
+    fn offload_capable(&self) -> bool {
+        self.offload_capable.load(Ordering::Acquire)
+    }
+
     unsafe fn state(&self) -> u32 {
         let mut state = 0;
         self.device.GetState(&mut state);
@@ -193,22 +591,69 @@ impl PhysicalDevice {
 }
 
 type PhysicalDeviceId = String;
-type PhysialDeviceMap = HashMap<PhysicalDeviceId, Handle<PhysicalDevice>>;
 
-pub struct Session(Option<audio_thread_priority::RtPriorityHandle>);
+/// Physical devices of an instance, addressed by endpoint id or raw handle.
+#[derive(Default)]
+struct PhysicalDeviceMap {
+    ids: BTreeMap<PhysicalDeviceId, api::PhysicalDevice>,
+    devices: HandleTable<PhysicalDevice>,
+}
+
+impl PhysicalDeviceMap {
+    /// Look up a physical device, rejecting unknown handles.
+    fn get(&self, handle: api::PhysicalDevice) -> Result<Arc<PhysicalDevice>> {
+        match self.devices.get(handle) {
+            Some(device) => Ok(device),
+            None => api::Error::validation("Invalid physical device handle"),
+        }
+    }
+}
+
+/// Event callback registration of `Instance::on_event`, unregisters the callback on drop.
+#[must_use]
+pub struct EventGuard {
+    callback: EventCallback,
+    generation: Arc<AtomicU64>,
+    /// Generation of the registration, later registrations aren't affected by the guard.
+    registered: u64,
+}
+
+impl std::ops::Drop for EventGuard {
+    fn drop(&mut self) {
+        let mut callback = self.callback.lock().unwrap();
+        if self.generation.load(Ordering::Acquire) == self.registered {
+            *callback = None;
+        }
+    }
+}
+
+pub struct Session {
+    rt_handle: Option<audio_thread_priority::RtPriorityHandle>,
+    /// Thread affinity before creating the session.
+    prev_affinity: Option<DWORD_PTR>,
+}
 
 impl std::ops::Drop for Session {
     fn drop(&mut self) {
-        if let Some(handle) = self.0.take() {
+        if let Some(handle) = self.rt_handle.take() {
             audio_thread_priority::demote_current_thread_from_real_time(handle).unwrap();
         }
+        if let Some(affinity) = self.prev_affinity.take() {
+            unsafe {
+                SetThreadAffinityMask(GetCurrentThread(), affinity);
+            }
+        }
     }
 }
 
 pub struct Instance {
     raw: InstanceRaw,
-    physical_devices: Mutex<PhysialDeviceMap>,
+    physical_devices: Arc<Mutex<PhysicalDeviceMap>>,
     notifier: WeakPtr<NotificationClient>,
+    event_callback: EventCallback,
+    /// Incremented on each callback registration, see `EventGuard`.
+    event_generation: Arc<AtomicU64>,
+    resample_policy: Option<api::ResamplePolicy>,
 }
 
 impl api::Instance for Instance {
@@ -220,30 +665,16 @@ impl api::Instance for Instance {
             driver_id: api::DriverId::Wasapi,
             stream_mode: api::StreamMode::Polling,
             sharing: api::SharingModeFlags::CONCURRENT | api::SharingModeFlags::EXCLUSIVE,
+            supports_duplex: true,
+            supports_spatial_audio: true,
         }
     }
 
     unsafe fn create(_: &str) -> Self {
         CoInitializeEx(ptr::null_mut(), COINIT_MULTITHREADED);
 
-        let mut instance = InstanceRaw::null();
-        let _hr = CoCreateInstance(
-            &CLSID_MMDeviceEnumerator,
-            ptr::null_mut(),
-            CLSCTX_ALL,
-            &IMMDeviceEnumerator::uuidof(),
-            instance.mut_void(),
-        );
-
-        let mut physical_devices = HashMap::new();
-        Self::enumerate_physical_devices_by_flow(&mut physical_devices, instance, eCapture);
-        Self::enumerate_physical_devices_by_flow(&mut physical_devices, instance, eRender);
-
-        Instance {
-            raw: instance,
-            physical_devices: Mutex::new(physical_devices),
-            notifier: WeakPtr::null(),
-        }
+        let (instance, _hr) = Self::create_enumerator();
+        Self::from_enumerator(instance)
     }
 
     unsafe fn enumerate_physical_devices(&self) -> Vec<api::PhysicalDevice> {
@@ -253,10 +684,11 @@ impl api::Instance for Instance {
         Self::enumerate_physical_devices_by_flow(&mut physical_devices, self.raw, eRender);
 
         physical_devices
-            .values()
-            .filter_map(|device| {
+            .devices
+            .iter()
+            .filter_map(|(handle, device)| {
                 if device.state() & DEVICE_STATE_ACTIVE != 0 {
-                    Some(device.raw())
+                    Some(handle)
                 } else {
                     None
                 }
@@ -273,7 +705,8 @@ impl api::Instance for Instance {
             None
         } else {
             let id = Self::get_physical_device_id(device);
-            Some(self.physical_devices.lock().unwrap()[&id].raw())
+            device.destroy();
+            self.physical_devices.lock().unwrap().ids.get(&id).copied()
         }
     }
 
@@ -286,7 +719,8 @@ impl api::Instance for Instance {
             None
         } else {
             let id = Self::get_physical_device_id(device);
-            Some(self.physical_devices.lock().unwrap()[&id].raw())
+            device.destroy();
+            self.physical_devices.lock().unwrap().ids.get(&id).copied()
         }
     }
 
@@ -296,7 +730,7 @@ impl api::Instance for Instance {
     ) -> Result<api::PhysicalDeviceProperties> {
         type PropertyStore = WeakPtr<IPropertyStore>;
 
-        let physical_device = Handle::<PhysicalDevice>::from_raw(physical_device);
+        let physical_device = self.physical_device(physical_device)?;
 
         let mut store = PropertyStore::null();
         physical_device
@@ -313,19 +747,48 @@ impl api::Instance for Instance {
             string_from_wstr(os_str)
         };
 
-        let _form_factor = {
+        let form_factor = {
             let mut value = mem::MaybeUninit::uninit();
-            store.GetValue(
+            let hr = store.GetValue(
                 &PKEY_AudioEndpoint_FormFactor as *const _ as *const _,
                 value.as_mut_ptr(),
             );
-            *value.assume_init().data.uintVal()
+            if winerror::SUCCEEDED(hr) && value.assume_init_ref().vt == VT_UI4 as _ {
+                map_form_factor(*value.assume_init().data.uintVal())
+            } else {
+                api::FormFactor::Unknown
+            }
+        };
+
+        let roles = {
+            let id = Self::get_physical_device_id(physical_device.device);
+            let flow = if physical_device.streams().contains(api::StreamFlags::INPUT) {
+                eCapture
+            } else {
+                eRender
+            };
+
+            let mut roles = api::DeviceRoleFlags::empty();
+            if self.default_physical_device_id(flow, eConsole).as_ref() == Some(&id) {
+                roles |= api::DeviceRoleFlags::CONSOLE_DEFAULT;
+            }
+            if self
+                .default_physical_device_id(flow, eCommunications)
+                .as_ref()
+                == Some(&id)
+            {
+                roles |= api::DeviceRoleFlags::COMMUNICATIONS_DEFAULT;
+            }
+            roles
         };
 
         Ok(api::PhysicalDeviceProperties {
             device_name,
-            form_factor: api::FormFactor::Unknown, // todo
-            streams: physical_device.streams,
+            form_factor,
+            streams: physical_device.streams(),
+            offload_capable: physical_device.offload_capable(),
+            roles,
+            adapter_id: adapter_id(physical_device.device).unwrap_or_default(),
         })
     }
 
@@ -333,11 +796,8 @@ impl api::Instance for Instance {
         &self,
         physical_device: api::PhysicalDevice,
     ) -> Result<api::FrameDesc> {
-        let physical_device = Handle::<PhysicalDevice>::from_raw(physical_device);
-
-        let mut mix_format = ptr::null_mut();
-        physical_device.audio_client.GetMixFormat(&mut mix_format);
-        map_waveformat(mix_format)
+        let physical_device = self.physical_device(physical_device)?;
+        client_format(**physical_device.audio_client())
     }
 
     unsafe fn create_device(
@@ -346,121 +806,136 @@ impl api::Instance for Instance {
         channels: api::Channels,
         callback: api::StreamCallback,
     ) -> Result<Device> {
-        if !channels.input.is_empty() && !channels.output.is_empty() {
-            // no duplex
-            return api::Error::validation("Duplex not supported");
-        }
-
-        let use_default_sample_rate = desc.sample_desc.sample_rate == api::DEFAULT_SAMPLE_RATE;
-        if use_default_sample_rate && desc.sharing == api::SharingMode::Exclusive {
-            return api::Error::validation(
-                "Default sample rate can't be used with exclusive sharing mode",
-            );
-        }
-
-        let physical_device = Handle::<PhysicalDevice>::from_raw(desc.physical_device);
-        let sharing = map_sharing_mode(desc.sharing);
-
-        let fence = Fence::create(false, false);
-
-        let sample_rate = if use_default_sample_rate {
-            self.physical_device_default_concurrent_format(desc.physical_device)?
-                .sample_rate
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("create_device", sharing = ?desc.sharing).entered();
+
+        let (frame_desc, mix_format, stream_flags) =
+            self.negotiate_device_desc(&desc, &channels)?;
+        // Negotiation picks the mono device layout if the output needs to be downmixed.
+        let downmix = if channels.input.is_empty() && frame_desc.channels != channels.output {
+            Some(frame_desc.format)
         } else {
-            desc.sample_desc.sample_rate
+            None
         };
+        let channel_map = desc
+            .channel_map
+            .clone()
+            .map(|map| (map, desc.sample_desc.format));
+        let physical_device = self.physical_device(desc.physical_device)?;
+        let sharing = map_sharing_mode(desc.sharing);
+        let fence = Fence::create(false, false);
 
-        let frame_desc = api::FrameDesc {
-            format: desc.sample_desc.format,
-            channels: if !channels.input.is_empty() {
-                channels.input
+        let mut config = StreamConfig {
+            sharing,
+            stream_flags,
+            mix_format,
+            session_guid: desc.session_guid.map(Guid::from),
+            // Reference time is given in 100ns units.
+            buffer_duration: desc
+                .target_latency
+                .map_or(0, |latency| (latency.as_nanos() / 100) as REFERENCE_TIME),
+            conversions: if downmix.is_some() || channel_map.is_some() {
+                api::ConversionFlags::CHANNELS
             } else {
-                channels.output
+                api::ConversionFlags::empty()
             },
-            sample_rate,
         };
-        let mix_format = map_frame_desc(&frame_desc).unwrap(); // todo
-        let _hr = physical_device.audio_client.Initialize(
-            sharing,
-            AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
-            0,
-            0,
-            &mix_format as *const _ as _,
-            ptr::null(),
-        );
-
-        physical_device.audio_client.SetEventHandle(fence.0);
-
-        let mut mix_format = ptr::null_mut();
-        physical_device.audio_client.GetMixFormat(&mut mix_format);
-        let frame_desc = map_waveformat(mix_format).unwrap();
-
-        let (properties, device_stream) = if !channels.input.is_empty() {
-            let mut capture_client = WeakPtr::<IAudioCaptureClient>::null();
-            physical_device.audio_client.GetService(
-                &IAudioCaptureClient::uuidof(),
-                capture_client.mut_void() as _,
-            );
-            let buffer_size = {
-                let mut size = 0;
-                physical_device.audio_client.GetBufferSize(&mut size);
-                size
-            };
-
-            let properties = api::StreamProperties {
-                channels: frame_desc.channels,
-                sample_rate: frame_desc.sample_rate,
-                buffer_size: buffer_size as _,
-            };
-            let device_stream = DeviceStream::Input {
-                client: capture_client,
-            };
-
-            (properties, device_stream)
+        let client = if sharing == AUDCLNT_SHAREMODE_EXCLUSIVE {
+            Device::initialize_exclusive_client(&physical_device, &mut config)?
         } else {
-            let mut render_client = WeakPtr::<IAudioRenderClient>::null();
-            physical_device
-                .audio_client
-                .GetService(&IAudioRenderClient::uuidof(), render_client.mut_void() as _);
-            let buffer_size = {
-                let mut size = 0;
-                physical_device.audio_client.GetBufferSize(&mut size);
-                size
-            };
-
-            let properties = api::StreamProperties {
-                channels: frame_desc.channels,
-                sample_rate: frame_desc.sample_rate,
-                buffer_size: buffer_size as _,
-            };
-            let device_stream = DeviceStream::Output {
-                client: render_client,
-                buffer_size,
+            let client = physical_device.active_audio_client()?;
+            Device::initialize_client(**client, &config)?;
+            client.add_ref()
+        };
+        // Duplex devices are driven by the output stream.
+        let (properties, device_stream) =
+            match Device::setup_stream(client, fence, &config, channels.output.is_empty()) {
+                Ok(stream) => stream,
+                Err(err) => {
+                    client.destroy();
+                    return Err(err);
+                }
             };
-
-            (properties, device_stream)
+        let callback = Device::wrap_callback(callback, downmix, &channel_map, &properties);
+        let duplex = if !channels.input.is_empty() && !channels.output.is_empty() {
+            let input_device = self.physical_device(self.duplex_input_device(&desc).unwrap())?;
+            match DuplexInput::create(input_device.device, fence, &config) {
+                Ok(duplex) => Some(duplex),
+                Err(err) => {
+                    device_stream.release();
+                    client.destroy();
+                    return Err(err);
+                }
+            }
+        } else {
+            None
         };
 
         Ok(Device {
-            client: physical_device.audio_client,
+            instance: self.raw,
+            physical_devices: self.physical_devices.clone(),
+            physical_device_id: Self::get_physical_device_id(physical_device.device),
+            config,
+            running: Cell::new(false),
+            last_submit: Cell::new(None),
+            fade: Cell::new(None),
+            stop_request: Arc::new(AtomicBool::new(false)),
+            event_callback: self.event_callback.clone(),
+            capture_position: None,
+            capture_scratch: Vec::new(),
+            render_scratch: Vec::new(),
+            auto_recover: desc.auto_recover,
+            discontinuities: 0,
+            last_callback_duration: time::Duration::from_secs(0),
+            acquire_timeout: desc.acquire_timeout_ms(&properties),
+            alertable_wait: desc.alertable_wait,
+            client,
             fence,
             device_stream,
+            duplex,
+            session_notifier: SessionNotifier::register(client, self.event_callback.clone()),
             callback,
+            downmix,
+            channel_map,
             properties,
         })
     }
 
-    unsafe fn create_session(&self, sample_rate: usize) -> Result<Session> {
+    unsafe fn create_session_with_affinity(
+        &self,
+        sample_rate: usize,
+        thread_affinity: Option<api::CoreMask>,
+    ) -> Result<Session> {
         if sample_rate == api::DEFAULT_SAMPLE_RATE {
             return api::Error::validation(
                 "Default sample rate can't be used for session creation",
             );
         }
 
+        let mut session = Session {
+            rt_handle: None,
+            prev_affinity: None,
+        };
+        if let Some(mask) = thread_affinity {
+            let prev = SetThreadAffinityMask(GetCurrentThread(), mask as DWORD_PTR);
+            if prev == 0 {
+                return api::Error::validation(format!(
+                    "Thread affinity {:#x} doesn't contain any processor of the system",
+                    mask
+                ));
+            }
+            session.prev_affinity = Some(prev);
+        }
+
+        // Dropping the session on failure restores the previous affinity.
         let rt_handle =
             audio_thread_priority::promote_current_thread_to_real_time(0, sample_rate as _)
-                .unwrap();
-        Ok(Session(Some(rt_handle)))
+                .map_err(|err| api::Error::Internal {
+                    cause: format!("failed to promote the audio thread: {}", err),
+                })?;
+        session.rt_handle = Some(rt_handle);
+
+        Ok(session)
     }
 
     unsafe fn set_event_callback<F>(&mut self, callback: Option<F>) -> Result<()>
@@ -471,10 +946,25 @@ impl api::Instance for Instance {
             self.raw
                 .UnregisterEndpointNotificationCallback(self.notifier.as_mut_ptr() as *mut _);
             self.notifier.as_unknown().Release();
+            self.notifier = WeakPtr::null();
+        }
+
+        let has_callback = callback.is_some();
+        {
+            let mut event_callback = self.event_callback.lock().unwrap();
+            *event_callback = match callback {
+                Some(callback) => Some(Box::new(callback)),
+                None => None,
+            };
+            self.event_generation.fetch_add(1, Ordering::AcqRel);
         }
 
-        if let Some(callback) = callback {
-            self.notifier = WeakPtr::from_raw(NotificationClient::create_raw(Box::new(callback)));
+        if has_callback {
+            self.notifier = WeakPtr::from_raw(NotificationClient::new(
+                self.raw,
+                self.physical_devices.clone(),
+                self.event_callback.clone(),
+            ));
             self.raw
                 .RegisterEndpointNotificationCallback(self.notifier.as_mut_ptr() as *mut _);
         }
@@ -488,13 +978,22 @@ impl api::Instance for Instance {
         sharing: api::SharingMode,
         frame_desc: api::FrameDesc,
     ) -> bool {
-        let physical_device = Handle::<PhysicalDevice>::from_raw(physical_device);
+        let physical_device = match self.physical_device(physical_device) {
+            Ok(device) => device,
+            Err(_) => return false,
+        };
 
-        let wave_format = map_frame_desc(&frame_desc).unwrap(); // todo
+        if frame_desc.validate().is_err() {
+            return false;
+        }
+        let wave_format = match map_frame_desc(&frame_desc) {
+            Some(format) => format,
+            None => return false,
+        };
         let sharing = map_sharing_mode(sharing);
 
         let mut closest_format = ptr::null_mut();
-        let hr = physical_device.audio_client.IsFormatSupported(
+        let hr = physical_device.audio_client().IsFormatSupported(
             sharing,
             &wave_format as *const _ as _,
             &mut closest_format,
@@ -505,31 +1004,777 @@ impl api::Instance for Instance {
 }
 
 impl Instance {
-    unsafe fn get_physical_device_id(device: PhysicalDeviceRaw) -> String {
-        let mut str_id = ptr::null_mut();
-        device.GetId(&mut str_id);
-        let mut len = 0;
-        while *str_id.offset(len) != 0 {
-            len += 1;
+    /// Create an instance, retrying if the audio service isn't available yet.
+    ///
+    /// Creation of the device enumerator may transiently fail while the audio service
+    /// is starting up. Failed attempts will be retried up to `attempts` times in total,
+    /// doubling the `delay` between each attempt.
+    ///
+    /// # Safety
+    ///
+    /// Same requirements as `api::Instance::create`.
+    pub unsafe fn create_with_retry(
+        _name: &str,
+        attempts: usize,
+        delay: time::Duration,
+    ) -> Result<Self> {
+        CoInitializeEx(ptr::null_mut(), COINIT_MULTITHREADED);
+
+        let mut delay = delay;
+        let mut hr = winerror::S_OK;
+        for attempt in 0..attempts {
+            if attempt > 0 {
+                thread::sleep(delay);
+                delay *= 2;
+            }
+
+            let (instance, result) = Self::create_enumerator();
+            if winerror::SUCCEEDED(result) {
+                return Ok(Self::from_enumerator(instance));
+            }
+            hr = result;
         }
-        let name: OsString = OsStringExt::from_wide(slice::from_raw_parts(str_id, len as _));
-        name.into_string().unwrap()
+
+        Err(api::Error::Internal {
+            cause: format!("failed to create device enumerator ({:#x})", hr),
+        })
     }
 
-    unsafe fn enumerate_physical_devices_by_flow(
-        physical_devices: &mut PhysialDeviceMap,
-        instance: InstanceRaw,
-        ty: EDataFlow,
-    ) {
-        type DeviceCollection = WeakPtr<IMMDeviceCollection>;
+    unsafe fn create_enumerator() -> WasapiResult<InstanceRaw> {
+        let mut instance = InstanceRaw::null();
+        let hr = CoCreateInstance(
+            &CLSID_MMDeviceEnumerator,
+            ptr::null_mut(),
+            CLSCTX_ALL,
+            &IMMDeviceEnumerator::uuidof(),
+            instance.mut_void(),
+        );
+        (instance, hr)
+    }
 
-        let stream_flags = match ty {
-            eCapture => api::StreamFlags::INPUT,
-            eRender => api::StreamFlags::OUTPUT,
-            _ => unreachable!(),
-        };
+    unsafe fn from_enumerator(instance: InstanceRaw) -> Self {
+        let mut physical_devices = PhysicalDeviceMap::default();
+        Self::enumerate_physical_devices_by_flow(&mut physical_devices, instance, eCapture);
+        Self::enumerate_physical_devices_by_flow(&mut physical_devices, instance, eRender);
+        // Shared with the notification client, interfaces of the multithreaded apartment
+        // can be used from the notification thread.
+        #[allow(clippy::arc_with_non_send_sync)]
+        let physical_devices = Arc::new(Mutex::new(physical_devices));
 
-        let collection = {
+        Instance {
+            raw: instance,
+            physical_devices,
+            notifier: WeakPtr::null(),
+            event_callback: Arc::new(Mutex::new(None)),
+            event_generation: Arc::new(AtomicU64::new(0)),
+            resample_policy: None,
+        }
+    }
+
+    /// Register a callback for device events, which stays registered until the returned
+    /// guard is dropped.
+    ///
+    /// Replaces previously registered callbacks, guards of replaced callbacks don't
+    /// unregister the new callback. Only supported by WASAPI.
+    ///
+    /// # Safety
+    ///
+    /// Same requirements as `api::Instance::set_event_callback`.
+    pub unsafe fn on_event(
+        &mut self,
+        callback: Box<dyn FnMut(api::Event) + Send>,
+    ) -> Result<EventGuard> {
+        api::Instance::set_event_callback(self, Some(callback))?;
+        Ok(EventGuard {
+            callback: self.event_callback.clone(),
+            generation: self.event_generation.clone(),
+            registered: self.event_generation.load(Ordering::Acquire),
+        })
+    }
+
+    /// Set the resample policy applied to all subsequently created concurrent streams.
+    ///
+    /// `None` (default) uses the `src_quality` of each device description.
+    pub fn set_resample_policy(&mut self, policy: Option<api::ResamplePolicy>) {
+        self.resample_policy = policy;
+    }
+
+    /// Count the known physical devices without allocating the device list.
+    ///
+    /// Unlike `enumerate_physical_devices` this doesn't query for newly added devices.
+    ///
+    /// # Safety
+    ///
+    /// Issues COM calls, COM **must** be initialized on the calling thread.
+    pub unsafe fn device_counts(&self) -> api::DeviceCounts {
+        let physical_devices = self.physical_devices.lock().unwrap();
+
+        let mut counts = api::DeviceCounts::default();
+        for (_, device) in physical_devices.devices.iter() {
+            let active = device.state() & DEVICE_STATE_ACTIVE != 0;
+            if device.streams().contains(api::StreamFlags::INPUT) {
+                counts.total_inputs += 1;
+                if active {
+                    counts.active_inputs += 1;
+                }
+            }
+            if device.streams().contains(api::StreamFlags::OUTPUT) {
+                counts.total_outputs += 1;
+                if active {
+                    counts.active_outputs += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    /// Get the device and audio engine periods of a physical device.
+    ///
+    /// Periods are calculated for the default concurrent format of the device.
+    /// If the platform doesn't support configuring the engine period (prior to Windows 10),
+    /// all engine periods are equal to the default device period.
+    ///
+    /// ## Validation
+    ///
+    /// - `physical_device` **must** be a valid handle.
+    pub unsafe fn physical_device_engine_periods(
+        &self,
+        physical_device: api::PhysicalDevice,
+    ) -> Result<api::EnginePeriods> {
+        let physical_device = self.physical_device(physical_device)?;
+        let (periods, _) = Self::engine_periods(**physical_device.audio_client())?;
+        Ok(periods)
+    }
+
+    /// Find the active output device with the smallest achievable period.
+    ///
+    /// Periods are compared in time, based on the minimum of the device and
+    /// audio engine periods for the default concurrent format of each device.
+    ///
+    /// # Safety
+    ///
+    /// Issues COM calls, COM **must** be initialized on the calling thread.
+    pub unsafe fn lowest_latency_output_device(&self) -> Option<api::PhysicalDevice> {
+        let physical_devices = self.physical_devices.lock().unwrap();
+
+        let mut lowest: Option<(api::PhysicalDevice, f64)> = None;
+        for (handle, device) in physical_devices.devices.iter() {
+            if device.state() & DEVICE_STATE_ACTIVE == 0
+                || !device.streams().contains(api::StreamFlags::OUTPUT)
+            {
+                continue;
+            }
+
+            let (periods, sample_rate) = match Self::engine_periods(**device.audio_client()) {
+                Ok(periods) => periods,
+                Err(_) => continue,
+            };
+            let min_period = periods.min_device_period.min(periods.min_period);
+            let latency = min_period as f64 / sample_rate as f64;
+
+            match lowest {
+                Some((_, lowest_latency)) if lowest_latency <= latency => (),
+                _ => lowest = Some((handle, latency)),
+            }
+        }
+
+        lowest.map(|(device, _)| device)
+    }
+
+    /// Create multiple logical devices at once (e.g input and output on separate endpoints).
+    ///
+    /// Each description is paired with the callback at the same index.
+    /// Returns the devices together with a session for the calling thread, created for the
+    /// highest sample rate of the devices. All devices can be polled from this thread,
+    /// instead of running a realtime thread per device.
+    /// If the creation of any device fails, all devices created so far are released.
+    ///
+    /// ## Validation
+    ///
+    /// - `descs` **must** not be empty.
+    /// - `descs` and `callbacks` **must** have the same length.
+    /// - Each description **must** fulfill the validation requirements of `create_device`.
+    pub unsafe fn create_devices(
+        &self,
+        descs: &[(api::DeviceDesc, api::Channels)],
+        callbacks: Vec<api::StreamCallback>,
+    ) -> Result<(Session, Vec<Device>)> {
+        if descs.is_empty() {
+            return api::Error::validation("No device descriptions");
+        }
+        if descs.len() != callbacks.len() {
+            return api::Error::validation(format!(
+                "Number of device descriptions ({}) and callbacks ({}) don't match",
+                descs.len(),
+                callbacks.len()
+            ));
+        }
+
+        let devices = descs
+            .iter()
+            .zip(callbacks)
+            .map(|((desc, channels), callback)| {
+                api::Instance::create_device(self, desc.clone(), *channels, callback)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let sample_rate = devices
+            .iter()
+            .map(|device| device.properties.sample_rate)
+            .max()
+            .unwrap();
+        let session = api::Instance::create_session(self, sample_rate)?;
+
+        Ok((session, devices))
+    }
+
+    /// Validate a device description without creating a device.
+    ///
+    /// Runs the format and period checks of `create_device` and returns the configuration
+    /// the device would be created with. Intended for previewing settings before applying them.
+    ///
+    /// Returns `Error::Validation` if the device couldn't be created with the description.
+    ///
+    /// ## Validation
+    ///
+    /// - `desc.physical_device` **must** be a valid handle.
+    pub unsafe fn validate_device_desc(
+        &self,
+        desc: &api::DeviceDesc,
+        channels: &api::Channels,
+    ) -> Result<api::NegotiatedConfig> {
+        let (frame_desc, mix_format, stream_flags) = self.negotiate_device_desc(desc, channels)?;
+        let physical_device = self.physical_device(desc.physical_device)?;
+
+        if !channels.input.is_empty() {
+            let input_device = if channels.output.is_empty() {
+                desc.physical_device
+            } else {
+                self.duplex_input_device(desc).unwrap()
+            };
+            let input_device = self.physical_device(input_device)?;
+            if !input_device.streams().contains(api::StreamFlags::INPUT) {
+                return api::Error::validation("Physical device doesn't support input streams");
+            }
+        }
+        if !channels.output.is_empty()
+            && !physical_device.streams().contains(api::StreamFlags::OUTPUT)
+        {
+            return api::Error::validation("Physical device doesn't support output streams");
+        }
+
+        // The audio engine converts any PCM format if requested.
+        if stream_flags & AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM == 0 {
+            let mut closest_format = ptr::null_mut();
+            let hr = physical_device.audio_client().IsFormatSupported(
+                map_sharing_mode(desc.sharing),
+                &mix_format as *const _ as _,
+                &mut closest_format,
+            );
+            if !closest_format.is_null() {
+                let closest = map_waveformat(closest_format);
+                CoTaskMemFree(closest_format as _);
+                if let Ok(closest) = closest {
+                    return api::Error::validation(format!(
+                        "Format {} isn't supported, closest match is {}",
+                        frame_desc, closest
+                    ));
+                }
+            }
+            if hr != winerror::S_OK {
+                return api::Error::validation(format!("Format {} isn't supported", frame_desc));
+            }
+        }
+
+        let mut default_period = 0;
+        let mut min_period = 0;
+        WasapiError::from_hr(
+            physical_device
+                .audio_client()
+                .GetDevicePeriod(&mut default_period, &mut min_period),
+        )?;
+        // Reference time is given in 100ns units.
+        let buffer_size = (default_period as u64 * frame_desc.sample_rate as u64)
+            .div_ceil(10_000_000) as api::Frames;
+
+        Ok(api::NegotiatedConfig {
+            sharing: desc.sharing,
+            frame_desc,
+            buffer_size,
+        })
+    }
+
+    /// Look up a physical device, rejecting unknown handles.
+    fn physical_device(&self, handle: api::PhysicalDevice) -> Result<Arc<PhysicalDevice>> {
+        self.physical_devices.lock().unwrap().get(handle)
+    }
+
+    /// Physical device of the input stream for duplex devices.
+    unsafe fn duplex_input_device(&self, desc: &api::DeviceDesc) -> Option<api::PhysicalDevice> {
+        desc.input_device
+            .or_else(|| api::Instance::default_physical_input_device(self))
+    }
+
+    /// Validate a device description and derive the stream format and flags.
+    unsafe fn negotiate_device_desc(
+        &self,
+        desc: &api::DeviceDesc,
+        channels: &api::Channels,
+    ) -> Result<(api::FrameDesc, WAVEFORMATEXTENSIBLE, DWORD)> {
+        if !channels.input.is_empty()
+            && !channels.output.is_empty()
+            && !<Self as api::Instance>::properties().supports_duplex
+        {
+            return api::Error::validation("Duplex not supported");
+        }
+        if channels.input.is_empty() && channels.output.is_empty() {
+            return api::Error::validation("No input or output channels specified");
+        }
+        if !channels.input.is_empty() && !channels.output.is_empty() {
+            if desc.sharing != api::SharingMode::Concurrent {
+                return api::Error::validation("Duplex devices require concurrent sharing mode");
+            }
+            if channels.input != channels.output {
+                return api::Error::validation(
+                    "Duplex devices require equal input and output channels",
+                );
+            }
+            if self.duplex_input_device(desc).is_none() {
+                return api::Error::validation("No input device found for duplex device");
+            }
+        }
+
+        let use_default_sample_rate = desc.sample_desc.sample_rate == api::DEFAULT_SAMPLE_RATE;
+        if use_default_sample_rate && desc.sharing == api::SharingMode::Exclusive {
+            return api::Error::validation(
+                "Default sample rate can't be used with exclusive sharing mode",
+            );
+        }
+
+        let sample_rate = if use_default_sample_rate {
+            api::Instance::physical_device_default_concurrent_format(self, desc.physical_device)?
+                .sample_rate
+        } else {
+            desc.sample_desc.sample_rate
+        };
+
+        let stereo = api::ChannelMask::FRONT_LEFT | api::ChannelMask::FRONT_RIGHT;
+        let downmix = if desc.auto_downmix && channels.input.is_empty() && channels.output == stereo
+        {
+            if desc.channel_map.is_some() {
+                return api::Error::validation("`auto_downmix` can't be used with `channel_map`");
+            }
+            if !matches!(desc.sample_desc.format, api::Format::F32 | api::Format::I16) {
+                return api::Error::validation(format!(
+                    "Sample format {:?} isn't supported for downmixing",
+                    desc.sample_desc.format
+                ));
+            }
+            let device_channels = api::Instance::physical_device_default_concurrent_format(
+                self,
+                desc.physical_device,
+            )?
+            .channels;
+            Some(device_channels).filter(|channels| channels.bits().count_ones() == 1)
+        } else {
+            None
+        };
+
+        let frame_desc = api::FrameDesc {
+            format: desc.sample_desc.format,
+            channels: if !channels.input.is_empty() {
+                channels.input
+            } else if let Some(mono) = downmix {
+                mono
+            } else {
+                channels.output
+            },
+            sample_rate,
+            endianness: api::Endianness::Little,
+        };
+        frame_desc.validate()?;
+        if frame_desc.format == api::Format::U32 {
+            return Err(api::Error::Unsupported {
+                description: "unsigned 32 bit samples aren't supported by WASAPI".into(),
+            });
+        }
+        let mix_format = match map_frame_desc(&frame_desc) {
+            Some(format) => format,
+            None => {
+                return api::Error::validation(format!(
+                    "Sample format {:?} isn't supported by WASAPI",
+                    frame_desc.format
+                ))
+            }
+        };
+
+        if let Some(map) = &desc.channel_map {
+            let num_channels = frame_desc.num_channels();
+            if map.is_empty() || map.len() > api::ChannelMask::MAX_UNASSIGNED {
+                return api::Error::validation(format!(
+                    "Channel map must contain between 1 and {} channels",
+                    api::ChannelMask::MAX_UNASSIGNED
+                ));
+            }
+            if let Some(&index) = map.iter().find(|&&index| index >= num_channels) {
+                return api::Error::validation(format!(
+                    "Channel map index {} exceeds the {} device channels",
+                    index, num_channels
+                ));
+            }
+            if (1..map.len()).any(|i| map[..i].contains(&map[i])) {
+                return api::Error::validation("Channel map contains duplicate channels");
+            }
+        }
+
+        let mut stream_flags = AUDCLNT_STREAMFLAGS_EVENTCALLBACK;
+        if desc.no_persist {
+            stream_flags |= AUDCLNT_STREAMFLAGS_NOPERSIST;
+        }
+        if desc.sharing == api::SharingMode::Concurrent {
+            let src_quality = match self.resample_policy {
+                None => desc.src_quality,
+                Some(api::ResamplePolicy::EnginePreferred) => match desc.src_quality {
+                    api::SrcQuality::None => api::SrcQuality::Default,
+                    quality => quality,
+                },
+                Some(api::ResamplePolicy::NativeOnly) => {
+                    let native_rate = api::Instance::physical_device_default_concurrent_format(
+                        self,
+                        desc.physical_device,
+                    )?
+                    .sample_rate;
+                    if sample_rate != native_rate {
+                        return api::Error::validation(format!(
+                            "Sample rate {} doesn't match the native rate {}",
+                            sample_rate, native_rate
+                        ));
+                    }
+                    api::SrcQuality::None
+                }
+            };
+            stream_flags |= match src_quality {
+                api::SrcQuality::None => 0,
+                api::SrcQuality::Linear => AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM,
+                api::SrcQuality::Default => {
+                    AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM | AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY
+                }
+            };
+        }
+
+        Ok((frame_desc, mix_format, stream_flags))
+    }
+
+    /// Query engine periods in frames together with the sample rate of the mix format.
+    unsafe fn engine_periods(
+        audio_client: WeakPtr<IAudioClient>,
+    ) -> Result<(api::EnginePeriods, usize)> {
+        let mut mix_format = ptr::null_mut();
+        let hr = audio_client.GetMixFormat(&mut mix_format);
+        if !winerror::SUCCEEDED(hr) {
+            return Err(api::Error::Internal {
+                cause: format!("failed to query mix format ({:#x})", hr),
+            });
+        }
+        let sample_rate = (*mix_format).nSamplesPerSec as i64;
+
+        let mut default_device_period = 0;
+        let mut min_device_period = 0;
+        audio_client.GetDevicePeriod(&mut default_device_period, &mut min_device_period);
+
+        // Reference time is given in 100ns units.
+        let to_frames = |period: i64| (period * sample_rate / 10_000_000) as api::Frames;
+        let default_device_period = to_frames(default_device_period);
+        let min_device_period = to_frames(min_device_period);
+
+        let mut periods = api::EnginePeriods {
+            default_device_period,
+            min_device_period,
+            default_period: default_device_period,
+            fundamental_period: default_device_period,
+            min_period: default_device_period,
+            max_period: default_device_period,
+        };
+
+        let (audio_client3, hr) = audio_client.cast::<IAudioClient3>();
+        if winerror::SUCCEEDED(hr) {
+            let mut default_period = 0;
+            let mut fundamental_period = 0;
+            let mut min_period = 0;
+            let mut max_period = 0;
+            let hr = audio_client3.GetSharedModeEnginePeriod(
+                mix_format,
+                &mut default_period,
+                &mut fundamental_period,
+                &mut min_period,
+                &mut max_period,
+            );
+            if winerror::SUCCEEDED(hr) {
+                periods.default_period = default_period as _;
+                periods.fundamental_period = fundamental_period as _;
+                periods.min_period = min_period as _;
+                periods.max_period = max_period as _;
+            }
+            audio_client3.destroy();
+        }
+        CoTaskMemFree(mix_format as _);
+
+        Ok((periods, sample_rate as _))
+    }
+
+    /// Range of buffer sizes in frames accepted by event driven exclusive streams.
+    ///
+    /// Returns the minimum and maximum buffer size of the physical device for the given format.
+    /// Requires `IAudioClient2` (Windows 8), returns `Error::Unsupported` otherwise.
+    /// Returns `Error::DeviceLost` for devices which have never been active.
+    ///
+    /// ## Validation
+    ///
+    /// - `physical_device` **must** be a valid handle.
+    /// - `frame_desc` **must** be a format supported by WASAPI, returns `Error::Validation` otherwise.
+    pub unsafe fn physical_device_exclusive_buffer_range(
+        &self,
+        physical_device: api::PhysicalDevice,
+        frame_desc: api::FrameDesc,
+    ) -> Result<(api::Frames, api::Frames)> {
+        let physical_device = self.physical_device(physical_device)?;
+        frame_desc.validate()?;
+        let wave_format = match map_frame_desc(&frame_desc) {
+            Some(format) => format,
+            None => {
+                return api::Error::validation(format!(
+                    "Frame description {:?} isn't supported by WASAPI",
+                    frame_desc
+                ))
+            }
+        };
+
+        let (audio_client2, hr) = physical_device
+            .active_audio_client()?
+            .cast::<IAudioClient2>();
+        if !winerror::SUCCEEDED(hr) {
+            return Err(api::Error::Unsupported {
+                description: "buffer size limits require `IAudioClient2`".into(),
+            });
+        }
+        let mut min_duration = 0;
+        let mut max_duration = 0;
+        let hr = audio_client2.GetBufferSizeLimits(
+            &wave_format as *const _ as _,
+            TRUE,
+            &mut min_duration,
+            &mut max_duration,
+        );
+        audio_client2.destroy();
+        WasapiError::from_hr(hr)?;
+
+        Ok(buffer_range_frames(
+            min_duration,
+            max_duration,
+            frame_desc.sample_rate,
+        ))
+    }
+
+    /// Current peak level of a physical device in the range `[0.0, 1.0]`.
+    ///
+    /// The level is measured on the endpoint, covering all streams of the device.
+    /// No stream needs to be created for monitoring the device activity.
+    ///
+    /// ## Validation
+    ///
+    /// - `physical_device` **must** be a valid handle.
+    pub unsafe fn physical_device_peak(&self, physical_device: api::PhysicalDevice) -> Result<f32> {
+        let physical_device = self.physical_device(physical_device)?;
+        endpoint_peak(physical_device.device)
+    }
+
+    /// Query the capabilities of a physical device.
+    ///
+    /// Composes the individual format probes in both sharing modes around the
+    /// default concurrent format. Probing is expensive, the result is cached per device.
+    /// The cache is cleared when the device state or its properties (e.g the device format)
+    /// change and when the device is re-activated.
+    ///
+    /// ## Validation
+    ///
+    /// - `physical_device` **must** be a valid handle.
+    pub unsafe fn physical_device_capabilities(
+        &self,
+        physical_device: api::PhysicalDevice,
+    ) -> Result<api::DeviceCapabilities> {
+        let device = self.physical_device(physical_device)?;
+        if let Some(capabilities) = &*device.capabilities.lock().unwrap() {
+            return Ok(capabilities.clone());
+        }
+
+        const SAMPLE_RATES: [usize; 6] = [44_100, 48_000, 88_200, 96_000, 176_400, 192_000];
+        const FORMATS: [api::Format; 4] = [
+            api::Format::F32,
+            api::Format::I16,
+            api::Format::I24,
+            api::Format::I32,
+        ];
+
+        let default =
+            api::Instance::physical_device_default_concurrent_format(self, physical_device)?;
+        let supported = |sharing, frame_desc| {
+            api::Instance::physical_device_supports_format(
+                self,
+                physical_device,
+                sharing,
+                frame_desc,
+            )
+        };
+        let supported_any = |frame_desc| {
+            supported(api::SharingMode::Concurrent, frame_desc)
+                || supported(api::SharingMode::Exclusive, frame_desc)
+        };
+
+        let mut sharing = api::SharingModeFlags::CONCURRENT;
+        let exclusive = SAMPLE_RATES.iter().any(|&sample_rate| {
+            FORMATS.iter().any(|&format| {
+                supported(
+                    api::SharingMode::Exclusive,
+                    api::FrameDesc {
+                        format,
+                        sample_rate,
+                        ..default
+                    },
+                )
+            })
+        });
+        if exclusive {
+            sharing |= api::SharingModeFlags::EXCLUSIVE;
+        }
+
+        let mut sample_rates = SAMPLE_RATES
+            .iter()
+            .copied()
+            .filter(|&sample_rate| {
+                FORMATS.iter().any(|&format| {
+                    supported_any(api::FrameDesc {
+                        format,
+                        sample_rate,
+                        ..default
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        if !sample_rates.contains(&default.sample_rate) {
+            sample_rates.push(default.sample_rate);
+            sample_rates.sort_unstable();
+        }
+
+        let formats = FORMATS
+            .iter()
+            .copied()
+            .filter(|&format| supported_any(api::FrameDesc { format, ..default }))
+            .collect();
+
+        let mut channel_layouts = vec![default.channels];
+        for &channels in &[
+            api::ChannelMask::FRONT_CENTER,
+            api::ChannelMask::FRONT_LEFT | api::ChannelMask::FRONT_RIGHT,
+        ] {
+            if channels != default.channels
+                && supported_any(api::FrameDesc {
+                    channels,
+                    ..default
+                })
+            {
+                channel_layouts.push(channels);
+            }
+        }
+
+        let (periods, _) = Self::engine_periods(**device.audio_client())?;
+
+        let capabilities = api::DeviceCapabilities {
+            sharing,
+            sample_rates,
+            formats,
+            channel_layouts,
+            periods,
+        };
+        *device.capabilities.lock().unwrap() = Some(capabilities.clone());
+        Ok(capabilities)
+    }
+
+    /// Describe the raw mix format of a physical device.
+    ///
+    /// Intended for debugging format negotiation issues.
+    /// Returns `Error::DeviceLost` for devices which have never been active.
+    ///
+    /// ## Validation
+    ///
+    /// - `physical_device` **must** be a valid handle.
+    pub unsafe fn physical_device_raw_format(
+        &self,
+        physical_device: api::PhysicalDevice,
+    ) -> Result<String> {
+        let physical_device = self.physical_device(physical_device)?;
+
+        let mut mix_format = ptr::null_mut();
+        let hr = physical_device
+            .active_audio_client()?
+            .GetMixFormat(&mut mix_format);
+        if !winerror::SUCCEEDED(hr) {
+            return Err(api::Error::Internal {
+                cause: format!("failed to query mix format ({:#x})", hr),
+            });
+        }
+
+        let format = &*mix_format;
+        let mut description = format!(
+            "tag: {:#06x}, channels: {}, sample rate: {}, bits per sample: {}, block align: {}",
+            { format.wFormatTag },
+            { format.nChannels },
+            { format.nSamplesPerSec },
+            { format.wBitsPerSample },
+            { format.nBlockAlign },
+        );
+        if format.wFormatTag == WAVE_FORMAT_EXTENSIBLE {
+            let format_ex = &*(mix_format as *const WAVEFORMATEXTENSIBLE);
+            description += &format!(
+                ", valid bits per sample: {}, channel mask: {:#x}, subformat: {}",
+                { format_ex.Samples },
+                { format_ex.dwChannelMask },
+                Guid(format_ex.SubFormat),
+            );
+        }
+        CoTaskMemFree(mix_format as _);
+
+        Ok(description)
+    }
+
+    unsafe fn default_physical_device_id(&self, flow: EDataFlow, role: ERole) -> Option<String> {
+        let mut device = PhysicalDeviceRaw::null();
+        let _hr = self
+            .raw
+            .GetDefaultAudioEndpoint(flow, role, device.mut_void() as *mut _);
+        if device.is_null() {
+            None
+        } else {
+            let id = Self::get_physical_device_id(device);
+            device.destroy();
+            Some(id)
+        }
+    }
+
+    unsafe fn get_physical_device_id(device: PhysicalDeviceRaw) -> String {
+        let mut str_id = ptr::null_mut();
+        device.GetId(&mut str_id);
+        let id = string_from_wstr(str_id);
+        CoTaskMemFree(str_id as _);
+        id
+    }
+
+    unsafe fn enumerate_physical_devices_by_flow(
+        physical_devices: &mut PhysicalDeviceMap,
+        instance: InstanceRaw,
+        ty: EDataFlow,
+    ) {
+        type DeviceCollection = WeakPtr<IMMDeviceCollection>;
+
+        let collection = {
             let mut collection = DeviceCollection::null();
             let _hr = instance.EnumAudioEndpoints(
                 ty,
@@ -548,52 +1793,72 @@ impl Instance {
         for i in 0..num_items {
             let mut device = PhysicalDeviceRaw::null();
             collection.Item(i, device.mut_void() as *mut _);
-            let id = Self::get_physical_device_id(device);
+            Self::insert_physical_device(physical_devices, device, ty, false);
+        }
 
-            let state = {
-                let mut state = 0;
-                device.GetState(&mut state);
-                state
-            };
+        collection.destroy();
+    }
 
-            physical_devices
-                .entry(id)
-                .and_modify(|device| {
-                    device.streams |= stream_flags;
-                })
-                .or_insert_with(|| {
-                    let mut audio_client = WeakPtr::<IAudioClient>::null();
-
-                    if state & DEVICE_STATE_ACTIVE != 0 {
-                        device.Activate(
-                            &IAudioClient::uuidof(),
-                            CLSCTX_ALL,
-                            ptr::null_mut(),
-                            audio_client.mut_void() as *mut _,
-                        );
-                    }
+    /// Add an endpoint to the physical devices or update its existing entry.
+    ///
+    /// Takes ownership of `device`. Devices inactive on insertion don't have an audio
+    /// client yet, `reactivate` replaces the client of known active devices.
+    unsafe fn insert_physical_device(
+        physical_devices: &mut PhysicalDeviceMap,
+        device: PhysicalDeviceRaw,
+        flow: EDataFlow,
+        reactivate: bool,
+    ) -> api::PhysicalDevice {
+        let stream_flags = match flow {
+            eCapture => api::StreamFlags::INPUT,
+            eRender => api::StreamFlags::OUTPUT,
+            _ => unreachable!(),
+        };
 
-                    Handle::new(PhysicalDevice {
-                        device,
-                        audio_client,
-                        streams: stream_flags,
-                    })
-                });
+        let id = Self::get_physical_device_id(device);
+        let state = {
+            let mut state = 0;
+            device.GetState(&mut state);
+            state
+        };
+        let is_active = state & DEVICE_STATE_ACTIVE != 0;
+
+        match physical_devices.ids.get(&id) {
+            Some(&handle) => {
+                // The entry holds its own reference of the endpoint.
+                device.destroy();
+                let physical_device = physical_devices.devices.get(handle).unwrap();
+                physical_device
+                    .streams
+                    .fetch_or(stream_flags.bits(), Ordering::AcqRel);
+                if is_active && (reactivate || physical_device.audio_client().is_null()) {
+                    physical_device.activate();
+                }
+                handle
+            }
+            None => {
+                let physical_device = PhysicalDevice::new(device, stream_flags);
+                if is_active {
+                    physical_device.activate();
+                }
+                let handle = physical_devices.devices.insert(physical_device);
+                physical_devices.ids.insert(id, handle);
+                handle
+            }
         }
-
-        collection.Release();
     }
 }
 
 impl std::ops::Drop for Instance {
     fn drop(&mut self) {
         unsafe {
-            self.raw.Release();
             if !self.notifier.is_null() {
+                self.raw
+                    .UnregisterEndpointNotificationCallback(self.notifier.as_mut_ptr() as *mut _);
                 WeakPtr::from_raw(self.notifier.as_mut_ptr() as *mut IMMNotificationClient)
                     .Release();
             }
-            // TODO: drop audio clients
+            self.raw.destroy();
         }
     }
 }
@@ -608,81 +1873,1281 @@ pub enum DeviceStream {
     },
 }
 
+/// Thread-safe handle for stopping a device stream.
+#[derive(Debug, Clone)]
+pub struct StopHandle(Arc<AtomicBool>);
+
+impl StopHandle {
+    /// Request the associated device stream to stop.
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+impl DeviceStream {
+    fn is_input(&self) -> bool {
+        match *self {
+            DeviceStream::Input { .. } => true,
+            DeviceStream::Output { .. } => false,
+        }
+    }
+
+    unsafe fn release(&self) {
+        match *self {
+            DeviceStream::Input { client } => {
+                client.destroy();
+            }
+            DeviceStream::Output { client, .. } => {
+                client.destroy();
+            }
+        }
+    }
+}
+
+/// Input stream of a duplex device.
+///
+/// Signals the fence of the device together with the output stream. Captured packets
+/// are collected in the capture scratch buffer until consumed by the stream callback.
+struct DuplexInput {
+    client: WeakPtr<IAudioClient>,
+    capture: WeakPtr<IAudioCaptureClient>,
+    /// Captured frames in the capture scratch buffer, which haven't been consumed yet.
+    pending: api::Frames,
+}
+
+impl DuplexInput {
+    unsafe fn create(
+        device: PhysicalDeviceRaw,
+        fence: Fence,
+        config: &StreamConfig,
+    ) -> Result<Self> {
+        let client = activate_client(device)?;
+        let capture = Device::initialize_client(client, config)
+            .map_err(api::Error::from)
+            .and_then(|()| {
+                client.SetEventHandle(fence.0);
+                get_service(client, "IAudioCaptureClient")
+            });
+        match capture {
+            Ok(capture) => Ok(DuplexInput {
+                client,
+                capture,
+                pending: 0,
+            }),
+            Err(err) => {
+                client.destroy();
+                Err(err)
+            }
+        }
+    }
+
+    unsafe fn release(&self) {
+        self.capture.destroy();
+        self.client.destroy();
+    }
+}
+
+/// 16 byte aligned storage of the capture and render scratch buffers.
+#[derive(Copy, Clone)]
+#[repr(C, align(16))]
+struct ScratchBlock([u8; 16]);
+
+/// Parameters for initializing the audio client of a device.
+#[derive(Copy, Clone)]
+struct StreamConfig {
+    sharing: AUDCLNT_SHAREMODE,
+    stream_flags: DWORD,
+    mix_format: WAVEFORMATEXTENSIBLE,
+    session_guid: Option<Guid>,
+    /// Requested buffer duration in 100ns units, zero for the default duration.
+    buffer_duration: REFERENCE_TIME,
+    /// Conversions applied to the stream callback (channel map or downmixing).
+    conversions: api::ConversionFlags,
+}
+
+impl StreamConfig {
+    /// The stream is polled at the device period instead of being signaled by the audio engine.
+    fn is_timer_driven(&self) -> bool {
+        self.stream_flags & AUDCLNT_STREAMFLAGS_EVENTCALLBACK == 0
+    }
+}
+
 pub struct Device {
+    instance: InstanceRaw,
+    physical_devices: Arc<Mutex<PhysicalDeviceMap>>,
+    physical_device_id: PhysicalDeviceId,
+    config: StreamConfig,
+    running: Cell<bool>,
+    /// Time and queued frames of the last output submission since the stream has been started.
+    last_submit: Cell<Option<(time::Instant, u32)>>,
+    /// Remaining and total frames of a pending fade out.
+    fade: Cell<Option<(api::Frames, api::Frames)>>,
+    stop_request: Arc<AtomicBool>,
+    event_callback: EventCallback,
+    capture_position: Option<u64>,
+    /// Reused for concatenating capture packets, grows but never shrinks.
+    ///
+    /// Stored as 16 byte blocks for aligning the samples (e.g for SIMD loads).
+    capture_scratch: Vec<ScratchBlock>,
+    /// Rendered into instead of unaligned engine buffers, grows but never shrinks.
+    render_scratch: Vec<ScratchBlock>,
+    auto_recover: bool,
+    discontinuities: u32,
+    /// Execution time of the most recent stream callback.
+    last_callback_duration: time::Duration,
+    acquire_timeout: u32,
+    alertable_wait: bool,
     client: WeakPtr<IAudioClient>,
     fence: Fence,
     device_stream: DeviceStream,
+    duplex: Option<DuplexInput>,
+    session_notifier: Option<SessionNotifier>,
     callback: api::StreamCallback,
+    /// Sample format of stereo output downmixed to mono devices, applied to replaced callbacks.
+    downmix: Option<api::Format>,
+    /// Channel map and sample format of the device description, applied to replaced callbacks.
+    channel_map: Option<(Vec<usize>, api::Format)>,
     properties: api::StreamProperties,
 }
 
 impl std::ops::Drop for Device {
     fn drop(&mut self) {
         unsafe {
-            self.client.Release();
+            if let Some(notifier) = self.session_notifier {
+                notifier.unregister();
+            }
+            // Render and capture clients hold their own references.
+            self.device_stream.release();
+            self.client.destroy();
+            if let Some(duplex) = &self.duplex {
+                duplex.release();
+            }
             self.fence.destory();
         }
     }
 }
 
 impl Device {
-    unsafe fn acquire_buffers(&mut self, timeout_ms: u32) -> Result<api::StreamBuffers> {
-        self.fence.wait(timeout_ms);
+    unsafe fn initialize_stream(
+        client: WeakPtr<IAudioClient>,
+        fence: Fence,
+        config: &StreamConfig,
+        input: bool,
+    ) -> Result<(api::StreamProperties, DeviceStream)> {
+        Self::initialize_client(client, config)?;
+        Self::setup_stream(client, fence, config, input)
+    }
 
-        match self.device_stream {
-            DeviceStream::Input { client } => {
-                let mut len = 0;
-                client.GetNextPacketSize(&mut len);
+    /// Initialize an exclusive stream, falling back to timer driven mode.
+    ///
+    /// Some drivers fail to initialize event driven exclusive streams due to their
+    /// buffer alignment requirements. Unaligned buffer durations are retried with the
+    /// duration aligned to the buffer size reported by the driver. If event driven mode
+    /// still fails, timer driven mode is used and the event callback flag is removed
+    /// from `config`.
+    /// Failed initialization leaves the audio client unusable, the client of the physical
+    /// device is replaced after each failed attempt and the next attempt uses the new client.
+    /// Returns a new reference of the audio client of the stream.
+    unsafe fn initialize_exclusive_client(
+        physical_device: &PhysicalDevice,
+        config: &mut StreamConfig,
+    ) -> Result<WeakPtr<IAudioClient>> {
+        let client = physical_device.active_audio_client()?;
+        let err = match Self::initialize_client(**client, config) {
+            Ok(()) => return Ok(client.add_ref()),
+            Err(err) => err,
+        };
+        physical_device.activate();
+
+        let err = match err {
+            WasapiError::BufferSizeNotAligned => {
+                let mut frames = 0;
+                client.GetBufferSize(&mut frames);
+                let sample_rate = config.mix_format.Format.nSamplesPerSec;
+                config.buffer_duration =
+                    (10_000_000.0 * frames as f64 / sample_rate as f64 + 0.5) as REFERENCE_TIME;
+                let aligned = physical_device.active_audio_client()?;
+                match Self::initialize_client(**aligned, config) {
+                    Ok(()) => return Ok(aligned.add_ref()),
+                    Err(err) => {
+                        physical_device.activate();
+                        err
+                    }
+                }
+            }
+            err => err,
+        };
 
-                let mut data = ptr::null_mut();
-                let mut num_frames = 0;
-                let mut flags = 0;
+        match err {
+            WasapiError::BufferSizeNotAligned
+            | WasapiError::BufferDurationPeriodNotEqual
+            | WasapiError::InvalidDevicePeriod => {
+                log::warn!("exclusive event driven mode not supported, using timer driven mode");
+                let fallback = physical_device.active_audio_client()?;
+                config.stream_flags &= !AUDCLNT_STREAMFLAGS_EVENTCALLBACK;
+                match Self::initialize_client(**fallback, config) {
+                    Ok(()) => Ok(fallback.add_ref()),
+                    Err(err) => {
+                        physical_device.activate();
+                        Err(err.into())
+                    }
+                }
+            }
+            err => Err(err.into()),
+        }
+    }
 
-                client.GetBuffer(
-                    &mut data,
-                    &mut num_frames,
-                    &mut flags,
-                    ptr::null_mut(),
-                    ptr::null_mut(),
-                );
+    unsafe fn initialize_client(
+        client: WeakPtr<IAudioClient>,
+        config: &StreamConfig,
+    ) -> std::result::Result<(), WasapiError> {
+        let buffer_duration = config.buffer_duration;
+        // Exclusive streams require the period to match the buffer duration.
+        let (buffer_duration, periodicity) =
+            if config.sharing == AUDCLNT_SHAREMODE_EXCLUSIVE && buffer_duration != 0 {
+                let mut default_period = 0;
+                let mut min_period = 0;
+                client.GetDevicePeriod(&mut default_period, &mut min_period);
+                let period = buffer_duration.max(min_period);
+                (period, period)
+            } else {
+                (buffer_duration, 0)
+            };
+
+        let mix_format = &config.mix_format;
+        let hr = client.Initialize(
+            config.sharing,
+            config.stream_flags,
+            buffer_duration,
+            periodicity,
+            mix_format as *const _ as _,
+            config
+                .session_guid
+                .as_ref()
+                .map_or(ptr::null(), |guid| &guid.0 as *const _),
+        );
+        WasapiError::from_hr(hr)
+    }
+
+    unsafe fn setup_stream(
+        client: WeakPtr<IAudioClient>,
+        fence: Fence,
+        config: &StreamConfig,
+        input: bool,
+    ) -> Result<(api::StreamProperties, DeviceStream)> {
+        if !config.is_timer_driven() {
+            client.SetEventHandle(fence.0);
+        }
+
+        let mix_format = &config.mix_format;
+        let requested = map_waveformat(mix_format as *const _ as _)?;
+        let requested_channels = requested.channels;
+
+        let frame_desc = client_format(client)?;
+        let valid_bits = valid_bits(mix_format as *const _ as _);
+
+        let buffer_size = {
+            let mut size = 0;
+            client.GetBufferSize(&mut size);
+            size
+        };
+
+        // Exclusive streams run with the requested format, conversions only happen in the engine.
+        let mut conversions = config.conversions;
+        if config.sharing == AUDCLNT_SHAREMODE_SHARED {
+            if requested.sample_rate != frame_desc.sample_rate {
+                conversions |= api::ConversionFlags::SAMPLE_RATE;
+            }
+            if requested.format != frame_desc.format {
+                conversions |= api::ConversionFlags::FORMAT;
+            }
+            if requested.channels != frame_desc.channels {
+                conversions |= api::ConversionFlags::CHANNELS;
+            }
+        }
+
+        let properties = api::StreamProperties {
+            channels: requested_channels,
+            requested_channels,
+            device_channels: frame_desc.channels,
+            sample_rate: frame_desc.sample_rate,
+            buffer_size_frames: frames_from_u32(buffer_size),
+            valid_bits,
+            timer_driven: config.is_timer_driven(),
+            conversions,
+        };
+
+        let device_stream = if input {
+            DeviceStream::Input {
+                client: get_service(client, "IAudioCaptureClient")?,
+            }
+        } else {
+            DeviceStream::Output {
+                client: get_service(client, "IAudioRenderClient")?,
+                buffer_size,
+            }
+        };
+
+        Ok((properties, device_stream))
+    }
+
+    /// Reconnect the device after the physical device got lost.
+    ///
+    /// Re-resolves the physical device by its id and re-creates the stream
+    /// with the same configuration. The stream callback is preserved and the
+    /// stream will be restarted if it was running before.
+    ///
+    /// Returns `Error::DeviceLost` if the physical device is still unavailable.
+    ///
+    /// # Safety
+    ///
+    /// Issues COM calls on the audio client, COM **must** be initialized on the calling
+    /// thread.
+    pub unsafe fn reconnect(&mut self) -> Result<()> {
+        let device = self.physical_device()?;
+
+        let result = self.reinitialize(device);
+        device.destroy();
+        result
+    }
+
+    /// Move the device stream to another physical device.
+    ///
+    /// The stream will be re-created on the new physical device with the same
+    /// configuration and restarted if it was running before. The stream callback
+    /// is preserved. On success `Event::StreamRerouted` will be emitted to the
+    /// instance event callback. Duplex devices only move their output stream.
+    ///
+    /// ## Validation
+    ///
+    /// - `physical_device` **must** be a valid handle.
+    /// - `physical_device` **must** support the stream direction of the device.
+    pub unsafe fn switch_to(&mut self, physical_device: api::PhysicalDevice) -> Result<()> {
+        let device = self.physical_devices.lock().unwrap().get(physical_device)?;
+        let direction = if self.device_stream.is_input() {
+            api::StreamFlags::INPUT
+        } else {
+            api::StreamFlags::OUTPUT
+        };
+        if !device.streams().contains(direction) {
+            return api::Error::validation(
+                "Physical device doesn't support the stream direction of the device",
+            );
+        }
+
+        self.reinitialize(device.device)?;
+        self.physical_device_id = Instance::get_physical_device_id(device.device);
+
+        if let Some(callback) = self.event_callback.lock().unwrap().as_mut() {
+            callback(api::Event::StreamRerouted(physical_device));
+        }
+
+        Ok(())
+    }
+
+    unsafe fn reinitialize(&mut self, device: PhysicalDeviceRaw) -> Result<()> {
+        let client = activate_client(device)?;
+
+        self.client.Stop();
 
-                if flags != 0 {
-                    dbg!(flags);
+        let (properties, device_stream) = match Self::initialize_stream(
+            client,
+            self.fence,
+            &self.config,
+            self.device_stream.is_input(),
+        ) {
+            Ok(stream) => stream,
+            Err(err) => {
+                client.destroy();
+                if self.running.get() {
+                    self.client.Start();
                 }
+                return Err(err);
+            }
+        };
 
-                Ok(api::StreamBuffers {
-                    frames: num_frames as _,
-                    input: data as _,
-                    output: ptr::null_mut(),
-                })
+        self.device_stream.release();
+        if let Some(notifier) = self.session_notifier {
+            notifier.unregister();
+        }
+        self.client.destroy();
+
+        self.client = client;
+        self.session_notifier = SessionNotifier::register(client, self.event_callback.clone());
+        self.device_stream = device_stream;
+        self.properties = properties;
+        self.capture_position = None;
+        self.discontinuities = 0;
+
+        if self.running.get() {
+            self.client.Start();
+        }
+
+        Ok(())
+    }
+
+    /// Wait until the device is ready for streaming.
+    ///
+    /// Some endpoints (e.g bluetooth devices) aren't immediately usable after device creation.
+    /// Polls the endpoint state until it's active and the stream can be queried.
+    /// Returns `Error::Timeout` if the device isn't ready within `timeout`.
+    ///
+    /// # Safety
+    ///
+    /// Issues COM calls on the audio client, COM **must** be initialized on the calling
+    /// thread.
+    pub unsafe fn wait_ready(&self, timeout: time::Duration) -> Result<()> {
+        let start = time::Instant::now();
+        loop {
+            let device = self.physical_device()?;
+            let state = {
+                let mut state = 0;
+                device.GetState(&mut state);
+                state
+            };
+            device.destroy();
+
+            if state & DEVICE_STATE_ACTIVE != 0 {
+                let mut padding = 0;
+                match WasapiError::from_hr(self.client.GetCurrentPadding(&mut padding)) {
+                    Ok(()) => return Ok(()),
+                    Err(err) => {
+                        if let api::Error::DeviceLost = api::Error::from(err) {
+                            return Err(api::Error::DeviceLost);
+                        }
+                    }
+                }
+            }
+
+            if start.elapsed() >= timeout {
+                return Err(api::Error::Timeout);
+            }
+            thread::sleep(time::Duration::from_millis(10));
+        }
+    }
+
+    /// Replace the stream callback.
+    ///
+    /// The new callback is invoked starting with the next `submit_buffers` call.
+    /// Requiring exclusive access ensures that the swap never races a running callback.
+    /// Stereo downmixing and the channel map of the device description also apply to
+    /// the new callback.
+    pub fn set_callback(&mut self, callback: api::StreamCallback) {
+        self.callback =
+            Device::wrap_callback(callback, self.downmix, &self.channel_map, &self.properties);
+    }
+
+    /// Wrap the user callback into the channel conversions of the device.
+    fn wrap_callback(
+        callback: api::StreamCallback,
+        downmix: Option<api::Format>,
+        channel_map: &Option<(Vec<usize>, api::Format)>,
+        properties: &api::StreamProperties,
+    ) -> api::StreamCallback {
+        let callback = match downmix {
+            Some(format) => {
+                convert::downmix_stereo(callback, format, properties.buffer_size_frames)
+            }
+            None => callback,
+        };
+        match channel_map.clone() {
+            Some((map, format)) => convert::map_channels(callback, map, format),
+            None => callback,
+        }
+    }
+
+    /// Wait for stream buffers, polling timer driven devices instead of waiting on the fence.
+    ///
+    /// Returns the result of the fence wait, `WAIT_IO_COMPLETION` for alertable waits
+    /// interrupted by queued APCs.
+    unsafe fn wait_buffers(&self, timeout_ms: u32) -> u32 {
+        if self.config.is_timer_driven() {
+            self.wait_timer(timeout_ms)
+        } else if self.alertable_wait {
+            self.fence.wait_alertable(timeout_ms)
+        } else {
+            self.fence.wait(timeout_ms)
+        }
+    }
+
+    /// Wait for stream buffers of a timer driven device by polling at half the buffer period.
+    ///
+    /// Returns `WAIT_OBJECT_0` if buffers are available, mirroring the fence wait.
+    unsafe fn wait_timer(&self, timeout_ms: u32) -> u32 {
+        let start = time::Instant::now();
+        let timeout = time::Duration::from_millis(timeout_ms as _);
+        let interval = self.properties.latency() / 2;
+        loop {
+            let available = match self.device_stream {
+                DeviceStream::Input { client } => {
+                    let mut len = 0;
+                    client.GetNextPacketSize(&mut len);
+                    len > 0
+                }
+                DeviceStream::Output { buffer_size, .. } => {
+                    let mut padding = 0;
+                    self.client.GetCurrentPadding(&mut padding);
+                    padding < buffer_size
+                }
+            };
+            if available {
+                return WAIT_OBJECT_0;
+            }
+
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                return winerror::WAIT_TIMEOUT;
+            }
+            thread::sleep(interval.min(timeout - elapsed));
+        }
+    }
+
+    /// Playback position of the stream in seconds.
+    ///
+    /// Derived from the device clock, reflecting the frames consumed by the hardware
+    /// instead of the frames submitted ahead. The position is reset when the stream is
+    /// reset, e.g by `reconnect` or capture recovery.
+    ///
+    /// # Safety
+    ///
+    /// Issues COM calls on the audio client, COM **must** be initialized on the calling
+    /// thread.
+    pub unsafe fn played_seconds(&self) -> Result<f64> {
+        let clock = get_service::<IAudioClock>(self.client, "IAudioClock")?;
+
+        let mut frequency = 0;
+        let mut position = 0;
+        let hr = clock.GetFrequency(&mut frequency);
+        let hr = if winerror::SUCCEEDED(hr) {
+            clock.GetPosition(&mut position, ptr::null_mut())
+        } else {
+            hr
+        };
+        clock.destroy();
+        WasapiError::from_hr(hr)?;
+
+        // Frequency is given in position units per second.
+        Ok(position as f64 / frequency as f64)
+    }
+
+    /// Execution time of the stream callback during the last `submit_buffers` call.
+    ///
+    /// Callbacks regularly exceeding the buffer period (see `StreamProperties::latency`)
+    /// will cause underruns. Zero if no callback has been invoked yet.
+    pub fn last_callback_duration(&self) -> time::Duration {
+        self.last_callback_duration
+    }
+
+    /// Fade out the output stream over `duration` and stop the device afterwards.
+    ///
+    /// The fade is applied to the buffers rendered by subsequent `submit_buffers` calls,
+    /// avoiding an audible click when stopping during playback. The device stops
+    /// once the faded frames have been played. Input devices are stopped immediately.
+    ///
+    /// # Safety
+    ///
+    /// Issues COM calls on the audio client, COM **must** be initialized on the calling
+    /// thread.
+    pub unsafe fn stop_with_fade(&self, duration: time::Duration) {
+        let frames = (duration.as_secs_f64() * self.properties.sample_rate as f64) as api::Frames;
+        if self.device_stream.is_input() || frames == 0 {
+            api::Device::stop(self);
+            return;
+        }
+        self.fade.set(Some((frames, frames)));
+    }
+
+    /// Start the device and measure the time until the audio engine begins streaming.
+    ///
+    /// Measures from `IAudioClient::Start` until the padding changes, i.e captured frames
+    /// are available or submitted output frames are consumed. This covers the cold-start
+    /// latency of the endpoint (e.g hundreds of milliseconds for bluetooth devices).
+    /// Output devices **should** have submitted frames before, otherwise no progress
+    /// can be observed.
+    ///
+    /// Returns `Error::Timeout` if no progress is observed within the acquire timeout
+    /// of the device description.
+    ///
+    /// # Safety
+    ///
+    /// Issues COM calls on the audio client, COM **must** be initialized on the calling
+    /// thread.
+    pub unsafe fn start_timed(&self) -> Result<time::Duration> {
+        let mut initial_padding = 0;
+        WasapiError::from_hr(self.client.GetCurrentPadding(&mut initial_padding))?;
+
+        let start = time::Instant::now();
+        api::Device::start(self);
+        let timeout = time::Duration::from_millis(self.acquire_timeout as _);
+        loop {
+            let mut padding = 0;
+            WasapiError::from_hr(self.client.GetCurrentPadding(&mut padding))?;
+            let started = if self.device_stream.is_input() {
+                padding != 0
+            } else {
+                padding != initial_padding
+            };
+            if started {
+                return Ok(start.elapsed());
+            }
+
+            if self.acquire_timeout != api::INFINITE_TIMEOUT_MS && start.elapsed() >= timeout {
+                return Err(api::Error::Timeout);
+            }
+            thread::sleep(time::Duration::from_millis(1));
+        }
+    }
+
+    /// Drop pending capture data and restart the stream.
+    unsafe fn recover_capture(&mut self) {
+        let client = match &mut self.duplex {
+            Some(duplex) => {
+                duplex.pending = 0;
+                duplex.client
             }
+            None => self.client,
+        };
+        client.Stop();
+        client.Reset();
+        if self.running.get() {
+            client.Start();
+        }
+        self.capture_position = None;
+        self.discontinuities = 0;
+
+        if let Some(callback) = self.event_callback.lock().unwrap().as_mut() {
+            callback(api::Event::CaptureRecovered);
+        }
+    }
+
+    /// Query the endpoint of the device stream.
+    unsafe fn physical_device(&self) -> Result<PhysicalDeviceRaw> {
+        let id = OsStr::new(&self.physical_device_id)
+            .encode_wide()
+            .chain(Some(0))
+            .collect::<Vec<_>>();
+
+        let mut device = PhysicalDeviceRaw::null();
+        let hr = self
+            .instance
+            .GetDevice(id.as_ptr(), device.mut_void() as *mut _);
+        if !winerror::SUCCEEDED(hr) {
+            return Err(api::Error::DeviceLost);
+        }
+        Ok(device)
+    }
+
+    /// Get a handle for requesting the stream to stop from another thread.
+    ///
+    /// The stop request will be handled by the next `submit_buffers` call,
+    /// which stops the device instead of processing the stream buffers.
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle(self.stop_request.clone())
+    }
+
+    /// Check if the device stream is currently running.
+    pub fn is_running(&self) -> bool {
+        self.running.get()
+    }
+
+    /// Current peak level of the capture endpoint in the range `[0.0, 1.0]`.
+    ///
+    /// The level is measured by the audio engine for the whole endpoint,
+    /// independent of the data read by the stream callback.
+    ///
+    /// ## Validation
+    ///
+    /// - The device **must** be an input device.
+    pub unsafe fn input_peak(&self) -> Result<f32> {
+        if !self.device_stream.is_input() {
+            return api::Error::validation("Input peak requested for an output device");
+        }
+
+        let device = self.physical_device()?;
+
+        let peak = endpoint_peak(device);
+        device.destroy();
+        peak
+    }
+
+    /// Write interleaved samples directly into the render buffer without invoking the stream callback.
+    ///
+    /// Writes as many frames as currently fit into the render buffer and returns the number
+    /// of frames consumed. Can be mixed with `submit_buffers` on the same device.
+    ///
+    /// ## Validation
+    ///
+    /// - The device **must** be an output device with `F32` sample format.
+    /// - `samples` **must** contain a multiple of the number of stream channels.
+    pub unsafe fn push(&mut self, samples: &[f32]) -> Result<api::Frames> {
+        let (client, buffer_size) = match self.device_stream {
             DeviceStream::Output {
                 client,
                 buffer_size,
-            } => {
-                let mut data = ptr::null_mut();
-                let mut padding = 0;
+            } => (client, buffer_size),
+            DeviceStream::Input { .. } => {
+                return api::Error::validation("Samples pushed to an input device")
+            }
+        };
+        if Guid(self.config.mix_format.SubFormat) != Guid(ksmedia::KSDATAFORMAT_SUBTYPE_IEEE_FLOAT)
+        {
+            return api::Error::validation("Samples pushed to a device without `F32` format");
+        }
+        let num_channels = self.properties.num_channels();
+        if !samples.len().is_multiple_of(num_channels) {
+            return api::Error::validation(
+                "Number of pushed samples isn't a multiple of the number of channels",
+            );
+        }
+
+        let mut padding = 0;
+        WasapiError::from_hr(self.client.GetCurrentPadding(&mut padding))?;
+
+        let num_frames = frames_from_u32(buffer_size - padding).min(samples.len() / num_channels);
+        if num_frames == 0 {
+            return Ok(0);
+        }
+
+        let mut data = ptr::null_mut();
+        WasapiError::from_hr(client.GetBuffer(frames_to_u32(num_frames)?, &mut data))?;
+        ptr::copy_nonoverlapping(
+            samples.as_ptr(),
+            data as *mut f32,
+            num_frames * num_channels,
+        );
+        WasapiError::from_hr(client.ReleaseBuffer(frames_to_u32(num_frames)?, 0))?;
+
+        Ok(num_frames)
+    }
+
+    /// Capture `frames` interleaved frames without invoking the stream callback.
+    ///
+    /// Waits for captured packets until the requested number of frames has been collected,
+    /// using the timeout specified on device creation for each wait. Samples are converted
+    /// to `F32`, frames exceeding the request are dropped.
+    ///
+    /// ## Validation
+    ///
+    /// - The device **must** be a started input device.
+    pub unsafe fn capture_to_vec(&mut self, frames: api::Frames) -> Result<Vec<f32>> {
+        if !self.device_stream.is_input() {
+            return api::Error::validation("Frames captured from an output device");
+        }
+        if !self.running.get() {
+            return api::Error::validation(
+                "`capture_to_vec` called before the device has been started",
+            );
+        }
+        let format = map_waveformat(&self.config.mix_format as *const _ as _)?.format;
+        let num_samples = frames * self.properties.num_channels();
+
+        let mut samples = Vec::with_capacity(num_samples);
+        while samples.len() < num_samples {
+            match self.wait_buffers(self.acquire_timeout) {
+                // Woken up for running queued APCs, keep waiting for buffers.
+                WAIT_IO_COMPLETION => continue,
+                WAIT_FAILED => return Err(api::Error::DeviceLost),
+                winerror::WAIT_TIMEOUT => return Err(api::Error::Timeout),
+                _ => (),
+            }
+
+            let buffers = self.acquire_capture_packets()?;
+            let len =
+                (buffers.frames * self.properties.num_channels()).min(num_samples - samples.len());
+            match format {
+                api::Format::F32 => samples
+                    .extend_from_slice(slice::from_raw_parts(buffers.input as *const f32, len)),
+                api::Format::I16 => samples.extend(
+                    slice::from_raw_parts(buffers.input as *const i16, len)
+                        .iter()
+                        .map(|&sample| sample as f32 / 32768.0),
+                ),
+                api::Format::I24 => samples.extend(
+                    slice::from_raw_parts(buffers.input as *const [u8; 3], len)
+                        .iter()
+                        .map(|&[a, b, c]| {
+                            i32::from_le_bytes([0, a, b, c]) as f32 / 2_147_483_648.0
+                        }),
+                ),
+                // Valid bits are stored in the most significant bits.
+                api::Format::I32 => samples.extend(
+                    slice::from_raw_parts(buffers.input as *const i32, len)
+                        .iter()
+                        .map(|&sample| sample as f32 / 2_147_483_648.0),
+                ),
+                api::Format::U32 => samples.extend(
+                    slice::from_raw_parts(buffers.input as *const u32, len)
+                        .iter()
+                        .map(|&sample| (sample ^ 0x8000_0000) as i32 as f32 / 2_147_483_648.0),
+                ),
+            }
+        }
+
+        Ok(samples)
+    }
 
-                self.client.GetCurrentPadding(&mut padding);
+    unsafe fn acquire_buffers(&mut self) -> Result<api::StreamBuffers> {
+        match self.device_stream {
+            DeviceStream::Input { client } => Ok(self.acquire_capture_packet(client)),
+            DeviceStream::Output {
+                client,
+                buffer_size,
+            } => Ok(self.acquire_render_buffer(client, buffer_size, api::Frames::MAX)),
+        }
+    }
+
+    /// Acquire the next packet of a capture stream.
+    unsafe fn acquire_capture_packet(
+        &mut self,
+        client: WeakPtr<IAudioCaptureClient>,
+    ) -> api::StreamBuffers {
+        let mut len = 0;
+        client.GetNextPacketSize(&mut len);
+
+        let mut data = ptr::null_mut();
+        let mut num_frames = 0;
+        let mut flags = 0;
+        let mut position = 0;
+        let mut qpc_position = 0;
+
+        client.GetBuffer(
+            &mut data,
+            &mut num_frames,
+            &mut flags,
+            &mut position,
+            &mut qpc_position,
+        );
+
+        let unexpected = flags
+            & !(AUDCLNT_BUFFERFLAGS_DATA_DISCONTINUITY
+                | AUDCLNT_BUFFERFLAGS_SILENT
+                | AUDCLNT_BUFFERFLAGS_TIMESTAMP_ERROR);
+        if unexpected != 0 {
+            log::trace!("unexpected capture buffer flags: {:#x}", unexpected);
+        }
 
-                let len = buffer_size - padding;
-                client.GetBuffer(len, &mut data);
-                Ok(api::StreamBuffers {
-                    frames: len as _,
-                    input: ptr::null(),
-                    output: data as _,
+        if flags & AUDCLNT_BUFFERFLAGS_DATA_DISCONTINUITY != 0 {
+            self.discontinuities += 1;
+        } else {
+            self.discontinuities = 0;
+        }
+
+        let gap_frames = match self.capture_position {
+            Some(expected) if flags & AUDCLNT_BUFFERFLAGS_DATA_DISCONTINUITY != 0 => {
+                position.saturating_sub(expected)
+            }
+            _ => 0,
+        };
+        self.capture_position = Some(position + num_frames as u64);
+
+        api::StreamBuffers {
+            frames: frames_from_u32(num_frames),
+            input: data as _,
+            output: ptr::null_mut(),
+            gap_frames,
+            capture_time: capture_time(flags, qpc_position),
+            silent: flags & AUDCLNT_BUFFERFLAGS_SILENT != 0,
+        }
+    }
+
+    /// Acquire the available space of a render stream, limited to `max_frames`.
+    unsafe fn acquire_render_buffer(
+        &self,
+        client: WeakPtr<IAudioRenderClient>,
+        buffer_size: u32,
+        max_frames: api::Frames,
+    ) -> api::StreamBuffers {
+        let mut data = ptr::null_mut();
+        let mut padding = 0;
+
+        self.client.GetCurrentPadding(&mut padding);
+
+        if let (0, Some((submitted, queued))) = (padding, self.last_submit.get()) {
+            // The engine drained all submitted frames, estimate the frames it ran short of.
+            let sample_rate = self.properties.sample_rate as f64;
+            let consumed = (submitted.elapsed().as_secs_f64() * sample_rate) as u64;
+            let missing = consumed.saturating_sub(queued as u64);
+            // Timing jitter can drain the buffer right on time, not an audible gap.
+            if missing > 0 {
+                log::warn!(
+                    "underrun: {} frames ({} ms)",
+                    missing,
+                    missing * 1000 / self.properties.sample_rate as u64
+                );
+            }
+        }
+
+        let len = (buffer_size - padding).min(max_frames.min(u32::MAX as _) as u32);
+        if len == 0 {
+            // Buffer is still full, nothing to acquire.
+            return api::StreamBuffers {
+                frames: 0,
+                input: ptr::null(),
+                output: ptr::null_mut(),
+                gap_frames: 0,
+                capture_time: None,
+                silent: false,
+            };
+        }
+        client.GetBuffer(len, &mut data);
+        api::StreamBuffers {
+            frames: frames_from_u32(len),
+            input: ptr::null(),
+            output: data as _,
+            gap_frames: 0,
+            capture_time: None,
+            silent: false,
+        }
+    }
+
+    /// Acquire input and output buffers of a duplex device with a common number of frames.
+    ///
+    /// The clocks of the input and output devices may drift apart, the number of frames is
+    /// clamped to the smaller amount of captured and writable frames. Captured frames
+    /// exceeding the render buffer size are dropped, starting with the oldest frames.
+    unsafe fn acquire_duplex_buffers(&mut self) -> Result<api::StreamBuffers> {
+        let (capture, client, buffer_size) = match (&self.duplex, &self.device_stream) {
+            (
+                Some(duplex),
+                &DeviceStream::Output {
+                    client,
+                    buffer_size,
+                },
+            ) => (duplex.capture, client, buffer_size),
+            _ => unreachable!(),
+        };
+
+        let mut next_packet = 0;
+        capture.GetNextPacketSize(&mut next_packet);
+        let mut input = if next_packet > 0 {
+            self.acquire_capture_packets()?
+        } else {
+            api::StreamBuffers {
+                frames: self.duplex.as_ref().map_or(0, |duplex| duplex.pending),
+                input: self.capture_scratch.as_ptr() as _,
+                output: ptr::null_mut(),
+                gap_frames: 0,
+                capture_time: None,
+                silent: false,
+            }
+        };
+
+        let max_pending = frames_from_u32(buffer_size);
+        if input.frames > max_pending {
+            let dropped = input.frames - max_pending;
+            log::warn!("duplex: dropped {} captured frames", dropped);
+            self.consume_duplex_input(dropped);
+            input.frames = max_pending;
+            input.gap_frames += dropped as u64;
+            input.capture_time = None;
+        }
+
+        let output = self.acquire_render_buffer(client, buffer_size, input.frames);
+        Ok(api::StreamBuffers {
+            frames: output.frames,
+            input: self.capture_scratch.as_ptr() as _,
+            output: output.output,
+            ..input
+        })
+    }
+
+    /// Remove frames consumed by the stream callback from the duplex input.
+    unsafe fn consume_duplex_input(&mut self, frames: api::Frames) {
+        let duplex = match &mut self.duplex {
+            Some(duplex) => duplex,
+            None => return,
+        };
+        let frame_size = self.config.mix_format.Format.nBlockAlign as usize;
+        let data = self.capture_scratch.as_mut_ptr() as *mut u8;
+        ptr::copy(
+            data.add(frames * frame_size),
+            data,
+            (duplex.pending - frames) * frame_size,
+        );
+        duplex.pending -= frames;
+    }
+
+    /// Wait until any of the devices is ready and submit its stream buffers.
+    ///
+    /// Polls multiple devices from a single thread by waiting on all device fences at once.
+    /// Returns the index of the device, which buffers have been submitted.
+    ///
+    /// `timeout` may be `INFINITE_TIMEOUT` or `None` for using the smallest acquire timeout
+    /// of the devices. Returns `Error::Timeout` if no device got ready.
+    /// The wait is alertable if any device has been created with `alertable_wait`.
+    ///
+    /// ## Validation
+    ///
+    /// - `devices` **must** not be empty and contain at most `MAXIMUM_WAIT_OBJECTS` (64) devices.
+    /// - All devices **must** have been started, returns `Error::Validation` otherwise.
+    /// - Devices **must** not be timer driven (see `StreamProperties::timer_driven`),
+    ///   returns `Error::Validation` otherwise.
+    pub unsafe fn submit_buffers_any(
+        devices: &mut [Device],
+        timeout: Option<time::Duration>,
+    ) -> Result<usize> {
+        if devices.is_empty() || devices.len() > MAXIMUM_WAIT_OBJECTS as usize {
+            return api::Error::validation(format!(
+                "Number of devices must be between 1 and {}",
+                MAXIMUM_WAIT_OBJECTS
+            ));
+        }
+        if devices.iter().any(|device| !device.running.get()) {
+            return api::Error::validation(
+                "`submit_buffers_any` called before all devices have been started",
+            );
+        }
+        if devices.iter().any(|device| device.config.is_timer_driven()) {
+            return api::Error::validation(
+                "`submit_buffers_any` doesn't support timer driven devices",
+            );
+        }
+
+        let timeout_ms = match timeout {
+            Some(timeout) => api::timeout_ms(timeout),
+            None => devices
+                .iter()
+                .map(|device| device.acquire_timeout)
+                .min()
+                .unwrap(),
+        };
+        let alertable = devices.iter().any(|device| device.alertable_wait);
+
+        let fences = devices
+            .iter()
+            .map(|device| device.fence.0)
+            .collect::<Vec<_>>();
+        let wait = WaitForMultipleObjectsEx(
+            fences.len() as _,
+            fences.as_ptr(),
+            FALSE,
+            timeout_ms,
+            alertable as _,
+        );
+        let index = match wait {
+            // Woken up for running queued APCs, no buffers available yet.
+            WAIT_IO_COMPLETION => return Err(api::Error::Timeout),
+            WAIT_FAILED => return Err(api::Error::DeviceLost),
+            winerror::WAIT_TIMEOUT => return Err(api::Error::Timeout),
+            _ if wait >= WAIT_ABANDONED_0 => return Err(api::Error::DeviceLost),
+            _ => (wait - WAIT_OBJECT_0) as usize,
+        };
+
+        devices[index].process_buffers()?;
+        Ok(index)
+    }
+
+    /// Process the stream buffers after the fence has been signaled.
+    unsafe fn process_buffers(&mut self) -> Result<()> {
+        if self.stop_request.swap(false, Ordering::AcqRel) {
+            api::Device::stop(self);
+            return Ok(());
+        }
+
+        let mut buffers = {
+            #[cfg(feature = "tracing")]
+            let _span = tracing::trace_span!("acquire_buffers").entered();
+            if self.duplex.is_some() {
+                self.acquire_duplex_buffers()?
+            } else if self.device_stream.is_input() {
+                self.acquire_capture_packets()?
+            } else {
+                self.acquire_buffers()?
+            }
+        };
+        if buffers.frames == 0 && !self.device_stream.is_input() {
+            // Skip the callback and avoid spurious `ReleaseBuffer(0)` calls,
+            // which aren't handled well by some drivers.
+            return Ok(());
+        }
+        // Engine buffers aren't guaranteed to be aligned, render into the scratch buffer instead.
+        let engine_output = buffers.output;
+        let output_len = buffers.frames * self.config.mix_format.Format.nBlockAlign as usize;
+        if !engine_output.is_null()
+            && !(engine_output as usize).is_multiple_of(mem::align_of::<ScratchBlock>())
+        {
+            self.render_scratch.resize(
+                output_len.div_ceil(mem::size_of::<ScratchBlock>()),
+                ScratchBlock([0; 16]),
+            );
+            buffers.output = self.render_scratch.as_mut_ptr() as _;
+        }
+        {
+            #[cfg(feature = "tracing")]
+            let _span = tracing::trace_span!("callback", frames = buffers.frames).entered();
+            let start = time::Instant::now();
+            let callback = &mut self.callback;
+            let properties = self.properties;
+            alloc_guard::guarded(|| {
+                callback(api::Stream {
+                    properties,
+                    buffers,
                 })
+            });
+            self.last_callback_duration = start.elapsed();
+        }
+        if buffers.output != engine_output {
+            ptr::copy_nonoverlapping(
+                buffers.output as *const u8,
+                engine_output as *mut u8,
+                output_len,
+            );
+            buffers.output = engine_output;
+        }
+        if !self.device_stream.is_input() {
+            if self.fade.get().is_some() {
+                self.apply_fade(&buffers)?;
+            }
+            self.release_buffers(buffers.frames)?;
+            self.consume_duplex_input(buffers.frames);
+        }
+
+        if self.auto_recover && self.discontinuities >= RECOVER_DISCONTINUITIES {
+            self.recover_capture();
+        }
+
+        Ok(())
+    }
+
+    /// Ramp the output buffer towards silence for a pending fade out.
+    ///
+    /// Requests stopping the device once the fade has been completed,
+    /// leaving the faded frames in the engine buffer time to play out.
+    unsafe fn apply_fade(&self, buffers: &api::StreamBuffers) -> Result<()> {
+        let (mut remaining, total) = match self.fade.get() {
+            Some(fade) => fade,
+            None => return Ok(()),
+        };
+
+        let num_channels = self.properties.num_channels();
+        let format = map_waveformat(&self.config.mix_format as *const _ as _)?.format;
+        for frame in 0..buffers.frames {
+            let gain = remaining as f32 / total as f32;
+            remaining = remaining.saturating_sub(1);
+            for channel in 0..num_channels {
+                let i = frame * num_channels + channel;
+                match format {
+                    api::Format::F32 => *(buffers.output as *mut f32).add(i) *= gain,
+                    api::Format::I16 => {
+                        let sample = (buffers.output as *mut i16).add(i);
+                        *sample = (*sample as f32 * gain) as i16;
+                    }
+                    api::Format::I24 => {
+                        let sample = (buffers.output as *mut [u8; 3]).add(i);
+                        let [a, b, c] = *sample;
+                        let value = i32::from_le_bytes([0, a, b, c]) >> 8;
+                        let [a, b, c, _] = ((value as f32 * gain) as i32).to_le_bytes();
+                        *sample = [a, b, c];
+                    }
+                    api::Format::I32 => {
+                        let sample = (buffers.output as *mut i32).add(i);
+                        *sample = (*sample as f64 * gain as f64) as i32;
+                    }
+                    // Rejected on stream creation.
+                    api::Format::U32 => unreachable!(),
+                }
+            }
+        }
+
+        if remaining == 0 {
+            self.fade.set(None);
+            self.stop_request.store(true, Ordering::Release);
+        } else {
+            self.fade.set(Some((remaining, total)));
+        }
+
+        Ok(())
+    }
+
+    /// Drain all pending capture packets into a single contiguous buffer.
+    ///
+    /// Packets are copied into the capture scratch buffer and released immediately.
+    /// Discontinuities after the first packet are filled with silence.
+    /// Packets of duplex devices are appended to the pending input frames.
+    unsafe fn acquire_capture_packets(&mut self) -> Result<api::StreamBuffers> {
+        let frame_size = self.config.mix_format.Format.nBlockAlign as usize;
+        // Unsigned formats are rejected on stream creation.
+        let silence = map_waveformat(&self.config.mix_format as *const _ as _)
+            .ok()
+            .and_then(|frame_desc| frame_desc.format.silence_byte())
+            .unwrap_or(0);
+        let (client, pending) = match (&self.duplex, &self.device_stream) {
+            (Some(duplex), _) => (duplex.capture, duplex.pending),
+            (None, &DeviceStream::Input { client }) => (client, 0),
+            (None, DeviceStream::Output { .. }) => unreachable!(),
+        };
+
+        let mut len = pending * frame_size;
+        let mut num_frames = pending;
+        let mut gap_frames = 0;
+        let mut capture_time = None;
+        let mut silent = pending == 0;
+        loop {
+            let packet = self.acquire_capture_packet(client);
+            let num_silent = if num_frames == 0 {
+                gap_frames = packet.gap_frames;
+                capture_time = packet.capture_time;
+                0
+            } else {
+                packet.gap_frames as usize
+            };
+
+            let silence_len = num_silent * frame_size;
+            let packet_len = packet.frames * frame_size;
+            self.capture_scratch.resize(
+                (len + silence_len + packet_len).div_ceil(mem::size_of::<ScratchBlock>()),
+                ScratchBlock([0; 16]),
+            );
+            let data = self.capture_scratch.as_mut_ptr() as *mut u8;
+            ptr::write_bytes(data.add(len), silence, silence_len);
+            silent &= packet.silent;
+            if packet.silent {
+                // Contents of silent packets are undefined.
+                ptr::write_bytes(data.add(len + silence_len), silence, packet_len);
+            } else if packet_len > 0 {
+                ptr::copy_nonoverlapping(
+                    packet.input as *const u8,
+                    data.add(len + silence_len),
+                    packet_len,
+                );
+            }
+            len += silence_len + packet_len;
+            num_frames += num_silent + packet.frames;
+            client.ReleaseBuffer(frames_to_u32(packet.frames)?);
+
+            let mut next_packet = 0;
+            client.GetNextPacketSize(&mut next_packet);
+            if next_packet == 0 {
+                break;
             }
         }
+
+        if let Some(duplex) = &mut self.duplex {
+            duplex.pending = num_frames;
+        }
+
+        Ok(api::StreamBuffers {
+            frames: num_frames,
+            input: self.capture_scratch.as_ptr() as _,
+            output: ptr::null_mut(),
+            gap_frames,
+            capture_time,
+            silent,
+        })
     }
 
     unsafe fn release_buffers(&mut self, num_frames: api::Frames) -> Result<()> {
         match self.device_stream {
             DeviceStream::Input { client } => {
-                client.ReleaseBuffer(num_frames as _);
+                client.ReleaseBuffer(frames_to_u32(num_frames)?);
             }
             DeviceStream::Output { client, .. } => {
-                client.ReleaseBuffer(num_frames as _, 0);
+                client.ReleaseBuffer(frames_to_u32(num_frames)?, 0);
+                if num_frames > 0 {
+                    let mut padding = 0;
+                    self.client.GetCurrentPadding(&mut padding);
+                    self.last_submit.set(Some((time::Instant::now(), padding)));
+                }
             }
         }
         Ok(())
@@ -691,23 +3156,489 @@ impl Device {
 
 impl api::Device for Device {
     unsafe fn start(&self) {
+        if let Some(duplex) = &self.duplex {
+            duplex.client.Start();
+        }
         self.client.Start();
+        self.running.set(true);
+        self.last_submit.set(None);
+        self.fade.set(None);
     }
 
     unsafe fn stop(&self) {
         self.client.Stop();
+        if let Some(duplex) = &self.duplex {
+            duplex.client.Stop();
+        }
+        self.running.set(false);
     }
 
     unsafe fn stream_properties(&self) -> api::StreamProperties {
         self.properties
     }
 
-    unsafe fn submit_buffers(&mut self, timeout_ms: u32) -> Result<()> {
-        let buffers = self.acquire_buffers(timeout_ms)?;
-        (self.callback)(api::Stream {
-            properties: self.properties,
-            buffers,
-        });
-        self.release_buffers(buffers.frames)
+    unsafe fn submit_buffers(&mut self, timeout: Option<time::Duration>) -> Result<()> {
+        if !self.running.get() {
+            return api::Error::validation(
+                "`submit_buffers` called before the device has been started",
+            );
+        }
+
+        let timeout_ms = timeout.map_or(self.acquire_timeout, api::timeout_ms);
+        match self.wait_buffers(timeout_ms) {
+            // Woken up for running queued APCs, no buffers available yet.
+            WAIT_IO_COMPLETION => return Ok(()),
+            WAIT_FAILED => {
+                // Invalid event handle, the device can't be polled anymore.
+                return Err(api::Error::DeviceLost);
+            }
+            winerror::WAIT_TIMEOUT => return Err(api::Error::Timeout),
+            _ => (),
+        }
+
+        self.process_buffers()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::com::mock::{MockAudioClient, MockCaptureClient, MockPacket, MockUnknown};
+    use super::*;
+
+    fn stereo(format: api::Format) -> api::FrameDesc {
+        api::FrameDesc {
+            format,
+            channels: api::ChannelMask::FRONT_LEFT | api::ChannelMask::FRONT_RIGHT,
+            sample_rate: 48_000,
+            endianness: api::Endianness::Little,
+        }
+    }
+
+    /// Started stereo `F32` device at 48kHz with a 10ms buffer on mocked clients.
+    unsafe fn mock_device(
+        client: WeakPtr<IAudioClient>,
+        device_stream: DeviceStream,
+        stream_flags: DWORD,
+    ) -> Device {
+        let frame_desc = stereo(api::Format::F32);
+        let config = StreamConfig {
+            sharing: AUDCLNT_SHAREMODE_EXCLUSIVE,
+            stream_flags,
+            mix_format: map_frame_desc(&frame_desc).unwrap(),
+            session_guid: None,
+            buffer_duration: 0,
+            conversions: api::ConversionFlags::empty(),
+        };
+        let properties = api::StreamProperties {
+            channels: frame_desc.channels,
+            requested_channels: frame_desc.channels,
+            device_channels: frame_desc.channels,
+            sample_rate: frame_desc.sample_rate,
+            buffer_size_frames: 480,
+            valid_bits: 32,
+            timer_driven: config.is_timer_driven(),
+            conversions: api::ConversionFlags::empty(),
+        };
+        Device {
+            instance: InstanceRaw::null(),
+            physical_devices: Arc::new(Mutex::new(PhysicalDeviceMap {
+                ids: BTreeMap::new(),
+                devices: HandleTable::default(),
+            })),
+            physical_device_id: String::new(),
+            config,
+            running: Cell::new(true),
+            last_submit: Cell::new(None),
+            fade: Cell::new(None),
+            stop_request: Arc::new(AtomicBool::new(false)),
+            event_callback: Arc::new(Mutex::new(None)),
+            capture_position: None,
+            capture_scratch: Vec::new(),
+            render_scratch: Vec::new(),
+            auto_recover: false,
+            discontinuities: 0,
+            last_callback_duration: time::Duration::from_secs(0),
+            acquire_timeout: 100,
+            alertable_wait: false,
+            client,
+            fence: Fence::create(false, false),
+            device_stream,
+            duplex: None,
+            session_notifier: None,
+            callback: Box::new(|_| ()),
+            downmix: None,
+            channel_map: None,
+            properties,
+        }
+    }
+
+    /// Packet of interleaved `F32` samples.
+    fn packet(samples: &[f32], flags: DWORD, qpc_position: u64) -> MockPacket {
+        MockPacket {
+            data: samples
+                .iter()
+                .flat_map(|sample| sample.to_le_bytes())
+                .collect(),
+            frames: (samples.len() / 2) as _,
+            flags,
+            qpc_position,
+        }
+    }
+
+    #[test]
+    fn frame_desc_round_trip() {
+        for &format in &[
+            api::Format::F32,
+            api::Format::I16,
+            api::Format::I24,
+            api::Format::I32,
+        ] {
+            let frame_desc = stereo(format);
+            let wave_format = map_frame_desc(&frame_desc).unwrap();
+            let mapped = unsafe { map_waveformat(&wave_format as *const _ as _) }.unwrap();
+            assert_eq!(mapped, frame_desc);
+        }
+    }
+
+    #[test]
+    fn surround_frame_desc_round_trip() {
+        let frame_desc = api::FrameDesc {
+            channels: api::ChannelMask::FRONT_LEFT
+                | api::ChannelMask::FRONT_RIGHT
+                | api::ChannelMask::FRONT_CENTER
+                | api::ChannelMask::LOW_FREQUENCY
+                | api::ChannelMask::BACK_LEFT
+                | api::ChannelMask::BACK_RIGHT,
+            ..stereo(api::Format::F32)
+        };
+        let wave_format = map_frame_desc(&frame_desc).unwrap();
+        let (num_channels, channel_mask) =
+            (wave_format.Format.nChannels, wave_format.dwChannelMask);
+        assert_eq!(num_channels, 6);
+        assert_eq!(channel_mask, ksmedia::KSAUDIO_SPEAKER_5POINT1);
+        let mapped = unsafe { map_waveformat(&wave_format as *const _ as _) }.unwrap();
+        assert_eq!(mapped, frame_desc);
+    }
+
+    fn wave_format_bytes(format: &WAVEFORMATEXTENSIBLE) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(
+                format as *const _ as *const u8,
+                mem::size_of::<WAVEFORMATEXTENSIBLE>(),
+            )
+        }
+    }
+
+    #[test]
+    fn wave_formats_are_byte_identical() {
+        let frame_descs = [
+            stereo(api::Format::F32),
+            stereo(api::Format::I16),
+            api::FrameDesc {
+                format: api::Format::I24,
+                channels: api::ChannelMask::FRONT_CENTER | api::ChannelMask::raw(2),
+                sample_rate: 44_100,
+                endianness: api::Endianness::Little,
+            },
+            api::FrameDesc {
+                format: api::Format::I32,
+                channels: api::ChannelMask::raw(4),
+                sample_rate: 96_000,
+                endianness: api::Endianness::Little,
+            },
+        ];
+        for frame_desc in &frame_descs {
+            let direct = map_frame_desc(frame_desc).unwrap();
+            // Device formats (mix format, closest match) are mapped back before rebuilding.
+            let mapped = unsafe { map_waveformat(&direct as *const _ as _) }.unwrap();
+            let rebuilt = map_frame_desc(&mapped).unwrap();
+            assert_eq!(wave_format_bytes(&direct), wave_format_bytes(&rebuilt));
+
+            let block_align = frame_desc.num_channels() * frame_desc.format.bytes_per_sample();
+            assert_eq!({ direct.Format.nBlockAlign } as usize, block_align);
+            assert_eq!(
+                { direct.Format.nAvgBytesPerSec } as usize,
+                block_align * frame_desc.sample_rate
+            );
+        }
+
+        // Plain PCM formats without channel mask.
+        let pcm = WAVEFORMATEX {
+            wFormatTag: WAVE_FORMAT_PCM,
+            nChannels: 2,
+            nSamplesPerSec: 48_000,
+            nAvgBytesPerSec: 48_000 * 4,
+            nBlockAlign: 4,
+            wBitsPerSample: 16,
+            cbSize: 0,
+        };
+        let mapped = unsafe { map_waveformat(&pcm) }.unwrap();
+        assert_eq!(
+            wave_format_bytes(&map_frame_desc(&mapped).unwrap()),
+            wave_format_bytes(&map_frame_desc(&stereo(api::Format::I16)).unwrap())
+        );
+    }
+
+    #[test]
+    fn unsigned_samples_are_rejected() {
+        assert!(map_frame_desc(&stereo(api::Format::U32)).is_none());
+    }
+
+    #[test]
+    fn timestamp_errors_drop_capture_time() {
+        let frame = [0.0; 2];
+        let mut client = MockUnknown::new();
+        let mut capture = MockCaptureClient::new(vec![
+            packet(&frame, 0, 1234),
+            packet(&frame, AUDCLNT_BUFFERFLAGS_TIMESTAMP_ERROR, 1235),
+            packet(
+                &frame,
+                AUDCLNT_BUFFERFLAGS_TIMESTAMP_ERROR | AUDCLNT_BUFFERFLAGS_SILENT,
+                1236,
+            ),
+            // Drained at once, the capture time of the first packet is reported.
+            packet(&frame, AUDCLNT_BUFFERFLAGS_TIMESTAMP_ERROR, 1237),
+            packet(&frame, 0, 1238),
+        ]);
+        unsafe {
+            let capture_client = capture.as_ptr();
+            let mut device = mock_device(
+                client.as_ptr(),
+                DeviceStream::Input {
+                    client: capture_client,
+                },
+                AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
+            );
+
+            let mut capture_times = Vec::new();
+            for _ in 0..3 {
+                let buffers = device.acquire_capture_packet(capture_client);
+                capture_times.push((buffers.capture_time, buffers.silent));
+                device.release_buffers(buffers.frames).unwrap();
+            }
+            assert_eq!(
+                capture_times,
+                [(Some(1234), false), (None, false), (None, true)]
+            );
+
+            let buffers = device.acquire_capture_packets().unwrap();
+            assert_eq!(buffers.frames, 2);
+            assert_eq!(buffers.capture_time, None);
+        }
+    }
+
+    #[test]
+    fn buffer_range_rounds_inwards() {
+        // Exact frame counts at 48kHz.
+        assert_eq!(buffer_range_frames(10_000, 20_000, 48_000), (48, 96));
+        // 3ms at 44.1kHz are 132.3 frames, 132 frames would be below the minimum duration.
+        assert_eq!(
+            buffer_range_frames(30_000, 20_000_000, 44_100),
+            (133, 88_200)
+        );
+        assert_eq!(buffer_range_frames(10_000, 10_000, 44_100), (45, 44));
+    }
+
+    #[test]
+    fn pcm32_maps_to_i32() {
+        let wave_format = WAVEFORMATEX {
+            wFormatTag: WAVE_FORMAT_PCM,
+            nChannels: 2,
+            nSamplesPerSec: 48_000,
+            nAvgBytesPerSec: 48_000 * 8,
+            nBlockAlign: 8,
+            wBitsPerSample: 32,
+            cbSize: 0,
+        };
+        let frame_desc = unsafe { map_waveformat(&wave_format) }.unwrap();
+        assert_eq!(frame_desc, stereo(api::Format::I32));
+    }
+
+    #[test]
+    fn valid_bits_map_to_container_format() {
+        // 24 bit samples in 32 bit containers.
+        let mut wave_format = map_frame_desc(&stereo(api::Format::I32)).unwrap();
+        wave_format.Samples = 24;
+        let wave_format = &wave_format as *const _ as *const WAVEFORMATEX;
+        let frame_desc = unsafe { map_waveformat(wave_format) }.unwrap();
+        assert_eq!(frame_desc, stereo(api::Format::I32));
+        assert_eq!(unsafe { valid_bits(wave_format) }, 24);
+    }
+
+    #[test]
+    fn streams_release_each_reference_once() {
+        let mut render = MockUnknown::new();
+        let mut capture = MockUnknown::new();
+        let mut client = MockUnknown::new();
+        unsafe {
+            DeviceStream::Output {
+                client: render.as_ptr(),
+                buffer_size: 0,
+            }
+            .release();
+            DeviceStream::Input {
+                client: capture.as_ptr(),
+            }
+            .release();
+        }
+        assert_eq!((render.refcount, render.releases), (0, 1));
+        assert_eq!((capture.refcount, capture.releases), (0, 1));
+
+        let mut capture = MockUnknown::new();
+        unsafe {
+            DuplexInput {
+                client: client.as_ptr(),
+                capture: capture.as_ptr(),
+                pending: 0,
+            }
+            .release();
+        }
+        assert_eq!((client.refcount, client.releases), (0, 1));
+        assert_eq!((capture.refcount, capture.releases), (0, 1));
+    }
+
+    #[test]
+    fn session_guids_are_passed_to_the_audio_client() {
+        // Clients of different processes passing the same `session_guid` join one session.
+        let id = 0x6f1d_2c3a_8b4e_4f5a_9c7d_1e2f_3a4b_5c6d;
+        let config = |session_guid: Option<u128>| StreamConfig {
+            sharing: AUDCLNT_SHAREMODE_SHARED,
+            stream_flags: AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
+            mix_format: map_frame_desc(&stereo(api::Format::F32)).unwrap(),
+            session_guid: session_guid.map(Guid::from),
+            buffer_duration: 0,
+            conversions: api::ConversionFlags::empty(),
+        };
+
+        let mut first = MockAudioClient::new();
+        let mut second = MockAudioClient::new();
+        let mut default = MockAudioClient::new();
+        unsafe {
+            Device::initialize_client(first.as_ptr(), &config(Some(id))).unwrap();
+            Device::initialize_client(second.as_ptr(), &config(Some(id))).unwrap();
+            Device::initialize_client(default.as_ptr(), &config(None)).unwrap();
+        }
+
+        let session = first.session_guid.map(Guid).unwrap();
+        assert_eq!(
+            session.to_string(),
+            "{6F1D2C3A-8B4E-4F5A-9C7D-1E2F3A4B5C6D}"
+        );
+        assert_eq!(second.session_guid.map(Guid), Some(session));
+        assert!(default.session_guid.is_none());
+    }
+
+    #[test]
+    fn devices_release_the_shared_physical_device_client_once() {
+        let mut client = MockUnknown::new();
+        let mut render = MockUnknown::new();
+        unsafe {
+            let physical_device =
+                PhysicalDevice::new(PhysicalDeviceRaw::null(), api::StreamFlags::OUTPUT);
+            physical_device.replace_audio_client(client.as_ptr());
+
+            // Shared streams alias the client of the physical device, see `create_device`.
+            let device = mock_device(
+                physical_device.audio_client().add_ref(),
+                DeviceStream::Output {
+                    client: render.as_ptr(),
+                    buffer_size: 480,
+                },
+                AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
+            );
+            assert_eq!(client.refcount, 2);
+
+            drop(device);
+            assert_eq!((client.refcount, client.releases), (1, 1));
+            assert_eq!((render.refcount, render.releases), (0, 1));
+
+            // Queries keep working on the client of the physical device.
+            assert_eq!(
+                **physical_device.audio_client(),
+                client.as_ptr::<IAudioClient>()
+            );
+            drop(physical_device);
+        }
+        assert_eq!((client.refcount, client.releases), (0, 2));
+    }
+
+    #[test]
+    fn timer_driven_capture_polls_for_packets() {
+        let mut client = MockUnknown::new();
+        let mut capture = MockCaptureClient::new(vec![
+            packet(&[0.25, -0.25, 0.5, -0.5], 0, 0),
+            packet(&[1.0, -1.0], 0, 0),
+        ]);
+        unsafe {
+            // The fence is never signaled, waiting on it would time out.
+            let mut device = mock_device(
+                client.as_ptr(),
+                DeviceStream::Input {
+                    client: capture.as_ptr(),
+                },
+                0,
+            );
+            assert!(device.config.is_timer_driven());
+            let samples = device.capture_to_vec(3).unwrap();
+            assert_eq!(samples, [0.25, -0.25, 0.5, -0.5, 1.0, -1.0]);
+
+            match device.capture_to_vec(1) {
+                Err(api::Error::Timeout) => (),
+                result => panic!("{:?}", result),
+            }
+        }
+        assert_eq!(capture.refcount, 0);
+        assert_eq!(client.refcount, 0);
+    }
+
+    #[test]
+    fn replaced_audio_clients_are_released_when_unused() {
+        let mut stale = MockUnknown::new();
+        let mut current = MockUnknown::new();
+        unsafe {
+            let physical_device =
+                PhysicalDevice::new(PhysicalDeviceRaw::null(), api::StreamFlags::OUTPUT);
+            physical_device.replace_audio_client(stale.as_ptr());
+            let stream = physical_device.audio_client().add_ref();
+            let query = physical_device.audio_client();
+
+            physical_device.replace_audio_client(current.as_ptr());
+            assert_eq!(stale.refcount, 2);
+            drop(query);
+            assert_eq!(stale.refcount, 1);
+            stream.destroy();
+            assert_eq!((stale.refcount, stale.releases), (0, 2));
+
+            assert_eq!(current.refcount, 1);
+            drop(physical_device);
+        }
+        assert_eq!((current.refcount, current.releases), (0, 1));
+    }
+
+    #[test]
+    fn event_guard_unregisters_its_callback() {
+        let callback: EventCallback = Arc::new(Mutex::new(None));
+        let generation = Arc::new(AtomicU64::new(0));
+        let register = |registered| {
+            *callback.lock().unwrap() = Some(Box::new(|_| ()));
+            generation.store(registered, Ordering::Release);
+            EventGuard {
+                callback: callback.clone(),
+                generation: generation.clone(),
+                registered,
+            }
+        };
+
+        let guard = register(1);
+        drop(guard);
+        assert!(callback.lock().unwrap().is_none());
+
+        // Guards of replaced callbacks keep the current callback.
+        let stale = register(2);
+        let current = register(3);
+        drop(stale);
+        assert!(callback.lock().unwrap().is_some());
+        drop(current);
+        assert!(callback.lock().unwrap().is_none());
     }
 }
@@ -0,0 +1,243 @@
+//! Direct monitoring of an input device on an output device.
+
+use super::{Device, Instance};
+use crate::api::{self, Device as _, Instance as _, Result};
+use std::{
+    iter,
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
+    time,
+};
+
+/// Captured samples waiting for playback, interleaved in the output channel layout.
+///
+/// Single producer single consumer ring: the input callback only writes and the output
+/// callback only reads, neither of the stream callbacks blocks on a lock.
+struct RingBuffer {
+    /// Samples stored as `f32` bits.
+    samples: Box<[AtomicU32]>,
+    /// Total number of samples written, only advanced by the producer.
+    write: AtomicUsize,
+    /// Total number of samples read, only advanced by the consumer.
+    read: AtomicUsize,
+}
+
+impl RingBuffer {
+    fn new(capacity: usize) -> Self {
+        RingBuffer {
+            samples: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+            write: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+        }
+    }
+
+    /// Append samples, samples exceeding the capacity are dropped.
+    fn push(&self, samples: impl Iterator<Item = f32>) {
+        let capacity = self.samples.len();
+        let write = self.write.load(Ordering::Relaxed);
+        let free = capacity - write.wrapping_sub(self.read.load(Ordering::Acquire));
+        let mut len = 0;
+        for sample in samples.take(free) {
+            self.samples[write.wrapping_add(len) % capacity]
+                .store(sample.to_bits(), Ordering::Relaxed);
+            len += 1;
+        }
+        self.write.store(write.wrapping_add(len), Ordering::Release);
+    }
+
+    /// Read samples into `dst`, returns the number of samples read.
+    ///
+    /// Samples exceeding `max_len` are dropped before reading for keeping the latency low.
+    fn pop(&self, dst: &mut [f32], max_len: usize) -> usize {
+        let capacity = self.samples.len();
+        let write = self.write.load(Ordering::Acquire);
+        let mut read = self.read.load(Ordering::Relaxed);
+        read = read.wrapping_add(write.wrapping_sub(read).saturating_sub(max_len));
+        let len = dst.len().min(write.wrapping_sub(read));
+        for (i, dst) in dst[..len].iter_mut().enumerate() {
+            *dst = f32::from_bits(
+                self.samples[read.wrapping_add(i) % capacity].load(Ordering::Relaxed),
+            );
+        }
+        self.read.store(read.wrapping_add(len), Ordering::Release);
+        len
+    }
+}
+
+/// Input device routed to an output device.
+///
+/// Captured frames are passed through an internal ring buffer to the output stream.
+/// The ring buffer holds at most two output buffers, older frames are dropped
+/// for keeping the latency low. Missing frames are filled with silence.
+pub struct Monitor {
+    input: Device,
+    output: Device,
+}
+
+impl Instance {
+    /// Create a monitor rendering the captured input of a physical device on an output device.
+    ///
+    /// Returns a `Monitor` owning both streams instead of a single `Device`, as input and
+    /// output are separate endpoints with a stream each.
+    ///
+    /// Both streams use the default concurrent format of the output device.
+    /// Differing input sample rates and channel layouts are converted by the audio engine
+    /// (see `DeviceDesc::src_quality`), the monitor itself doesn't resample.
+    /// Captured samples are scaled by `gain`.
+    ///
+    /// ## Validation
+    ///
+    /// - `input_device` **must** be a valid handle supporting input streams.
+    /// - `output_device` **must** be a valid handle supporting output streams.
+    pub unsafe fn create_monitor(
+        &self,
+        input_device: api::PhysicalDevice,
+        output_device: api::PhysicalDevice,
+        gain: f32,
+    ) -> Result<Monitor> {
+        let frame_desc = self.physical_device_default_concurrent_format(output_device)?;
+        let sample_desc = api::SampleDesc {
+            format: api::Format::F32,
+            sample_rate: frame_desc.sample_rate,
+        };
+        let num_channels = frame_desc.num_channels();
+        // One second of audio, the output callback limits the queued samples.
+        let ring = Arc::new(RingBuffer::new(frame_desc.sample_rate * num_channels));
+
+        let output = {
+            let ring = ring.clone();
+            self.create_device(
+                api::DeviceDesc {
+                    physical_device: output_device,
+                    sharing: api::SharingMode::Concurrent,
+                    sample_desc,
+                    no_persist: false,
+                    acquire_timeout: None,
+                    src_quality: api::SrcQuality::None,
+                    auto_recover: false,
+                    session_guid: None,
+                    alertable_wait: false,
+                    target_latency: None,
+                    channel_map: None,
+                    auto_downmix: false,
+                    input_device: None,
+                },
+                api::Channels {
+                    input: api::ChannelMask::empty(),
+                    output: frame_desc.channels,
+                },
+                Box::new(move |mut stream| {
+                    let max_len = 2 * stream.properties.buffer_size_frames * num_channels;
+                    let buffer = stream.output_buffer::<f32>();
+                    let len = ring.pop(buffer, max_len);
+                    for sample in &mut buffer[..len] {
+                        *sample *= gain;
+                    }
+                    for sample in &mut buffer[len..] {
+                        *sample = 0.0;
+                    }
+                }),
+            )?
+        };
+
+        let input = self.create_device(
+            api::DeviceDesc {
+                physical_device: input_device,
+                sharing: api::SharingMode::Concurrent,
+                sample_desc,
+                no_persist: false,
+                acquire_timeout: None,
+                src_quality: api::SrcQuality::Default,
+                auto_recover: true,
+                session_guid: None,
+                alertable_wait: false,
+                target_latency: None,
+                channel_map: None,
+                auto_downmix: false,
+                input_device: None,
+            },
+            api::Channels {
+                input: frame_desc.channels,
+                output: api::ChannelMask::empty(),
+            },
+            Box::new(move |stream| {
+                // Keep the timeline aligned after discontinuities.
+                let gap = stream.buffers.gap_frames as usize * num_channels;
+                ring.push(iter::repeat_n(0.0, gap));
+                ring.push(stream.input_buffer::<f32>().iter().copied());
+            }),
+        )?;
+
+        Ok(Monitor { input, output })
+    }
+}
+
+impl Monitor {
+    /// # Safety
+    ///
+    /// Same requirements as `api::Device::start` for both devices.
+    pub unsafe fn start(&self) {
+        self.input.start();
+        self.output.start();
+    }
+
+    /// # Safety
+    ///
+    /// Same requirements as `api::Device::stop` for both devices.
+    pub unsafe fn stop(&self) {
+        self.output.stop();
+        self.input.stop();
+    }
+
+    /// Stream properties of the output device.
+    ///
+    /// # Safety
+    ///
+    /// Same requirements as `api::Device::stream_properties`.
+    pub unsafe fn stream_properties(&self) -> api::StreamProperties {
+        self.output.stream_properties()
+    }
+
+    /// Submit stream buffers of the input and output device.
+    ///
+    /// Pending captured frames are pushed to the ring buffer before waiting for
+    /// the next output buffer. `timeout` applies to the output device.
+    ///
+    /// ## Validation
+    ///
+    /// - **Must** only be called after the monitor has been started, returns `Error::Validation` otherwise.
+    pub unsafe fn submit_buffers(&mut self, timeout: Option<time::Duration>) -> Result<()> {
+        match self
+            .input
+            .submit_buffers(Some(time::Duration::from_secs(0)))
+        {
+            Ok(()) | Err(api::Error::Timeout) => (),
+            Err(err) => return Err(err),
+        }
+        self.output.submit_buffers(timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RingBuffer;
+
+    #[test]
+    fn ring_buffer_wraps_and_drops() {
+        let ring = RingBuffer::new(4);
+        let mut dst = [0.0; 4];
+
+        // Samples exceeding the capacity are dropped.
+        ring.push((0..6).map(|i| i as f32));
+        assert_eq!(ring.pop(&mut dst[..2], 4), 2);
+        assert_eq!(dst[..2], [0.0, 1.0]);
+
+        // Writes wrap around, reads drop samples exceeding `max_len`.
+        ring.push([4.0, 5.0].iter().copied());
+        assert_eq!(ring.pop(&mut dst, 3), 3);
+        assert_eq!(dst[..3], [3.0, 4.0, 5.0]);
+        assert_eq!(ring.pop(&mut dst, 3), 0);
+    }
+}
@@ -0,0 +1,358 @@
+//! Object based spatial audio rendering (e.g Windows Sonic).
+//!
+//! Requires Windows 10 and a spatial sound format being enabled for the endpoint.
+
+use super::{
+    com::{WasapiError, WeakPtr},
+    fence::Fence,
+    ffi::*,
+    frames_from_u32, Instance,
+};
+use crate::api::{self, Result};
+use std::{mem, ptr, slice, time};
+use winapi::shared::{
+    mmreg::*,
+    winerror,
+    wtypes::VT_BLOB,
+    wtypesbase::{BLOB, CLSCTX_INPROC_SERVER},
+};
+use winapi::um::{
+    audiosessiontypes::AudioCategory_GameEffects, mmdeviceapi::IMMDevice, propidl::PROPVARIANT,
+    winbase::WAIT_FAILED,
+};
+use winapi::Interface;
+
+/// Dynamic audio object of a spatial device.
+pub struct SpatialObject {
+    raw: WeakPtr<ISpatialAudioObject>,
+    buffer: *mut f32,
+    frames: api::Frames,
+    /// Position in meters relative to the listener.
+    ///
+    /// Right-handed coordinates: `x` points to the right, `y` upwards and `z` backwards.
+    pub position: [f32; 3],
+    /// Volume in the range `[0.0, 1.0]`.
+    pub volume: f32,
+}
+
+impl SpatialObject {
+    /// Mono sample buffer of the current update, initialized with silence.
+    pub fn buffer(&mut self) -> &mut [f32] {
+        unsafe { slice::from_raw_parts_mut(self.buffer, self.frames) }
+    }
+}
+
+/// Spatial stream passed to the spatial callback for each update.
+pub struct SpatialStream<'a> {
+    /// Sample rate of the object buffers.
+    pub sample_rate: usize,
+    /// Number of frames per object buffer.
+    pub frames: api::Frames,
+    /// Dynamic audio objects, samples are `F32` mono.
+    pub objects: &'a mut [SpatialObject],
+}
+
+pub type SpatialCallback = Box<dyn FnMut(SpatialStream) + Send>;
+
+pub struct SpatialDevice {
+    client: WeakPtr<ISpatialAudioClient>,
+    stream: WeakPtr<ISpatialAudioObjectRenderStream>,
+    fence: Fence,
+    sample_rate: usize,
+    /// Timeout for `submit_buffers` without explicit timeout, twice the update period.
+    acquire_timeout: u32,
+    num_objects: usize,
+    objects: Vec<SpatialObject>,
+    callback: SpatialCallback,
+}
+
+impl std::ops::Drop for SpatialDevice {
+    fn drop(&mut self) {
+        unsafe {
+            for object in &self.objects {
+                object.raw.destroy();
+            }
+            self.stream.destroy();
+            self.client.destroy();
+            self.fence.destory();
+        }
+    }
+}
+
+impl Instance {
+    /// Check if spatial audio streams can be created for a physical device.
+    ///
+    /// Spatial audio requires Windows 10 and a spatial sound format (e.g Windows Sonic)
+    /// being enabled for the endpoint.
+    ///
+    /// # Safety
+    ///
+    /// Issues COM calls on the physical device, COM **must** be initialized on the calling
+    /// thread.
+    pub unsafe fn spatial_audio_available(&self, physical_device: api::PhysicalDevice) -> bool {
+        let physical_device = match self.physical_device(physical_device) {
+            Ok(physical_device) => physical_device,
+            Err(_) => return false,
+        };
+        if !physical_device.streams().contains(api::StreamFlags::OUTPUT) {
+            return false;
+        }
+
+        let client = match Self::activate_spatial_client(&physical_device.device) {
+            Ok(client) => client,
+            Err(_) => return false,
+        };
+        let hr = client
+            .IsSpatialAudioStreamAvailable(&ISpatialAudioObjectRenderStream::uuidof(), ptr::null());
+        client.destroy();
+        winerror::SUCCEEDED(hr)
+    }
+
+    /// Create a spatial device rendering `num_objects` dynamic audio objects.
+    ///
+    /// Objects are rendered with `F32` mono samples at the sample rate of the spatial device.
+    /// Returns `Error::Unsupported` if spatial audio isn't available, see
+    /// `spatial_audio_available`.
+    ///
+    /// ## Validation
+    ///
+    /// - `physical_device` **must** be a valid handle supporting output streams.
+    /// - `num_objects` **must** not exceed the maximum number of dynamic objects of the device.
+    pub unsafe fn create_spatial_device(
+        &self,
+        physical_device: api::PhysicalDevice,
+        num_objects: usize,
+        callback: SpatialCallback,
+    ) -> Result<SpatialDevice> {
+        let physical_device = self.physical_device(physical_device)?;
+        if !physical_device.streams().contains(api::StreamFlags::OUTPUT) {
+            return api::Error::validation("Spatial devices require an output device");
+        }
+
+        let client = Self::activate_spatial_client(&physical_device.device)?;
+        let result = Self::create_spatial_stream(client, num_objects);
+        let (stream, fence, sample_rate, max_frames) = match result {
+            Ok(stream) => stream,
+            Err(err) => {
+                client.destroy();
+                return Err(err);
+            }
+        };
+
+        Ok(SpatialDevice {
+            client,
+            stream,
+            fence,
+            sample_rate,
+            acquire_timeout: api::timeout_ms(time::Duration::from_secs_f64(
+                2.0 * max_frames as f64 / sample_rate as f64,
+            )),
+            num_objects,
+            objects: Vec::with_capacity(num_objects),
+            callback,
+        })
+    }
+
+    /// Activate the spatial audio client, unavailable before Windows 10.
+    unsafe fn activate_spatial_client(
+        device: &WeakPtr<IMMDevice>,
+    ) -> Result<WeakPtr<ISpatialAudioClient>> {
+        let mut client = WeakPtr::<ISpatialAudioClient>::null();
+        let hr = device.Activate(
+            &ISpatialAudioClient::uuidof(),
+            CLSCTX_INPROC_SERVER,
+            ptr::null_mut(),
+            client.mut_void() as *mut _,
+        );
+        if !winerror::SUCCEEDED(hr) {
+            return api::Error::unsupported("Spatial audio isn't supported by the physical device");
+        }
+        Ok(client)
+    }
+
+    /// Returns the stream, its update fence, sample rate and maximum frames per update.
+    unsafe fn create_spatial_stream(
+        client: WeakPtr<ISpatialAudioClient>,
+        num_objects: usize,
+    ) -> Result<(WeakPtr<ISpatialAudioObjectRenderStream>, Fence, usize, u32)> {
+        let hr = client
+            .IsSpatialAudioStreamAvailable(&ISpatialAudioObjectRenderStream::uuidof(), ptr::null());
+        if !winerror::SUCCEEDED(hr) {
+            return api::Error::unsupported("Spatial audio isn't enabled for the physical device");
+        }
+
+        let mut max_objects = 0;
+        WasapiError::from_hr(client.GetMaxDynamicObjectCount(&mut max_objects))?;
+        if num_objects > max_objects as usize {
+            return api::Error::validation(format!(
+                "Number of objects exceeds the maximum of {} dynamic objects",
+                max_objects
+            ));
+        }
+
+        // Spatial formats share the sample rate, use the first one for a float mono format.
+        let sample_rate = {
+            let mut enumerator = WeakPtr::<IAudioFormatEnumerator>::null();
+            WasapiError::from_hr(
+                client.GetSupportedAudioObjectFormatEnumerator(enumerator.mut_void() as *mut _),
+            )?;
+            let mut format = ptr::null_mut();
+            let hr = enumerator.GetFormat(0, &mut format);
+            let sample_rate = if winerror::SUCCEEDED(hr) {
+                Some((*format).nSamplesPerSec)
+            } else {
+                None
+            };
+            enumerator.destroy();
+            match sample_rate {
+                Some(sample_rate) => sample_rate,
+                None => return api::Error::validation("No spatial audio format available"),
+            }
+        };
+
+        let bytes_per_sample = api::Format::F32.bytes_per_sample() as u32;
+        let format = WAVEFORMATEX {
+            wFormatTag: WAVE_FORMAT_IEEE_FLOAT,
+            nChannels: 1,
+            nSamplesPerSec: sample_rate,
+            nAvgBytesPerSec: sample_rate * bytes_per_sample,
+            nBlockAlign: bytes_per_sample as _,
+            wBitsPerSample: api::Format::F32.bits_per_sample() as _,
+            cbSize: 0,
+        };
+        let hr = client.IsAudioObjectFormatSupported(&format);
+        if !winerror::SUCCEEDED(hr) {
+            return api::Error::validation("Float mono format isn't supported for spatial audio");
+        }
+
+        let mut max_frames = 0;
+        WasapiError::from_hr(client.GetMaxFrameCount(&format, &mut max_frames))?;
+
+        let fence = Fence::create(false, false);
+        let params = SpatialAudioObjectRenderStreamActivationParams {
+            ObjectFormat: &format,
+            StaticObjectTypeMask: AudioObjectType_None,
+            MinDynamicObjectCount: num_objects as _,
+            MaxDynamicObjectCount: num_objects as _,
+            Category: AudioCategory_GameEffects,
+            EventHandle: fence.0,
+            NotifyObject: ptr::null_mut(),
+        };
+        let mut activation: PROPVARIANT = mem::zeroed();
+        activation.vt = VT_BLOB as _;
+        *activation.data.blob_mut() = BLOB {
+            cbSize: mem::size_of::<SpatialAudioObjectRenderStreamActivationParams>() as _,
+            pBlobData: &params as *const _ as *mut _,
+        };
+
+        let mut stream = WeakPtr::<ISpatialAudioObjectRenderStream>::null();
+        let hr = client.ActivateSpatialAudioStream(
+            &activation,
+            &ISpatialAudioObjectRenderStream::uuidof(),
+            stream.mut_void(),
+        );
+        if let Err(err) = WasapiError::from_hr(hr) {
+            fence.destory();
+            return Err(err.into());
+        }
+
+        Ok((stream, fence, sample_rate as _, max_frames))
+    }
+}
+
+impl SpatialDevice {
+    /// Sample rate of the object buffers.
+    pub fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    /// # Safety
+    ///
+    /// Issues COM calls on the spatial stream, COM **must** be initialized on the calling
+    /// thread.
+    pub unsafe fn start(&self) -> Result<()> {
+        WasapiError::from_hr(self.stream.Start())?;
+        Ok(())
+    }
+
+    /// # Safety
+    ///
+    /// Issues COM calls on the spatial stream, COM **must** be initialized on the calling
+    /// thread.
+    pub unsafe fn stop(&self) -> Result<()> {
+        WasapiError::from_hr(self.stream.Stop())?;
+        Ok(())
+    }
+
+    /// Update the audio objects.
+    ///
+    /// Waits for the next update, calls the spatial callback for filling the
+    /// object buffers and submits the buffers and object properties.
+    /// Objects are activated on the first update.
+    ///
+    /// `timeout` may be `INFINITE_TIMEOUT` or `None` for waiting twice the update period.
+    /// Returns `Error::Timeout` if no update was requested.
+    ///
+    /// # Safety
+    ///
+    /// Issues COM calls on the spatial stream, COM **must** be initialized on the calling
+    /// thread.
+    pub unsafe fn submit_buffers(&mut self, timeout: Option<time::Duration>) -> Result<()> {
+        let timeout_ms = timeout.map_or(self.acquire_timeout, api::timeout_ms);
+        match self.fence.wait(timeout_ms) {
+            WAIT_FAILED => return Err(api::Error::DeviceLost),
+            winerror::WAIT_TIMEOUT => return Err(api::Error::Timeout),
+            _ => (),
+        }
+
+        let mut available_objects = 0;
+        let mut frames = 0;
+        WasapiError::from_hr(
+            self.stream
+                .BeginUpdatingAudioObjects(&mut available_objects, &mut frames),
+        )?;
+        let result = self.update_objects(frames_from_u32(frames));
+        WasapiError::from_hr(self.stream.EndUpdatingAudioObjects())?;
+        result
+    }
+
+    unsafe fn update_objects(&mut self, frames: api::Frames) -> Result<()> {
+        while self.objects.len() < self.num_objects {
+            let mut raw = WeakPtr::<ISpatialAudioObject>::null();
+            WasapiError::from_hr(
+                self.stream
+                    .ActivateSpatialAudioObject(AudioObjectType_Dynamic, raw.mut_void() as *mut _),
+            )?;
+            self.objects.push(SpatialObject {
+                raw,
+                buffer: ptr::null_mut(),
+                frames: 0,
+                position: [0.0; 3],
+                volume: 1.0,
+            });
+        }
+
+        for object in &mut self.objects {
+            let mut buffer = ptr::null_mut();
+            let mut len = 0;
+            WasapiError::from_hr(object.raw.GetBuffer(&mut buffer, &mut len))?;
+            ptr::write_bytes(buffer, 0, len as _);
+            object.buffer = buffer as _;
+            object.frames = frames;
+        }
+
+        (self.callback)(SpatialStream {
+            sample_rate: self.sample_rate,
+            frames,
+            objects: &mut self.objects,
+        });
+
+        for object in &self.objects {
+            let [x, y, z] = object.position;
+            object.raw.SetPosition(x, y, z);
+            object.raw.SetVolume(object.volume);
+        }
+
+        Ok(())
+    }
+}
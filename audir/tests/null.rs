@@ -0,0 +1,149 @@
+//! Device lifecycle against the null backend, driven by its virtual clock.
+
+#![cfg(feature = "null")]
+
+use audir::{null::Instance, Device as _, Instance as _};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+const SAMPLE_RATE: usize = 48_000;
+
+unsafe fn create_device(
+    instance: &Instance,
+    callback: audir::StreamCallback,
+) -> audir::null::Device {
+    let output_device = instance
+        .default_output_or_first()
+        .expect("No output device found");
+    instance
+        .create_device(
+            audir::DeviceDesc {
+                physical_device: output_device,
+                sharing: audir::SharingMode::Concurrent,
+                sample_desc: audir::SampleDesc {
+                    format: audir::Format::F32,
+                    sample_rate: SAMPLE_RATE,
+                },
+                no_persist: false,
+                acquire_timeout: None,
+                src_quality: audir::SrcQuality::None,
+                auto_recover: false,
+                session_guid: None,
+                alertable_wait: false,
+                target_latency: None,
+                channel_map: None,
+                auto_downmix: false,
+                input_device: None,
+            },
+            audir::Channels {
+                input: audir::ChannelMask::empty(),
+                output: audir::ChannelMask::FRONT_LEFT | audir::ChannelMask::FRONT_RIGHT,
+            },
+            callback,
+        )
+        .unwrap()
+}
+
+#[test]
+fn physical_devices() {
+    unsafe {
+        assert_eq!(Instance::properties().driver_id, audir::DriverId::Null);
+
+        let instance = Instance::create("audir - null");
+        let physical_devices = instance.enumerate_physical_devices();
+        assert_eq!(physical_devices.len(), 1);
+
+        let output_device = instance.default_output_or_first().unwrap();
+        let properties = instance.physical_device_properties(output_device).unwrap();
+        assert!(properties.streams.contains(audir::StreamFlags::OUTPUT));
+
+        let formats = instance.physical_device_supported_formats(
+            output_device,
+            audir::SharingMode::Concurrent,
+            SAMPLE_RATE,
+            audir::ChannelMask::FRONT_LEFT | audir::ChannelMask::FRONT_RIGHT,
+        );
+        assert!(formats.contains(&audir::Format::F32));
+    }
+}
+
+#[test]
+fn position_follows_virtual_clock() {
+    unsafe {
+        let instance = Instance::create("audir - null");
+        let frames = Arc::new(AtomicUsize::new(0));
+        let callback_frames = frames.clone();
+        let mut device = create_device(
+            &instance,
+            Box::new(move |mut stream| {
+                assert_eq!(stream.buffers.frames, SAMPLE_RATE / 100);
+                assert!(stream.buffers.input.is_null());
+                stream.output_buffer::<f32>().fill(0.0);
+                callback_frames.fetch_add(stream.buffers.frames, Ordering::Relaxed);
+            }),
+        );
+        assert_eq!(device.stream_properties().sample_rate, SAMPLE_RATE);
+
+        // Time doesn't advance on its own.
+        device.start();
+        assert_eq!(device.position(), 0);
+
+        for i in 1..=10 {
+            instance.advance(Duration::from_millis(10));
+            device.submit_buffers(None).unwrap();
+            assert_eq!(device.position(), i * SAMPLE_RATE / 100);
+            assert_eq!(frames.load(Ordering::Relaxed), device.position());
+        }
+
+        // Nothing to process without time passing.
+        device.submit_buffers(None).unwrap();
+        assert_eq!(frames.load(Ordering::Relaxed), 4_800);
+        assert_eq!(instance.now(), Duration::from_millis(100));
+        assert_eq!(device.running_time(), Duration::from_millis(100));
+        assert_eq!(device.position(), 4_800);
+    }
+}
+
+#[test]
+fn stopped_devices_dont_advance() {
+    unsafe {
+        let instance = Instance::create("audir - null");
+        let device = create_device(&instance, Box::new(|_| ()));
+
+        // Not started yet.
+        instance.advance(Duration::from_millis(50));
+        assert_eq!(device.position(), 0);
+
+        device.start();
+        instance.advance(Duration::from_millis(20));
+        device.stop();
+        instance.advance(Duration::from_millis(100));
+        assert_eq!(device.running_time(), Duration::from_millis(20));
+        assert_eq!(device.position(), 960);
+
+        // Restarting accumulates the running time.
+        device.start();
+        device.start();
+        instance.advance(Duration::from_millis(10));
+        assert_eq!(device.running_time(), Duration::from_millis(30));
+        assert_eq!(device.position(), 1_440);
+        assert_eq!(instance.now(), Duration::from_millis(180));
+    }
+}
+
+#[test]
+fn sessions_reject_thread_affinity() {
+    unsafe {
+        let instance = Instance::create("audir - null");
+        assert!(instance.create_session(SAMPLE_RATE).is_ok());
+        assert!(matches!(
+            instance.create_session_with_affinity(SAMPLE_RATE, Some(0x1)),
+            Err(audir::Error::Unsupported { .. })
+        ));
+    }
+}